@@ -0,0 +1,428 @@
+use lettre::Address;
+use log::info;
+use mail_parser::{HeaderName, MessageParser, MimeHeaders};
+
+use crate::{
+    config::BounceConfig,
+    database::Database,
+    error::MailerError,
+    imap::{ImapClient, NewMessages},
+    model::{
+        bounce::{BounceKind, NewBounce},
+        email_reply::InboundSyncState,
+        recipient::RecipientStatus,
+    },
+};
+
+/// A delivery status notification (RFC 3464), reduced to what maps it back to
+/// the sent email and its failed recipients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryStatusNotification {
+    /// The `Message-ID` of the notification itself, derived from its content
+    /// when it has none, so that syncing it again doesn't record it twice.
+    pub message_id: Option<String>,
+    /// The `Message-ID` of the bounced message, from the returned headers.
+    pub original_message_id: Option<String>,
+    /// The addresses the notification was delivered to, where a VERP return
+    /// path shows up.
+    pub delivered_to: Vec<String>,
+    pub failed_recipients: Vec<FailedRecipient>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedRecipient {
+    /// The address that bounced, lowercased.
+    pub email: String,
+    pub kind: BounceKind,
+    pub status: String,
+    pub diagnostic: Option<String>,
+}
+
+/// Returns the VERP return path of a message: `local+<token>@domain` of the
+/// configured return path, the token being the local part of the `Message-ID`.
+/// `None` if the `Message-ID` doesn't fit in an address.
+pub fn verp_address(return_path: &str, message_id: &str) -> Option<Address> {
+    let (local, domain) = return_path.rsplit_once('@')?;
+    let (token, _) = message_id
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .rsplit_once('@')?;
+    if token.is_empty()
+        || !token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return None;
+    }
+
+    format!("{local}+{token}@{domain}").parse().ok()
+}
+
+/// Returns the token of a VERP address built by `verp_address`.
+pub fn verp_token<'a>(return_path: &str, address: &'a str) -> Option<&'a str> {
+    let (local, domain) = return_path.rsplit_once('@')?;
+    let (address_local, address_domain) = address.rsplit_once('@')?;
+    if !address_domain.eq_ignore_ascii_case(domain) {
+        return None;
+    }
+
+    address_local
+        .strip_prefix(local)?
+        .strip_prefix('+')
+        .filter(|token| !token.is_empty())
+}
+
+/// Parses a delivery status notification. Returns `None` if the message isn't one.
+pub fn parse_dsn(raw: &[u8]) -> Option<DeliveryStatusNotification> {
+    let message = MessageParser::default().parse(raw)?;
+
+    let mut delivery_status = None;
+    let mut original_message_id = None;
+    for part in message.parts.iter() {
+        let Some(content_type) = part.content_type() else {
+            continue;
+        };
+        let subtype = content_type.subtype().unwrap_or_default();
+        match (
+            content_type.ctype().to_ascii_lowercase().as_str(),
+            subtype.to_ascii_lowercase().as_str(),
+        ) {
+            ("message", "delivery-status") => {
+                delivery_status = Some(String::from_utf8_lossy(part.contents()).to_string());
+            }
+            ("message", "rfc822") | ("message", "global") | ("text", "rfc822-headers") => {
+                // A resent message is identified by its newest Resent-Message-ID
+                original_message_id =
+                    MessageParser::default()
+                        .parse(part.contents())
+                        .and_then(|original| {
+                            original
+                                .headers()
+                                .iter()
+                                .find(|header| header.name == HeaderName::ResentMessageId)
+                                .and_then(|header| header.value.as_text())
+                                .or_else(|| original.message_id())
+                                .map(|id| format!("<{id}>"))
+                        });
+            }
+            _ => {}
+        }
+    }
+    let delivery_status = delivery_status?;
+
+    // The first group describes the whole message, the next ones a recipient each
+    let failed_recipients = delivery_status_groups(&delivery_status)
+        .into_iter()
+        .skip(1)
+        .filter_map(|fields| {
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field, _)| field.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.as_str())
+            };
+            if !field("Action")?.eq_ignore_ascii_case("failed") {
+                return None;
+            }
+
+            // `rfc822; bob@domain.com`
+            let email = field("Final-Recipient")
+                .or_else(|| field("Original-Recipient"))?
+                .rsplit(';')
+                .next()?
+                .trim()
+                .to_lowercase();
+            let status = field("Status")?
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            Some(FailedRecipient {
+                email,
+                kind: if status.starts_with('5') {
+                    BounceKind::Hard
+                } else {
+                    BounceKind::Soft
+                },
+                status,
+                diagnostic: field("Diagnostic-Code").map(str::to_string),
+            })
+        })
+        .collect();
+
+    Some(DeliveryStatusNotification {
+        message_id: Some(match message.message_id() {
+            Some(id) => format!("<{id}>"),
+            None => {
+                let digest = openssl::sha::sha256(raw);
+                let digest: String = digest.iter().map(|b| format!("{b:02x}")).collect();
+                format!("<{digest}@dsn.invalid>")
+            }
+        }),
+        original_message_id,
+        delivered_to: delivered_to(raw),
+        failed_recipients,
+    })
+}
+
+/// Splits the `message/delivery-status` content into its groups of fields,
+/// unfolding the continuation lines.
+fn delivery_status_groups(content: &str) -> Vec<Vec<(String, String)>> {
+    let mut groups = vec![];
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in content.lines() {
+        if line.trim().is_empty() {
+            if !fields.is_empty() {
+                groups.push(std::mem::take(&mut fields));
+            }
+        } else if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    if !fields.is_empty() {
+        groups.push(fields);
+    }
+
+    groups
+}
+
+/// Returns the addresses of the `To`, `Delivered-To` and `X-Original-To` headers.
+fn delivered_to(raw: &[u8]) -> Vec<String> {
+    let raw = String::from_utf8_lossy(raw);
+    raw.lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| {
+            ["To", "Delivered-To", "X-Original-To"]
+                .iter()
+                .any(|header| name.trim().eq_ignore_ascii_case(header))
+        })
+        .flat_map(|(_, value)| value.split(','))
+        .map(|address| {
+            let address = address.trim();
+            match address.split_once('<') {
+                Some((_, address)) => address.trim_end_matches('>').trim(),
+                None => address,
+            }
+            .to_lowercase()
+        })
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+
+/// Records the failed recipients of a notification, and deactivates the
+/// recipients after a hard bounce or too many soft ones. Returns the number
+/// of bounces recorded.
+pub fn process_dsn(
+    db: &mut Database,
+    config: &BounceConfig,
+    dsn: &DeliveryStatusNotification,
+) -> Result<usize, MailerError> {
+    let mut original = match &dsn.original_message_id {
+        Some(message_id) => db.find_email_record_by_message_id(message_id)?,
+        None => None,
+    };
+    if original.is_none()
+        && let Some(return_path) = &config.return_path
+        && let Some(token) = dsn
+            .delivered_to
+            .iter()
+            .find_map(|address| verp_token(return_path, address))
+    {
+        original = db.find_email_record_by_message_id_local_part(token)?;
+    }
+
+    let mut recorded = 0;
+    for failed in &dsn.failed_recipients {
+        let recipient = db.find_recipient_by_email(failed.email.clone()).ok();
+        if !db.add_bounce(NewBounce {
+            email_history_id: original.as_ref().map(|original| original.id),
+            recipient_id: recipient.as_ref().map(|recipient| recipient.id),
+            email: failed.email.clone(),
+            kind: failed.kind,
+            status: failed.status.clone(),
+            diagnostic: failed.diagnostic.clone(),
+            notification_id: dsn.message_id.clone(),
+        })? {
+            continue;
+        }
+        recorded += 1;
+
+        let Some(recipient) = recipient else {
+            continue;
+        };
+        let deactivate = failed.kind == BounceKind::Hard
+            || db.count_bounces(recipient.id, BounceKind::Soft)?
+                >= i64::from(config.soft_bounce_limit);
+        if deactivate {
+            db.set_recipient_status(recipient.id, RecipientStatus::Bounced)?;
+            info!("Deactivated recipient {} after bounces", recipient.email);
+        }
+    }
+
+    Ok(recorded)
+}
+
+/// Reads the notifications received in the bounce folder since the last
+/// sync and processes them. Returns the number of bounces recorded. Blocks on
+/// the IMAP connection.
+pub fn sync_bounces(
+    db: &mut Database,
+    client: &ImapClient,
+    config: &BounceConfig,
+) -> Result<usize, MailerError> {
+    let state = db.find_inbound_sync_state(&client.email, &config.folder)?;
+    let NewMessages {
+        uid_validity,
+        messages,
+    } = client.fetch_new_messages(
+        &config.folder,
+        state.map(|state| state.uid_validity),
+        state.map_or(0, |state| state.last_uid),
+    )?;
+
+    let Some(last_uid) = messages.iter().map(|(uid, _)| *uid).max() else {
+        return Ok(0);
+    };
+
+    let mut recorded = 0;
+    for (_, raw) in messages {
+        if let Some(dsn) = parse_dsn(&raw) {
+            recorded += process_dsn(db, config, &dsn)?;
+        }
+    }
+
+    db.set_inbound_sync_state(
+        &client.email,
+        &config.folder,
+        InboundSyncState {
+            uid_validity,
+            last_uid,
+        },
+    )?;
+    if recorded > 0 {
+        info!("Recorded {recorded} bounces returned to {}", client.email);
+    }
+
+    Ok(recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DSN: &[u8] = b"From: Mail Delivery System <MAILER-DAEMON@mx.test.com>\r\n\
+        To: bounces+abc-123@test.com\r\n\
+        Subject: Undelivered Mail Returned to Sender\r\n\
+        Message-ID: <dsn-1@mx.test.com>\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/report; report-type=delivery-status; boundary=\"b\"\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        Your message could not be delivered.\r\n\
+        --b\r\n\
+        Content-Type: message/delivery-status\r\n\
+        \r\n\
+        Reporting-MTA: dns; mx.test.com\r\n\
+        \r\n\
+        Final-Recipient: rfc822; Bob@Domain.com\r\n\
+        Action: failed\r\n\
+        Status: 5.1.1\r\n\
+        Diagnostic-Code: smtp; 550 5.1.1 User unknown\r\n\
+        \r\n\
+        Final-Recipient: rfc822; carol@domain.com\r\n\
+        Action: delayed\r\n\
+        Status: 4.2.2\r\n\
+        \r\n\
+        Final-Recipient: rfc822; dave@domain.com\r\n\
+        Action: failed\r\n\
+        Status: 4.2.2 (mailbox full)\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/rfc822-headers\r\n\
+        \r\n\
+        From: test@test.com\r\n\
+        Subject: Hello\r\n\
+        Message-ID: <abc-123@test.com>\r\n\
+        \r\n\
+        --b--\r\n";
+
+    #[test]
+    fn test_verp() {
+        let address = verp_address("bounces@test.com", "<abc-123@host.test.com>").unwrap();
+        assert_eq!(address.to_string(), "bounces+abc-123@test.com");
+        assert_eq!(
+            verp_token("bounces@test.com", "bounces+abc-123@Test.com"),
+            Some("abc-123")
+        );
+        assert_eq!(verp_token("bounces@test.com", "bounces@test.com"), None);
+        assert_eq!(
+            verp_token("bounces@test.com", "bounces+abc@other.com"),
+            None
+        );
+        assert!(verp_address("bounces@test.com", "<a_b%c@host>").is_none());
+    }
+
+    #[test]
+    fn test_parse_dsn() {
+        let dsn = parse_dsn(DSN).unwrap();
+        assert_eq!(dsn.message_id.as_deref(), Some("<dsn-1@mx.test.com>"));
+        assert_eq!(
+            dsn.original_message_id.as_deref(),
+            Some("<abc-123@test.com>")
+        );
+        assert_eq!(dsn.delivered_to, vec!["bounces+abc-123@test.com"]);
+        assert_eq!(
+            dsn.failed_recipients,
+            vec![
+                FailedRecipient {
+                    email: "bob@domain.com".to_string(),
+                    kind: BounceKind::Hard,
+                    status: "5.1.1".to_string(),
+                    diagnostic: Some("smtp; 550 5.1.1 User unknown".to_string()),
+                },
+                FailedRecipient {
+                    email: "dave@domain.com".to_string(),
+                    kind: BounceKind::Soft,
+                    status: "4.2.2".to_string(),
+                    diagnostic: None,
+                },
+            ]
+        );
+
+        // A resend bounces with its own Message-ID
+        let resent = String::from_utf8_lossy(DSN).replace(
+            "\r\nFrom: test@test.com\r\n",
+            "\r\nResent-Message-ID: <resent-2@test.com>\r\n\
+            Resent-Message-ID: <resent-1@test.com>\r\n\
+            From: test@test.com\r\n",
+        );
+        assert_eq!(
+            parse_dsn(resent.as_bytes())
+                .unwrap()
+                .original_message_id
+                .as_deref(),
+            Some("<resent-2@test.com>")
+        );
+
+        // A notification without a Message-ID is keyed by its content
+        let anonymous =
+            String::from_utf8_lossy(DSN).replace("Message-ID: <dsn-1@mx.test.com>\r\n", "");
+        let message_id = parse_dsn(anonymous.as_bytes()).unwrap().message_id.unwrap();
+        assert!(message_id.ends_with("@dsn.invalid>"));
+        assert_eq!(
+            parse_dsn(anonymous.as_bytes()).unwrap().message_id,
+            Some(message_id)
+        );
+
+        assert!(parse_dsn(b"From: alice@domain.com\r\nSubject: Hi\r\n\r\nHello\r\n").is_none());
+    }
+}
@@ -8,6 +8,12 @@ pub struct Config {
     pub db_config: DatabaseConfig,
     pub mailer_config: MailerConfig,
     pub logger_config: LoggerConfig,
+    /// Accepts the messages pushed to a webhook when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbound_config: Option<InboundConfig>,
+    /// Adds one-click unsubscribe links to group emails when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unsubscribe_config: Option<UnsubscribeConfig>,
 }
 
 impl Config {
@@ -31,6 +37,10 @@ impl Config {
             panic!("mailer_config.smtp_host must be set in the config.toml");
         }
 
+        if config.mailer_config.smtp_pool.max_size == 0 {
+            panic!("mailer_config.smtp_pool.max_size must be at least 1 in the config.toml");
+        }
+
         if config.mailer_config.senders.is_empty() {
             panic!(
                 "mailer_config.senders must have at least one sender configured in the config.toml"
@@ -47,6 +57,108 @@ impl Config {
             );
         }
 
+        if let Some(sandbox) = &config.mailer_config.sandbox {
+            if sandbox.redirect_to.is_none() && sandbox.dir.is_none() {
+                panic!("mailer_config.sandbox must set redirect_to or dir in the config.toml");
+            }
+
+            if let Some(redirect_to) = &sandbox.redirect_to
+                && redirect_to.parse::<lettre::Address>().is_err()
+            {
+                panic!(
+                    "mailer_config.sandbox.redirect_to must be a valid email address in the config.toml"
+                );
+            }
+        }
+
+        if let Some(bounces) = &config.mailer_config.bounces {
+            let Some(sender) = config.mailer_config.find_sender(&bounces.mailbox) else {
+                panic!(
+                    "mailer_config.bounces.mailbox must be a configured sender in the config.toml"
+                );
+            };
+            let Some(imap) = &sender.imap else {
+                panic!(
+                    "mailer_config.bounces.mailbox must have an IMAP account in the config.toml"
+                );
+            };
+            if imap.sync_interval_minutes.is_some()
+                && bounces
+                    .folder
+                    .eq_ignore_ascii_case(crate::imap::DEFAULT_FOLDER)
+            {
+                panic!(
+                    "mailer_config.bounces.folder can't be the inbox synced for replies in the config.toml"
+                );
+            }
+
+            if let Some(return_path) = &bounces.return_path
+                && return_path.parse::<lettre::Address>().is_err()
+            {
+                panic!(
+                    "mailer_config.bounces.return_path must be a valid email address in the config.toml"
+                );
+            }
+        }
+
+        let rate_limits = config.mailer_config.rate_limit.iter().chain(
+            config
+                .mailer_config
+                .senders
+                .iter()
+                .filter_map(|sender| sender.rate_limit.as_ref()),
+        );
+        for rate_limit in rate_limits {
+            if rate_limit.messages_per_minute == 0 || rate_limit.burst() == 0 {
+                panic!(
+                    "rate_limit.messages_per_minute and rate_limit.burst must be at least 1 in the config.toml"
+                );
+            }
+        }
+
+        if let Some(policy) = &config.mailer_config.policy {
+            if policy.max_recipients == Some(0) {
+                panic!("mailer_config.policy.max_recipients must be at least 1 in the config.toml");
+            }
+
+            if policy
+                .allowed_domains
+                .iter()
+                .chain(&policy.denied_domains)
+                .any(|domain| domain.trim_start_matches('@').is_empty())
+            {
+                panic!("mailer_config.policy domains can't be empty in the config.toml");
+            }
+        }
+
+        if let Some(inbound) = &config.inbound_config {
+            if inbound.token.trim().is_empty() {
+                panic!("inbound_config.token must be set in the config.toml");
+            }
+
+            if !inbound.path.starts_with('/')
+                || ["/mcp", "/unsubscribe"].contains(&inbound.path.as_str())
+            {
+                panic!(
+                    "inbound_config.path must start with / and differ from /mcp and /unsubscribe"
+                );
+            }
+        }
+
+        if let Some(unsubscribe) = &config.unsubscribe_config {
+            if unsubscribe.secret.len() < 16 {
+                panic!(
+                    "unsubscribe_config.secret must be at least 16 characters in the config.toml"
+                );
+            }
+
+            if !unsubscribe.base_url.starts_with("https://")
+                && !unsubscribe.base_url.starts_with("http://")
+            {
+                panic!("unsubscribe_config.base_url must be an http(s) URL in the config.toml");
+            }
+        }
+
         config
     }
 }
@@ -58,14 +170,47 @@ impl Default for Config {
             db_config: Default::default(),
             mailer_config: Default::default(),
             logger_config: Default::default(),
+            inbound_config: None,
+            unsubscribe_config: None,
         }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MailerConfig {
+    /// Default SMTP port, used by senders that don't set their own.
     pub smtp_port: u16,
+    /// Default SMTP host, used by senders that don't set their own.
     pub smtp_host: String,
+    /// Default TLS policy, used by senders that don't set their own.
+    /// If neither is set, implicit TLS is used for senders with credentials
+    /// and no TLS for the others.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_tls: Option<TlsMode>,
+    /// Connection pool settings of the SMTP transports.
+    #[serde(default)]
+    pub smtp_pool: SmtpPoolConfig,
+    /// Backend used to deliver the messages. Defaults to SMTP.
+    #[serde(default)]
+    pub backend: DeliveryBackend,
+    /// Redirects all outgoing mail away from the real recipients when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
+    /// DKIM signing keys, one per sender domain.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dkim: Vec<DkimDomainConfig>,
+    /// Processing of the bounces returned to a bounce mailbox. Bounces are
+    /// ignored if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounces: Option<BounceConfig>,
+    /// Restricts who the messages may be sent to. Anyone can be emailed if
+    /// not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<SendPolicy>,
+    /// Global rate limit of the outgoing messages, e.g. the one of the SMTP
+    /// provider. Applies on top of the limits of the senders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
     pub senders: Vec<MailSender>,
 }
 
@@ -94,6 +239,28 @@ impl MailerConfig {
             }
         })
     }
+
+    /// Returns the SMTP host of the sender, falling back to the global `smtp_host`.
+    pub fn smtp_host_for<'a>(&'a self, sender: &'a MailSender) -> &'a str {
+        sender.smtp_host.as_deref().unwrap_or(&self.smtp_host)
+    }
+
+    /// Returns the SMTP port of the sender, falling back to the global `smtp_port`.
+    pub fn smtp_port_for(&self, sender: &MailSender) -> u16 {
+        sender.smtp_port.unwrap_or(self.smtp_port)
+    }
+
+    /// Returns the TLS policy of the sender, falling back to the global `smtp_tls`.
+    pub fn tls_mode_for(&self, sender: &MailSender) -> TlsMode {
+        sender
+            .tls
+            .or(self.smtp_tls)
+            .unwrap_or(if sender.credentials.is_some() {
+                TlsMode::Implicit
+            } else {
+                TlsMode::None
+            })
+    }
 }
 
 impl Default for MailerConfig {
@@ -101,21 +268,281 @@ impl Default for MailerConfig {
         Self {
             smtp_port: 2525,
             smtp_host: "localhost".to_string(),
+            smtp_tls: None,
+            smtp_pool: Default::default(),
+            backend: Default::default(),
+            sandbox: None,
+            dkim: vec![],
+            bounces: None,
+            policy: None,
+            rate_limit: None,
             senders: vec![MailSender {
                 email: "test@test.com".to_string(),
-                credentials: None,
+                ..Default::default()
             }],
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Where the built messages are delivered to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeliveryBackend {
+    /// Send through the SMTP server of each sender.
+    #[default]
+    Smtp,
+    /// Write each message as an `.eml` file into `dir`.
+    File { dir: String },
+    /// Pipe each message into a `sendmail` binary. Uses `sendmail` from PATH if
+    /// no command is given.
+    Sendmail { command: Option<String> },
+    /// Keep the messages in memory. Nothing leaves the process.
+    Stub,
+}
+
+/// Sandbox mode, for running against real data without emailing real people.
+/// At least one of `redirect_to` and `dir` must be set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Catch-all address receiving every message instead of the real recipients.
+    pub redirect_to: Option<String>,
+    /// Directory the messages are written to, replacing the configured backend.
+    pub dir: Option<String>,
+}
+
+/// DKIM signing configuration of a sender domain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimDomainConfig {
+    /// The domain of the senders whose messages are signed.
+    pub domain: String,
+    /// The selector the public key is published under, i.e. `{selector}._domainkey.{domain}`.
+    pub selector: String,
+    /// Path to the private key. A PKCS#1 PEM file for RSA, or the base64 encoded
+    /// 32 byte secret key for Ed25519.
+    pub private_key_path: String,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+    /// Names of the headers covered by the signature.
+    #[serde(default = "default_dkim_signed_headers")]
+    pub signed_headers: Vec<String>,
+    /// Canonicalization in the `header/body` form, e.g. `relaxed/relaxed`.
+    #[serde(default = "default_dkim_canonicalization")]
+    pub canonicalization: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DkimAlgorithm {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+fn default_dkim_signed_headers() -> Vec<String> {
+    [
+        "From",
+        "Subject",
+        "To",
+        "Date",
+        "Message-ID",
+        "List-Unsubscribe",
+        "List-Unsubscribe-Post",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+fn default_dkim_canonicalization() -> String {
+    "relaxed/relaxed".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmtpPoolConfig {
+    /// Maximum number of pooled connections per sender.
+    pub max_size: u32,
+    /// Seconds an idle connection is kept open before it is closed.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for SmtpPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            idle_timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MailSender {
     pub email: String,
     pub credentials: Option<SMTPCredentials>,
+    /// SMTP host for this sender. Defaults to `MailerConfig::smtp_host`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_host: Option<String>,
+    /// SMTP port for this sender. Defaults to `MailerConfig::smtp_port`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_port: Option<u16>,
+    /// TLS policy for this sender. Defaults to `MailerConfig::smtp_tls`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsMode>,
+    /// Path to a PEM encoded CA certificate to trust in addition to the system roots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_certificate_path: Option<String>,
+    /// Client certificate presented to the SMTP server during the TLS handshake.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<ClientCertificate>,
+    /// OpenPGP key used to sign outgoing PGP/MIME messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pgp_signing_key: Option<PgpSigningKey>,
+    /// Certificate and private key used to sign outgoing S/MIME messages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smime: Option<SmimeConfig>,
+    /// IMAP account of this sender, to read the mail it receives.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imap: Option<ImapAccount>,
+    /// Rate limit of the messages of this sender.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A token bucket: it holds up to `burst` messages and is refilled with
+/// `messages_per_minute`. Sends wait for a token instead of failing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub messages_per_minute: u32,
+    /// Messages that can be sent at once. Defaults to `messages_per_minute`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    pub fn burst(&self) -> u32 {
+        self.burst.unwrap_or(self.messages_per_minute)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImapAccount {
+    pub host: String,
+    #[serde(default = "default_imap_port")]
+    pub port: u16,
+    /// Whether to connect with implicit TLS. Only disable it for local servers.
+    #[serde(default = "default_imap_tls")]
+    pub tls: bool,
+    /// IMAP login. Defaults to the SMTP credentials of the sender.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<SMTPCredentials>,
+    /// Minutes between two syncs of the replies received in the inbox.
+    /// Replies aren't synced if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_interval_minutes: Option<u64>,
+}
+
+fn default_imap_port() -> u16 {
+    993
+}
+
+fn default_imap_tls() -> bool {
+    true
+}
+
+/// Reads the delivery status notifications (RFC 3464) returned to a folder of
+/// the IMAP account of a sender.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BounceConfig {
+    /// The email address of the sender whose IMAP account receives the bounces.
+    pub mailbox: String,
+    #[serde(default = "default_bounce_folder")]
+    pub folder: String,
+    /// VERP return path. If set, the envelope sender of each message becomes
+    /// `local+<Message-ID>@domain` of this address, so that the bounces that
+    /// don't return the original headers still map back to the sent email.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_path: Option<String>,
+    /// Soft bounces after which a recipient is deactivated. A single hard
+    /// bounce deactivates it.
+    #[serde(default = "default_soft_bounce_limit")]
+    pub soft_bounce_limit: u32,
+    /// Minutes between two reads of the bounce folder.
+    #[serde(default = "default_bounce_sync_interval_minutes")]
+    pub sync_interval_minutes: u64,
+}
+
+fn default_bounce_folder() -> String {
+    "INBOX".to_string()
+}
+
+fn default_soft_bounce_limit() -> u32 {
+    3
+}
+
+fn default_bounce_sync_interval_minutes() -> u64 {
+    10
+}
+
+/// The recipients a message may be sent to. A domain also covers its
+/// subdomains, and a denied domain wins over an allowed one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendPolicy {
+    /// Only these recipient domains may be emailed. Any domain if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_domains: Vec<String>,
+    /// These recipient domains must never be emailed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_domains: Vec<String>,
+    /// Maximum number of recipients of a single message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_recipients: Option<usize>,
+}
+
+/// TLS policy used when connecting to the SMTP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsMode {
+    /// TLS from the first byte of the connection (SMTPS, usually port 465).
+    Implicit,
+    /// Plain connection upgraded with STARTTLS. Fails if the server doesn't support it.
+    StarttlsRequired,
+    /// Plain connection upgraded with STARTTLS if the server supports it.
+    StarttlsOpportunistic,
+    /// No encryption at all.
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCertificate {
+    /// Path to the PEM encoded client certificate.
+    pub certificate_path: String,
+    /// Path to the PEM encoded private key of the client certificate.
+    pub private_key_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PgpSigningKey {
+    /// Path to the ASCII armored OpenPGP secret key.
+    pub private_key_path: String,
+    /// Passphrase protecting the secret key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+    /// Also sign messages that can't be encrypted, as `multipart/signed`.
+    #[serde(default)]
+    pub sign_unencrypted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SmimeConfig {
+    /// Path to the PEM encoded signing certificate.
+    pub certificate_path: String,
+    /// Path to the PEM encoded private key of the certificate.
+    pub private_key_path: String,
+    /// Also sign messages that can't be encrypted, as opaque signed-data.
+    #[serde(default)]
+    pub sign_unencrypted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SMTPCredentials {
     pub username: String,
     pub password: String,
@@ -124,16 +551,101 @@ pub struct SMTPCredentials {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DatabaseConfig {
     pub db_path: String,
+    /// Days to keep the raw messages of sent emails, purged along with the
+    /// retention rules. Kept forever if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_email_retention_days: Option<u32>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
 }
 
 impl Default for DatabaseConfig {
     fn default() -> Self {
         Self {
             db_path: "mailer.db".to_string(),
+            raw_email_retention_days: None,
+            retention: Default::default(),
+        }
+    }
+}
+
+/// Rules purging old email history, applied periodically and by the `purge_history` tool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionConfig {
+    /// Hours between two runs of the rules and of the raw message purge.
+    #[serde(default = "default_retention_interval_hours")]
+    pub interval_hours: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RetentionRule>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            interval_hours: default_retention_interval_hours(),
+            rules: Vec::new(),
         }
     }
 }
 
+fn default_retention_interval_hours() -> u64 {
+    24
+}
+
+/// Purges the emails older than `after_days`. Restricted to the emails of a
+/// sender and/or to the emails sent to the members of a group if set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionRule {
+    pub action: RetentionAction,
+    pub after_days: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionAction {
+    /// Deletes the body and the raw message, and keeps the metadata.
+    DeleteBody,
+    /// Deletes the whole record.
+    DeleteRecord,
+}
+
+/// The webhook receiving raw MIME messages, for the deployments that can't
+/// poll IMAP. The messages are posted as the request body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundConfig {
+    /// The path of the webhook on the server.
+    #[serde(default = "default_inbound_path")]
+    pub path: String,
+    /// The bearer token the requests must send in the `Authorization` header.
+    pub token: String,
+    /// Larger messages are rejected.
+    #[serde(default = "default_inbound_max_message_bytes")]
+    pub max_message_bytes: usize,
+}
+
+fn default_inbound_path() -> String {
+    "/inbound".to_string()
+}
+
+fn default_inbound_max_message_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+/// The one-click unsubscribe links (RFC 8058) added to group emails. The
+/// links point to the `/unsubscribe` endpoint of this server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsubscribeConfig {
+    /// The public URL of this server, e.g. `https://mailer.domain.com`.
+    pub base_url: String,
+    /// The key the links are signed with. Changing it invalidates the links
+    /// of the emails already sent.
+    pub secret: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggerConfig {
     pub config_file_path: String,
@@ -172,11 +684,19 @@ fn test_toml_config() {
 
     // check [db_config]
     assert_eq!(config.db_config.db_path, "mailer.db");
+    assert!(config.db_config.raw_email_retention_days.is_none());
+    assert_eq!(config.db_config.retention, RetentionConfig::default());
 
     // check [mailer_config]
     assert_eq!(config.mailer_config.smtp_port, 2525);
     assert_eq!(config.mailer_config.smtp_host, "localhost");
     assert_eq!(config.mailer_config.senders.len(), 2);
+    assert_eq!(config.mailer_config.smtp_pool, SmtpPoolConfig::default());
+    assert_eq!(config.mailer_config.backend, DeliveryBackend::Smtp);
+    assert!(config.mailer_config.sandbox.is_none());
+    assert!(config.mailer_config.bounces.is_none());
+    assert!(config.mailer_config.policy.is_none());
+    assert!(config.mailer_config.rate_limit.is_none());
 
     let first_sender = config.mailer_config.default_sender();
     assert_eq!(first_sender.email, "test@test.com");
@@ -194,4 +714,348 @@ fn test_toml_config() {
     assert!(second_sender.credentials.is_none());
 
     assert_eq!(config.logger_config.config_file_path, "log4rs.yaml");
+    assert!(config.inbound_config.is_none());
+    assert!(config.unsubscribe_config.is_none());
+}
+
+#[test]
+fn test_toml_config_per_sender_smtp() {
+    let toml_str = r#"
+    server_host = "127.0.0.1:3000"
+    [db_config]
+    db_path = "mailer.db"
+    [mailer_config]
+    smtp_port = 2525
+    smtp_host = "localhost"
+    smtp_tls = "starttls_opportunistic"
+    [[mailer_config.senders]]
+    email = "test@test.com"
+    [[mailer_config.senders]]
+    email = "test2@test.com"
+    smtp_host = "smtp.domain.com"
+    smtp_port = 465
+    tls = "implicit"
+    ca_certificate_path = "ca.pem"
+    [mailer_config.senders.client_certificate]
+    certificate_path = "client.pem"
+    private_key_path = "client.key"
+    [logger_config]
+    config_file_path = "log4rs.yaml"
+    "#;
+
+    let config = toml::from_str::<Config>(toml_str).unwrap();
+    let mailer_config = &config.mailer_config;
+
+    // the first sender falls back to the global values
+    let first_sender = mailer_config.default_sender();
+    assert_eq!(mailer_config.smtp_host_for(first_sender), "localhost");
+    assert_eq!(mailer_config.smtp_port_for(first_sender), 2525);
+    assert_eq!(
+        mailer_config.tls_mode_for(first_sender),
+        TlsMode::StarttlsOpportunistic
+    );
+
+    // the second sender overrides them
+    let second_sender = mailer_config.senders.get(1).unwrap();
+    assert_eq!(
+        mailer_config.smtp_host_for(second_sender),
+        "smtp.domain.com"
+    );
+    assert_eq!(mailer_config.smtp_port_for(second_sender), 465);
+    assert_eq!(mailer_config.tls_mode_for(second_sender), TlsMode::Implicit);
+    assert_eq!(second_sender.ca_certificate_path.as_deref(), Some("ca.pem"));
+    let client_certificate = second_sender.client_certificate.as_ref().unwrap();
+    assert_eq!(client_certificate.certificate_path, "client.pem");
+    assert_eq!(client_certificate.private_key_path, "client.key");
+}
+
+/// Parses a config made of the required settings followed by `tables`, which
+/// may extend the mailer config and its only sender.
+#[cfg(test)]
+fn parse_test_config(tables: &str) -> Config {
+    let toml_str = format!(
+        r#"
+    server_host = "127.0.0.1:3000"
+    [db_config]
+    db_path = "mailer.db"
+    [logger_config]
+    config_file_path = "log4rs.yaml"
+    [mailer_config]
+    smtp_port = 2525
+    smtp_host = "localhost"
+    [[mailer_config.senders]]
+    email = "test@test.com"
+    {tables}"#
+    );
+
+    toml::from_str::<Config>(&toml_str).unwrap()
+}
+
+#[test]
+fn test_toml_config_smtp_pool() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.smtp_pool]
+    max_size = 4
+    idle_timeout_secs = 30
+    "#,
+    );
+    assert_eq!(
+        config.mailer_config.smtp_pool,
+        SmtpPoolConfig {
+            max_size: 4,
+            idle_timeout_secs: 30,
+        }
+    );
+}
+
+#[test]
+fn test_toml_config_backend() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.backend]
+    kind = "file"
+    dir = "outbox"
+    "#,
+    );
+    assert_eq!(
+        config.mailer_config.backend,
+        DeliveryBackend::File {
+            dir: "outbox".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_toml_config_sandbox() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.sandbox]
+    redirect_to = "catch-all@test.com"
+    "#,
+    );
+    let sandbox = config.mailer_config.sandbox.unwrap();
+    assert_eq!(sandbox.redirect_to.as_deref(), Some("catch-all@test.com"));
+    assert!(sandbox.dir.is_none());
+}
+
+#[test]
+fn test_toml_config_dkim() {
+    let config = parse_test_config(
+        r#"
+    [[mailer_config.dkim]]
+    domain = "domain.com"
+    selector = "mail"
+    private_key_path = "dkim.pem"
+    "#,
+    );
+    let dkim = &config.mailer_config.dkim[0];
+    assert_eq!(dkim.domain, "domain.com");
+    assert_eq!(dkim.selector, "mail");
+    assert_eq!(dkim.private_key_path, "dkim.pem");
+    assert_eq!(dkim.algorithm, DkimAlgorithm::Rsa);
+    assert_eq!(dkim.signed_headers, default_dkim_signed_headers());
+    assert_eq!(dkim.canonicalization, "relaxed/relaxed");
+}
+
+#[test]
+fn test_toml_config_pgp_signing_key() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.senders.pgp_signing_key]
+    private_key_path = "pgp.asc"
+    sign_unencrypted = true
+    "#,
+    );
+    let pgp_signing_key = config.mailer_config.senders[0]
+        .pgp_signing_key
+        .as_ref()
+        .unwrap();
+    assert_eq!(pgp_signing_key.private_key_path, "pgp.asc");
+    assert!(pgp_signing_key.passphrase.is_none());
+    assert!(pgp_signing_key.sign_unencrypted);
+}
+
+#[test]
+fn test_toml_config_smime() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.senders.smime]
+    certificate_path = "smime.pem"
+    private_key_path = "smime.key"
+    "#,
+    );
+    let smime = config.mailer_config.senders[0].smime.as_ref().unwrap();
+    assert_eq!(smime.certificate_path, "smime.pem");
+    assert_eq!(smime.private_key_path, "smime.key");
+    assert!(!smime.sign_unencrypted);
+}
+
+#[test]
+fn test_toml_config_raw_email_retention() {
+    let toml_str = r#"
+    server_host = "127.0.0.1:3000"
+    [db_config]
+    db_path = "mailer.db"
+    raw_email_retention_days = 90
+    [logger_config]
+    config_file_path = "log4rs.yaml"
+    [mailer_config]
+    smtp_port = 2525
+    smtp_host = "localhost"
+    [[mailer_config.senders]]
+    email = "test@test.com"
+    "#;
+
+    let config = toml::from_str::<Config>(toml_str).unwrap();
+    assert_eq!(config.db_config.raw_email_retention_days, Some(90));
+}
+
+#[test]
+fn test_toml_config_retention() {
+    let config = parse_test_config(
+        r#"
+    [db_config.retention]
+    interval_hours = 6
+    [[db_config.retention.rules]]
+    action = "delete_body"
+    after_days = 30
+    [[db_config.retention.rules]]
+    action = "delete_record"
+    after_days = 365
+    group = "sales"
+    "#,
+    );
+    assert_eq!(config.db_config.retention.interval_hours, 6);
+    assert_eq!(
+        config.db_config.retention.rules,
+        vec![
+            RetentionRule {
+                action: RetentionAction::DeleteBody,
+                after_days: 30,
+                sender: None,
+                group: None,
+            },
+            RetentionRule {
+                action: RetentionAction::DeleteRecord,
+                after_days: 365,
+                sender: None,
+                group: Some("sales".to_string()),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_toml_config_imap() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.senders.imap]
+    host = "imap.domain.com"
+    sync_interval_minutes = 5
+    "#,
+    );
+    let imap = config.mailer_config.senders[0].imap.as_ref().unwrap();
+    assert_eq!(imap.host, "imap.domain.com");
+    assert_eq!(imap.port, 993);
+    assert!(imap.tls);
+    assert_eq!(imap.sync_interval_minutes, Some(5));
+}
+
+#[test]
+fn test_toml_config_inbound() {
+    let config = parse_test_config(
+        r#"
+    [inbound_config]
+    token = "secret"
+    "#,
+    );
+    assert_eq!(
+        config.inbound_config,
+        Some(InboundConfig {
+            path: "/inbound".to_string(),
+            token: "secret".to_string(),
+            max_message_bytes: 10 * 1024 * 1024,
+        })
+    );
+}
+
+#[test]
+fn test_toml_config_bounces() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.bounces]
+    mailbox = "test@test.com"
+    folder = "Bounces"
+    return_path = "bounces@test.com"
+    "#,
+    );
+    assert_eq!(
+        config.mailer_config.bounces,
+        Some(BounceConfig {
+            mailbox: "test@test.com".to_string(),
+            folder: "Bounces".to_string(),
+            return_path: Some("bounces@test.com".to_string()),
+            soft_bounce_limit: 3,
+            sync_interval_minutes: 10,
+        })
+    );
+}
+
+#[test]
+fn test_toml_config_unsubscribe() {
+    let config = parse_test_config(
+        r#"
+    [unsubscribe_config]
+    base_url = "https://mailer.test.com"
+    secret = "0123456789abcdef"
+    "#,
+    );
+    let unsubscribe = config.unsubscribe_config.unwrap();
+    assert_eq!(unsubscribe.base_url, "https://mailer.test.com");
+    assert_eq!(unsubscribe.secret, "0123456789abcdef");
+}
+
+#[test]
+fn test_toml_config_policy() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.policy]
+    allowed_domains = ["test.com", "domain.com"]
+    denied_domains = ["partner.domain.com"]
+    max_recipients = 20
+    "#,
+    );
+    assert_eq!(
+        config.mailer_config.policy,
+        Some(SendPolicy {
+            allowed_domains: vec!["test.com".to_string(), "domain.com".to_string()],
+            denied_domains: vec!["partner.domain.com".to_string()],
+            max_recipients: Some(20),
+        })
+    );
+}
+
+#[test]
+fn test_toml_config_rate_limit() {
+    let config = parse_test_config(
+        r#"
+    [mailer_config.rate_limit]
+    messages_per_minute = 100
+    [mailer_config.senders.rate_limit]
+    messages_per_minute = 10
+    burst = 2
+    "#,
+    );
+    // The burst defaults to the rate
+    let rate_limit = config.mailer_config.rate_limit.unwrap();
+    assert_eq!(rate_limit.messages_per_minute, 100);
+    assert_eq!(rate_limit.burst(), 100);
+    assert_eq!(
+        config.mailer_config.senders[0].rate_limit,
+        Some(RateLimitConfig {
+            messages_per_minute: 10,
+            burst: Some(2),
+        })
+    );
 }
@@ -0,0 +1,79 @@
+use crate::{
+    error::MailerError,
+    model::{
+        bounce::{Bounce, BounceKind, NewBounce},
+        email_record::EmailRecord,
+    },
+    pagination::Page,
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Records a bounce. Returns `false` if the notification was already
+    /// recorded for this address.
+    pub fn add_bounce(&mut self, new_bounce: NewBounce) -> Result<bool, MailerError> {
+        use schema::bounces::dsl::*;
+
+        diesel::insert_into(bounces)
+            .values((new_bounce, received_at.eq(diesel::dsl::now)))
+            .on_conflict_do_nothing()
+            .execute(&mut self.connection)
+            .map(|inserted| inserted > 0)
+            .map_err(MailerError::from)
+    }
+
+    pub fn count_bounces(
+        &mut self,
+        by_recipient_id: i32,
+        by_kind: BounceKind,
+    ) -> Result<i64, MailerError> {
+        use schema::bounces::dsl::*;
+
+        bounces
+            .filter(recipient_id.eq(by_recipient_id).and(kind.eq(by_kind)))
+            .count()
+            .get_result(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn list_bounces(
+        &mut self,
+        by_email: Option<String>,
+        page: &Page,
+    ) -> Result<Vec<Bounce>, MailerError> {
+        use schema::bounces::dsl::*;
+
+        let mut query = bounces.into_boxed();
+        if let Some(by_email) = by_email {
+            query = query.filter(email.eq(by_email.to_lowercase()));
+        }
+
+        paginate!(query, id, page)
+            .select(Bounce::as_select())
+            .load::<Bounce>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Finds the latest email record whose `Message-ID` has the given local
+    /// part, i.e. `<local_part@...>`.
+    pub fn find_email_record_by_message_id_local_part(
+        &mut self,
+        local_part: &str,
+    ) -> Result<Option<EmailRecord>, MailerError> {
+        use schema::email_history::dsl::*;
+
+        // Match the local part literally
+        let pattern = local_part
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        email_history
+            .filter(message_id.like(format!("<{pattern}@%")).escape('\\'))
+            .order(id.desc())
+            .first::<EmailRecord>(&mut self.connection)
+            .optional()
+            .map_err(MailerError::from)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     error::{MailerError, new_rmcp_error},
-    model::email_record::EmailRecord,
+    model::email_record::{EmailRecord, EmailSearchResult, EmailStatus, NewEmailRecord},
+    pagination::Page,
 };
 use diesel::prelude::*;
 
@@ -11,45 +12,125 @@ impl Database {
         &mut self,
         start_end_time: Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)>,
         by_recipient_id: Option<i32>,
+        by_sender: Option<String>,
+        by_status: Option<EmailStatus>,
+        page: &Page,
     ) -> Result<Vec<EmailRecord>, MailerError> {
         use schema::email_history::dsl::*;
         use schema::email_history_recipients::dsl::*;
 
-        if start_end_time.is_none() && by_recipient_id.is_none() {
+        if start_end_time.is_none()
+            && by_recipient_id.is_none()
+            && by_sender.is_none()
+            && by_status.is_none()
+        {
             return Err(new_rmcp_error(
                 "At least one filter must be provided for listing email records.",
             ));
         }
 
-        let mut query = email_history_recipients
-            .inner_join(schema::email_history::table)
-            .into_boxed();
+        // Records without recipient rows are listed too
+        let mut query = email_history.into_boxed();
 
         if let Some((start, end)) = start_end_time {
             query = query.filter(sent_at.ge(start).and(sent_at.le(end)));
         }
 
         if let Some(by_recipient_id) = by_recipient_id {
-            query = query.filter(recipient_id.eq(by_recipient_id));
+            query = query.filter(
+                id.eq_any(
+                    email_history_recipients
+                        .filter(recipient_id.eq(by_recipient_id))
+                        .select(email_history_id),
+                ),
+            );
         }
-        query
+
+        if let Some(by_sender) = by_sender {
+            query = query.filter(sender.eq(by_sender));
+        }
+
+        if let Some(by_status) = by_status {
+            query = query.filter(status.eq(by_status));
+        }
+        paginate!(query, id, page)
             .select(EmailRecord::as_select())
             .load::<EmailRecord>(&mut self.connection)
             .map_err(MailerError::from)
     }
 
+    /// Searches the email history with an FTS5 query over the subject, body,
+    /// sender and recipients. Returns the best matches first.
+    pub fn search_email_records(
+        &mut self,
+        query: &str,
+        by_sender: Option<String>,
+        start_end_time: Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)>,
+        by_group_id: Option<i32>,
+        limit: i64,
+    ) -> Result<Vec<EmailSearchResult>, MailerError> {
+        use diesel::sql_types::{BigInt, Integer, Nullable, Text, Timestamp};
+
+        let (start, end) = start_end_time.unzip();
+
+        diesel::sql_query(
+            "SELECT h.id, h.subject, h.sender, h.sent_at,
+                snippet(email_search, -1, '**', '**', '...', 16) AS snippet
+            FROM email_search
+            JOIN email_history h ON h.id = email_search.rowid
+            WHERE email_search MATCH ?1
+                AND (?2 IS NULL OR h.sender = ?2)
+                AND (?3 IS NULL OR h.sent_at >= ?3)
+                AND (?4 IS NULL OR h.sent_at <= ?4)
+                AND (?5 IS NULL OR h.id IN (
+                    SELECT hr.email_history_id
+                    FROM email_history_recipients hr
+                    JOIN group_recipients gr ON gr.recipient_id = hr.recipient_id
+                    WHERE gr.group_id = ?5
+                ))
+            ORDER BY bm25(email_search)
+            LIMIT ?6",
+        )
+        .bind::<Text, _>(query)
+        .bind::<Nullable<Text>, _>(by_sender)
+        .bind::<Nullable<Timestamp>, _>(start)
+        .bind::<Nullable<Timestamp>, _>(end)
+        .bind::<Nullable<Integer>, _>(by_group_id)
+        .bind::<BigInt, _>(limit)
+        .load::<EmailSearchResult>(&mut self.connection)
+        .map_err(MailerError::from)
+    }
+
+    pub fn find_email_record_by_id(&mut self, record_id: i32) -> Result<EmailRecord, MailerError> {
+        use schema::email_history::dsl::*;
+
+        email_history
+            .filter(id.eq(record_id))
+            .first::<EmailRecord>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the records of the thread, oldest first.
+    pub fn list_email_records_by_thread(
+        &mut self,
+        root_id: i32,
+    ) -> Result<Vec<EmailRecord>, MailerError> {
+        use schema::email_history::dsl::*;
+
+        email_history
+            .filter(id.eq(root_id).or(thread_id.eq(root_id)))
+            .order(id.asc())
+            .load::<EmailRecord>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
     pub fn add_email_record(
         &mut self,
-        new_subject: String,
-        new_body: String,
+        new_record: NewEmailRecord,
     ) -> Result<EmailRecord, MailerError> {
         use schema::email_history::dsl::*;
         diesel::insert_into(schema::email_history::table)
-            .values((
-                subject.eq(new_subject),
-                body.eq(new_body),
-                sent_at.eq(diesel::dsl::now),
-            ))
+            .values((new_record, sent_at.eq(diesel::dsl::now)))
             .returning(EmailRecord::as_returning())
             .get_result(&mut self.connection)
             .map_err(MailerError::from)
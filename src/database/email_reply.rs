@@ -0,0 +1,112 @@
+use crate::{
+    error::MailerError,
+    model::{
+        email_record::{EmailRecord, EmailStatus},
+        email_reply::{EmailReply, InboundSyncState, NewEmailReply},
+    },
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Records a reply. Returns `false` if a reply with the same `Message-ID`
+    /// was already recorded.
+    pub fn add_email_reply(&mut self, new_reply: NewEmailReply) -> Result<bool, MailerError> {
+        use schema::email_replies::dsl::*;
+
+        diesel::insert_into(email_replies)
+            .values((new_reply, received_at.eq(diesel::dsl::now)))
+            .on_conflict_do_nothing()
+            .execute(&mut self.connection)
+            .map(|inserted| inserted > 0)
+            .map_err(MailerError::from)
+    }
+
+    pub fn list_email_replies(&mut self, record_id: i32) -> Result<Vec<EmailReply>, MailerError> {
+        use schema::email_replies::dsl::*;
+
+        email_replies
+            .filter(email_history_id.eq(record_id))
+            .order(id.asc())
+            .load::<EmailReply>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn find_email_record_by_message_id(
+        &mut self,
+        by_message_id: &str,
+    ) -> Result<Option<EmailRecord>, MailerError> {
+        use schema::email_history::dsl::*;
+
+        email_history
+            .filter(message_id.eq(by_message_id))
+            .first::<EmailRecord>(&mut self.connection)
+            .optional()
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the delivered emails sent before `sent_before` that got no reply, oldest first.
+    pub fn list_unanswered_email_records(
+        &mut self,
+        sent_before: chrono::NaiveDateTime,
+        by_sender: Option<String>,
+        limit: i64,
+    ) -> Result<Vec<EmailRecord>, MailerError> {
+        use schema::email_history::dsl::*;
+
+        let replied_ids =
+            schema::email_replies::table.select(schema::email_replies::email_history_id);
+        let mut query = email_history
+            .filter(sent_at.lt(sent_before))
+            .filter(status.ne(EmailStatus::Failed))
+            .filter(id.ne_all(replied_ids))
+            .into_boxed();
+
+        if let Some(by_sender) = by_sender {
+            query = query.filter(sender.eq(by_sender));
+        }
+
+        query
+            .order(sent_at.asc())
+            .limit(limit)
+            .load::<EmailRecord>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn find_inbound_sync_state(
+        &mut self,
+        by_account: &str,
+        by_folder: &str,
+    ) -> Result<Option<InboundSyncState>, MailerError> {
+        use schema::inbound_sync_state::dsl::*;
+
+        inbound_sync_state
+            .filter(account.eq(by_account).and(folder.eq(by_folder)))
+            .select((uid_validity, last_uid))
+            .first::<InboundSyncState>(&mut self.connection)
+            .optional()
+            .map_err(MailerError::from)
+    }
+
+    pub fn set_inbound_sync_state(
+        &mut self,
+        by_account: &str,
+        by_folder: &str,
+        state: InboundSyncState,
+    ) -> Result<(), MailerError> {
+        use schema::inbound_sync_state::dsl::*;
+
+        diesel::replace_into(inbound_sync_state)
+            .values((
+                account.eq(by_account),
+                folder.eq(by_folder),
+                uid_validity.eq(i64::from(state.uid_validity)),
+                last_uid.eq(i64::from(state.last_uid)),
+            ))
+            .execute(&mut self.connection)
+            .map_err(MailerError::from)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{
+    error::MailerError,
+    model::email_stats::{EmailStatsDimension, EmailStatsRow},
+};
+use diesel::prelude::*;
+
+use super::Database;
+
+impl Database {
+    /// Counts the recorded emails by delivery status, per period of time or per
+    /// recipient, group, sender or template.
+    pub fn email_stats(
+        &mut self,
+        dimension: EmailStatsDimension,
+        start_end_time: Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)>,
+    ) -> Result<Vec<EmailStatsRow>, MailerError> {
+        use diesel::sql_types::{Nullable, Timestamp};
+
+        let (key, joins) = match dimension {
+            EmailStatsDimension::Day => ("strftime('%Y-%m-%d', h.sent_at)", ""),
+            EmailStatsDimension::Week => ("strftime('%Y-W%W', h.sent_at)", ""),
+            EmailStatsDimension::Month => ("strftime('%Y-%m', h.sent_at)", ""),
+            EmailStatsDimension::Recipient => (
+                "r.email",
+                "JOIN email_history_recipients hr ON hr.email_history_id = h.id
+                JOIN recipients r ON r.id = hr.recipient_id",
+            ),
+            EmailStatsDimension::Group => (
+                "g.name",
+                "JOIN email_history_recipients hr ON hr.email_history_id = h.id
+                JOIN group_recipients gr ON gr.recipient_id = hr.recipient_id
+                JOIN groups g ON g.id = gr.group_id",
+            ),
+            EmailStatsDimension::Sender => ("h.sender", ""),
+            EmailStatsDimension::Template => ("t.name", "JOIN templates t ON t.id = h.template_id"),
+        };
+        let order = if dimension.is_period() {
+            "key"
+        } else {
+            "total DESC, key"
+        };
+
+        // An email joined to several members of a group is counted once
+        diesel::sql_query(format!(
+            "SELECT {key} AS key,
+                COUNT(DISTINCT h.id) AS total,
+                COUNT(DISTINCT CASE WHEN h.status = 'sent' THEN h.id END) AS sent,
+                COUNT(DISTINCT CASE WHEN h.status = 'queued' THEN h.id END) AS queued,
+                COUNT(DISTINCT CASE WHEN h.status = 'failed' THEN h.id END) AS failed
+            FROM email_history h
+            {joins}
+            WHERE (?1 IS NULL OR h.sent_at >= ?1)
+                AND (?2 IS NULL OR h.sent_at <= ?2)
+            GROUP BY key
+            ORDER BY {order}"
+        ))
+        .bind::<Nullable<Timestamp>, _>(start_end_time.map(|(start, _)| start))
+        .bind::<Nullable<Timestamp>, _>(start_end_time.map(|(_, end)| end))
+        .load::<EmailStatsRow>(&mut self.connection)
+        .map_err(MailerError::from)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{error::MailerError, model::event::Event};
+use crate::{error::MailerError, model::event::Event, pagination::Page};
 use diesel::prelude::*;
 
 use super::{Database, schema};
@@ -8,6 +8,7 @@ impl Database {
         &mut self,
         from_time: chrono::NaiveDateTime,
         to_time: Option<chrono::NaiveDateTime>,
+        page: &Page,
     ) -> Result<Vec<Event>, MailerError> {
         use schema::events::dsl::*;
 
@@ -17,7 +18,7 @@ impl Database {
             query = query.filter(start_time.le(to_time));
         }
 
-        query
+        paginate!(query, id, page)
             .select(Event::as_select())
             .load::<Event>(&mut self.connection)
             .map_err(MailerError::from)
@@ -1,13 +1,13 @@
-use crate::{error::MailerError, model::group::Group};
+use crate::{error::MailerError, model::group::Group, pagination::Page};
 use diesel::prelude::*;
 
 use super::{Database, schema};
 
 impl Database {
-    pub fn list_groups(&mut self) -> Result<Vec<Group>, MailerError> {
+    pub fn list_groups(&mut self, page: &Page) -> Result<Vec<Group>, MailerError> {
         use schema::groups::dsl::*;
 
-        groups
+        paginate!(groups.into_boxed(), id, page)
             .load::<Group>(&mut self.connection)
             .map_err(MailerError::from)
     }
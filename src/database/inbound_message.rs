@@ -0,0 +1,74 @@
+use crate::{
+    error::MailerError,
+    model::{
+        inbound_message::{InboundMessage, NewInboundMessage},
+        raw_email::RawEmail,
+    },
+    pagination::Page,
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Stores a received message. Returns `None` if a message with the same
+    /// `Message-ID` was already stored.
+    pub fn add_inbound_message(
+        &mut self,
+        new_message: NewInboundMessage,
+    ) -> Result<Option<InboundMessage>, MailerError> {
+        use schema::inbound_messages::dsl::*;
+
+        diesel::insert_into(inbound_messages)
+            .values((new_message, received_at.eq(diesel::dsl::now)))
+            .on_conflict_do_nothing()
+            .returning(InboundMessage::as_returning())
+            .get_result(&mut self.connection)
+            .optional()
+            .map_err(MailerError::from)
+    }
+
+    pub fn find_inbound_message(&mut self, inbound_id: i32) -> Result<InboundMessage, MailerError> {
+        use schema::inbound_messages::dsl::*;
+
+        inbound_messages
+            .find(inbound_id)
+            .select(InboundMessage::as_select())
+            .first(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the message exactly as it was received.
+    pub fn find_inbound_raw_message(&mut self, inbound_id: i32) -> Result<Vec<u8>, MailerError> {
+        use schema::inbound_messages::dsl::*;
+
+        let compressed = inbound_messages
+            .find(inbound_id)
+            .select(raw_message)
+            .first::<Vec<u8>>(&mut self.connection)?;
+        RawEmail::decompress_bytes(&compressed)
+    }
+
+    pub fn list_inbound_messages(
+        &mut self,
+        by_sender: Option<String>,
+        by_recipient_id: Option<i32>,
+        page: &Page,
+    ) -> Result<Vec<InboundMessage>, MailerError> {
+        use schema::inbound_messages::dsl::*;
+
+        let mut query = inbound_messages.into_boxed();
+
+        if let Some(by_sender) = by_sender {
+            query = query.filter(sender.eq(by_sender.to_lowercase()));
+        }
+        if let Some(by_recipient_id) = by_recipient_id {
+            query = query.filter(recipient_id.eq(by_recipient_id));
+        }
+
+        paginate!(query, id, page)
+            .select(InboundMessage::as_select())
+            .load::<InboundMessage>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+}
@@ -1,21 +1,59 @@
+/// Applies a `Page` to a boxed query, using `$id` as the sort key.
+macro_rules! paginate {
+    ($query:expr, $id:expr, $page:expr) => {{
+        let page: &$crate::pagination::Page = $page;
+        let mut query = $query;
+        match page.order {
+            $crate::pagination::SortOrder::Asc => {
+                if let Some(after_id) = page.after_id {
+                    query = query.filter($id.gt(after_id));
+                }
+                query = query.order($id.asc());
+            }
+            $crate::pagination::SortOrder::Desc => {
+                if let Some(after_id) = page.after_id {
+                    query = query.filter($id.lt(after_id));
+                }
+                query = query.order($id.desc());
+            }
+        }
+        if let Some(limit) = page.limit {
+            query = query.limit(limit);
+        }
+        query
+    }};
+}
+
+pub(crate) mod bounce;
 pub(crate) mod email_record;
+pub(crate) mod email_reply;
+pub(crate) mod email_stats;
 pub(crate) mod event;
 pub(crate) mod event_attendee;
 pub(crate) mod group;
+pub(crate) mod inbound_message;
+pub(crate) mod raw_email;
 pub(crate) mod recipient;
+pub(crate) mod recipient_attribute;
 pub(crate) mod recipient_email_record;
 pub(crate) mod recipient_group;
+pub(crate) mod recipient_tag;
+pub(crate) mod retention;
 pub(crate) mod schema;
+pub(crate) mod suppression;
 pub(crate) mod template;
+pub(crate) mod unsubscribe;
 
 use std::fmt::Debug;
 
 use diesel::prelude::*;
 
-use crate::config::DatabaseConfig;
+use crate::config::{DatabaseConfig, RetentionRule};
 
 pub struct Database {
     connection: diesel::SqliteConnection,
+    raw_email_retention_days: Option<u32>,
+    retention_rules: Vec<RetentionRule>,
 }
 
 impl Database {
@@ -28,7 +66,54 @@ impl Database {
                 .execute(&mut connection)
                 .expect("Error creating tables");
         }
-        Self { connection }
+        for column in schema::added_columns() {
+            let exists = diesel::select(diesel::dsl::sql::<diesel::sql_types::Bool>(&format!(
+                "EXISTS (SELECT 1 FROM pragma_table_info('{}') WHERE name = '{}')",
+                column.table, column.name
+            )))
+            .get_result::<bool>(&mut connection)
+            .expect("Error reading the table columns");
+            if !exists {
+                diesel::sql_query(format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    column.table, column.name, column.definition
+                ))
+                .execute(&mut connection)
+                .expect("Error adding a column");
+            }
+        }
+        let outdated_recipients = diesel::select(diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'recipients' AND sql NOT LIKE '%''Bounced''%')",
+        ))
+        .get_result::<bool>(&mut connection)
+        .expect("Error reading the table schema");
+        if outdated_recipients {
+            for rebuild_sql in schema::rebuild_recipients_sqls() {
+                diesel::sql_query(rebuild_sql)
+                    .execute(&mut connection)
+                    .expect("Error rebuilding the recipients table");
+            }
+        }
+        let search_index_exists = diesel::select(diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'email_search')",
+        ))
+        .get_result::<bool>(&mut connection)
+        .expect("Error reading the table schema");
+        for index_sql in schema::create_search_index_sqls() {
+            diesel::sql_query(index_sql)
+                .execute(&mut connection)
+                .expect("Error creating the search index");
+        }
+        if !search_index_exists {
+            diesel::sql_query(schema::backfill_search_index_sql())
+                .execute(&mut connection)
+                .expect("Error filling the search index");
+        }
+        Self {
+            connection,
+            raw_email_retention_days: config.raw_email_retention_days,
+            retention_rules: config.retention.rules,
+        }
     }
 }
 
@@ -48,7 +133,21 @@ mod tests {
     use chrono::Days;
 
     use super::*;
-    use crate::{error::MailerError, model::recipient::Recipient};
+    use crate::{
+        bounce::{DeliveryStatusNotification, FailedRecipient, process_dsn},
+        config::{BounceConfig, RetentionAction, RetentionRule},
+        error::MailerError,
+        model::{
+            bounce::BounceKind,
+            email_record::{EmailStatus, NewEmailRecord},
+            email_reply::{InboundSyncState, NewEmailReply},
+            email_stats::EmailStatsDimension,
+            recipient::{Recipient, RecipientStatus},
+            suppression::NewSuppression,
+        },
+        pagination::{Page, SortOrder},
+        tag_expression::TagExpression,
+    };
 
     #[test]
     fn test_database() {
@@ -57,27 +156,153 @@ mod tests {
 
         let mut db = Database::new(DatabaseConfig {
             db_path: DB_PATH.to_string(),
+            raw_email_retention_days: Some(30),
+            retention: Default::default(),
         });
 
         // Test that the database is empty
-        assert!(db.list_recipients().unwrap().is_empty());
-        assert!(db.list_groups().unwrap().is_empty());
+        assert!(db.list_recipients(&Page::default()).unwrap().is_empty());
+        assert!(db.list_groups(&Page::default()).unwrap().is_empty());
 
         test_script_for_recipient(&mut db).expect("Failed to run test_script_for_recipient");
         test_script_for_group(&mut db).expect("Failed to run test_script_for_group");
         test_script_for_recipient_group(&mut db)
             .expect("Failed to run test_script_for_recipient_group");
+        test_script_for_recipient_tag(&mut db)
+            .expect("Failed to run test_script_for_recipient_tag");
         test_script_for_template(&mut db).expect("Failed to run test_script_for_template");
         test_script_for_email_record(&mut db).expect("Failed to run test_script_for_email_record");
+        test_script_for_email_reply(&mut db).expect("Failed to run test_script_for_email_reply");
+        test_script_for_inbound_message(&mut db)
+            .expect("Failed to run test_script_for_inbound_message");
+        test_script_for_bounce(&mut db).expect("Failed to run test_script_for_bounce");
+        test_script_for_unsubscribe(&mut db).expect("Failed to run test_script_for_unsubscribe");
+        test_script_for_suppression(&mut db).expect("Failed to run test_script_for_suppression");
         test_script_for_event(&mut db).expect("Failed to run test_script_for_event");
+        test_script_for_retention(&mut db).expect("Failed to run test_script_for_retention");
 
         drop(db);
         std::fs::remove_file(DB_PATH).expect("Failed to remove test.db");
     }
 
+    #[test]
+    fn test_migrate_baseline_database() {
+        const DB_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_baseline.db");
+        _ = std::fs::remove_file(DB_PATH);
+
+        // The tables changed since, as first released
+        let mut connection = SqliteConnection::establish(DB_PATH).unwrap();
+        for sql in [
+            "CREATE TABLE recipients (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                email TEXT NOT NULL UNIQUE,
+                status TEXT NOT NULL CHECK (status IN ('Active', 'Inactive'))
+            );",
+            "CREATE TABLE email_history (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+                subject TEXT NOT NULL,
+                body TEXT NOT NULL,
+                sent_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );",
+            "CREATE TABLE email_history_recipients (
+                email_history_id INTEGER,
+                recipient_id INTEGER,
+                PRIMARY KEY (email_history_id, recipient_id),
+                FOREIGN KEY (email_history_id) REFERENCES email_history(id),
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id)
+            );",
+            "INSERT INTO recipients (name, email, status) VALUES ('bob', 'bob@domain.com', 'Active');",
+            "INSERT INTO email_history (subject, body) VALUES ('Old Subject', 'Old Body');",
+            "INSERT INTO email_history_recipients (email_history_id, recipient_id) VALUES (1, 1);",
+        ] {
+            diesel::sql_query(sql).execute(&mut connection).unwrap();
+        }
+        drop(connection);
+
+        let config = DatabaseConfig {
+            db_path: DB_PATH.to_string(),
+            raw_email_retention_days: None,
+            retention: Default::default(),
+        };
+        // Opening it again doesn't add the columns twice
+        drop(Database::new(config.clone()));
+        let mut db = Database::new(config.clone());
+
+        let recipient = db
+            .find_recipient_by_email("bob@domain.com".to_string())
+            .unwrap();
+        assert!(recipient.pgp_public_key.is_none());
+        assert!(!recipient.encryption_required);
+        // The recipients table accepts the bounced status, and keeps its rows
+        let recipient = db
+            .set_recipient_status(recipient.id, RecipientStatus::Bounced)
+            .unwrap();
+        assert_eq!(recipient.status, RecipientStatus::Bounced);
+
+        let records = db
+            .list_email_records_by_criteria(
+                None,
+                Some(recipient.id),
+                None,
+                Some(EmailStatus::Sent),
+                &Page::default(),
+            )
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].subject, "Old Subject");
+        assert_eq!(records[0].message_id, "");
+
+        // The records saved before are indexed
+        let results = db
+            .search_email_records("subject", None, None, None, 10)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        // The table is rebuilt in a database with the search index too
+        for rebuild_sql in schema::rebuild_recipients_sqls() {
+            diesel::sql_query(rebuild_sql)
+                .execute(&mut db.connection)
+                .unwrap();
+        }
+        drop(db);
+        let mut db = Database::new(config);
+        let recipient = db
+            .set_recipient_status(recipient.id, RecipientStatus::Bounced)
+            .unwrap();
+        assert_eq!(recipient.status, RecipientStatus::Bounced);
+        assert_eq!(
+            db.search_email_records("bob", None, None, None, 10)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        drop(db);
+        std::fs::remove_file(DB_PATH).expect("Failed to remove test_baseline.db");
+    }
+
     fn test_script_for_recipient(db: &mut Database) -> Result<(), MailerError> {
         let nr = db.new_recipient("me".to_string(), "me@domain.com".to_string())?;
-        assert!(!db.list_recipients()?.is_empty());
+        assert!(!db.list_recipients(&Page::default())?.is_empty());
+
+        // Test for paginating recipients
+        let nr2 = db.new_recipient("you".to_string(), "you@domain.com".to_string())?;
+        let first_page = db.list_recipients(&Page {
+            after_id: None,
+            limit: Some(1),
+            order: SortOrder::Desc,
+        })?;
+        assert_eq!(first_page.len(), 1);
+        assert_eq!(first_page[0].id, nr2.id);
+        let second_page = db.list_recipients(&Page {
+            after_id: Some(nr2.id),
+            limit: Some(1),
+            order: SortOrder::Desc,
+        })?;
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].id, nr.id);
+        db.remove_recipient(nr2.id)?;
 
         // Test for updating recipient
         let updated_recipient =
@@ -86,19 +311,34 @@ mod tests {
         assert_eq!(updated_recipient.email, "me2@domain.com");
         assert_eq!(updated_recipient.id, nr.id);
 
+        // Test for setting the attributes of a recipient
+        let attributes = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<std::collections::HashMap<_, _>>()
+        };
+        db.set_recipient_attributes(nr.id, &attributes(&[("company", "ACME"), ("plan", "free")]))?;
+        db.set_recipient_attributes(nr.id, &attributes(&[("plan", "pro"), ("company", "")]))?;
+        assert_eq!(
+            db.find_recipient_attributes(nr.id)?,
+            attributes(&[("plan", "pro")])
+        );
+        assert!(db.find_recipient_attributes(nr2.id)?.is_empty());
+
         // Test for removing recipient
         let removed_recipient = db.remove_recipient(nr.id)?;
         assert_eq!(removed_recipient.name, "me2");
         assert_eq!(removed_recipient.id, nr.id);
         assert_eq!(removed_recipient.email, "me2@domain.com");
-        assert!(db.list_recipients()?.is_empty());
+        assert!(db.list_recipients(&Page::default())?.is_empty());
 
         Ok(())
     }
 
     fn test_script_for_group(db: &mut Database) -> Result<(), MailerError> {
         let ng = db.new_group("test".to_string())?;
-        assert!(!db.list_groups()?.is_empty());
+        assert!(!db.list_groups(&Page::default())?.is_empty());
 
         // Test for updating group
         let updated_group = db.update_group(ng.id, "test2".to_string())?;
@@ -109,7 +349,7 @@ mod tests {
         let removed_group = db.remove_group(ng.id)?;
         assert_eq!(removed_group.id, ng.id);
         assert_eq!(removed_group.name, "test2");
-        assert!(db.list_groups()?.is_empty());
+        assert!(db.list_groups(&Page::default())?.is_empty());
 
         Ok(())
     }
@@ -131,6 +371,9 @@ mod tests {
                 name: nr2.name.clone(),
                 email: nr2.email.clone(),
                 status: nr2.status.clone(),
+                pgp_public_key: None,
+                smime_certificate: None,
+                encryption_required: false,
             }
         );
         db.remove_recipient_from_group(ng2.id, nr2.id)?;
@@ -139,9 +382,72 @@ mod tests {
         Ok(())
     }
 
+    fn test_script_for_recipient_tag(db: &mut Database) -> Result<(), MailerError> {
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let emails = |db: &mut Database, expression: &str| -> Result<Vec<String>, MailerError> {
+            Ok(db
+                .find_recipients_by_tag_expression(&TagExpression::parse(expression)?)?
+                .into_iter()
+                .map(|r| r.email)
+                .collect())
+        };
+        let alice = db.new_recipient("Alice".to_string(), "alice@tag.com".to_string())?;
+        let bob = db.new_recipient("Bob".to_string(), "bob@tag.com".to_string())?;
+        let carol = db.new_recipient("Carol".to_string(), "carol@tag.com".to_string())?;
+
+        assert_eq!(
+            db.tag_recipients(&[alice.id, bob.id, carol.id], &tags(&["customer"]))?,
+            3
+        );
+        assert_eq!(
+            db.tag_recipients(&[alice.id], &tags(&["emea", "customer"]))?,
+            1
+        );
+        assert_eq!(
+            db.tag_recipients(&[bob.id], &tags(&["apac", "churned"]))?,
+            2
+        );
+        assert_eq!(db.tag_recipients(&[], &tags(&["emea"]))?, 0);
+        assert_eq!(
+            db.list_tags()?,
+            vec![
+                ("apac".to_string(), 1),
+                ("churned".to_string(), 1),
+                ("customer".to_string(), 3),
+                ("emea".to_string(), 1),
+            ]
+        );
+
+        assert_eq!(
+            emails(db, "customer AND (emea OR apac) AND NOT churned")?,
+            vec!["alice@tag.com"]
+        );
+        assert_eq!(
+            db.untag_recipients(&[bob.id], &tags(&["churned", "emea"]))?,
+            1
+        );
+        assert_eq!(
+            emails(db, "customer AND (emea OR apac) AND NOT churned")?,
+            vec!["alice@tag.com", "bob@tag.com"]
+        );
+
+        // Removed recipients don't match anymore
+        db.remove_recipient(alice.id)?;
+        db.remove_recipient(bob.id)?;
+        db.remove_recipient(carol.id)?;
+        assert!(
+            emails(db, "customer OR NOT churned")?
+                .iter()
+                .all(|e| !e.ends_with("@tag.com"))
+        );
+        assert!(db.list_tags()?.is_empty());
+
+        Ok(())
+    }
+
     fn test_script_for_template(db: &mut Database) -> Result<(), MailerError> {
         let nt = db.new_template("test".to_string(), "template {name}".to_string())?;
-        assert!(!db.list_templates()?.is_empty());
+        assert!(!db.list_templates(&Page::default())?.is_empty());
 
         // Test for updating template
         let updated_template = db.update_template(
@@ -156,19 +462,110 @@ mod tests {
         let removed_template = db.remove_template(nt.id)?;
         assert_eq!(removed_template.id, nt.id);
         assert_eq!(removed_template.name, "test2");
-        assert!(db.list_templates()?.is_empty());
+        assert!(db.list_templates(&Page::default())?.is_empty());
 
         Ok(())
     }
 
     fn test_script_for_email_record(db: &mut Database) -> Result<(), MailerError> {
-        let new_email_record =
-            db.add_email_record("Test Subject".to_string(), "Test Body".to_string())?;
+        let new_email_record = db.add_email_record(NewEmailRecord {
+            subject: "Test Subject".to_string(),
+            body: "Test Body".to_string(),
+            sandboxed: false,
+            message_id: "<1@domain.com>".to_string(),
+            sender: "test@test.com".to_string(),
+            headers: "Message-ID: <1@domain.com>\r\n".to_string(),
+            in_reply_to_id: None,
+            thread_id: None,
+            reply_to: None,
+            transport: "smtp".to_string(),
+            smtp_code: Some(250),
+            smtp_message: Some("OK".to_string()),
+            status: EmailStatus::Sent,
+            resent_from_id: None,
+            template_id: None,
+        })?;
         assert_eq!(new_email_record.subject, "Test Subject");
         assert_eq!(new_email_record.body, "Test Body");
+        assert!(!new_email_record.sandboxed);
+        assert_eq!(new_email_record.message_id, "<1@domain.com>");
+        assert_eq!(new_email_record.thread_root_id(), new_email_record.id);
+
+        let raw_message = b"Message-ID: <1@domain.com>\r\n\r\nTest Body\r\n";
+        db.add_raw_email(new_email_record.id, raw_message)?;
+        let raw_email = db.find_raw_email(new_email_record.id)?;
+        assert_ne!(raw_email.message, raw_message);
+        assert_eq!(raw_email.decompress()?, raw_message);
+
+        // Raw messages are kept for the configured days
+        assert_eq!(db.purge_raw_emails()?, 0);
+        diesel::sql_query("UPDATE raw_emails SET created_at = datetime('now', '-31 days')")
+            .execute(&mut db.connection)?;
+        assert_eq!(db.purge_raw_emails()?, 1);
+        assert!(db.find_raw_email(new_email_record.id).is_err());
+
+        let reply_record = db.add_email_record(NewEmailRecord {
+            subject: "Re: Test Subject".to_string(),
+            body: "Test Reply".to_string(),
+            sandboxed: false,
+            message_id: "<2@domain.com>".to_string(),
+            sender: "test@test.com".to_string(),
+            headers: "Message-ID: <2@domain.com>\r\n".to_string(),
+            in_reply_to_id: Some(new_email_record.id),
+            thread_id: Some(new_email_record.id),
+            reply_to: None,
+            transport: "smtp".to_string(),
+            smtp_code: Some(550),
+            smtp_message: Some("Mailbox unavailable".to_string()),
+            status: EmailStatus::Failed,
+            resent_from_id: None,
+            template_id: None,
+        })?;
+        assert_eq!(
+            db.find_email_record_by_id(reply_record.id)?.in_reply_to_id,
+            Some(new_email_record.id)
+        );
+        let thread = db.list_email_records_by_thread(new_email_record.id)?;
+        assert_eq!(
+            thread.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![new_email_record.id, reply_record.id]
+        );
 
         let nr = db.new_recipient("someone2".to_string(), "someone2@domain.com".to_string())?;
         db.add_recipient_email_record(new_email_record.id, nr.id)?;
+        assert_eq!(
+            db.find_recipients_by_email_record_id(new_email_record.id)?,
+            vec![nr.clone()]
+        );
+
+        // The recipients are indexed once they are added to the record
+        let results = db.search_email_records("someone2 AND body:test", None, None, None, 10)?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, new_email_record.id);
+        assert!(results[0].snippet.contains("**"));
+        let results =
+            db.search_email_records("reply", Some("test@test.com".to_string()), None, None, 10)?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, reply_record.id);
+        assert!(
+            db.search_email_records("test", Some("nobody@test.com".to_string()), None, None, 10)?
+                .is_empty()
+        );
+
+        let stats = db.email_stats(EmailStatsDimension::Sender, None)?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].key, "test@test.com");
+        assert_eq!((stats[0].total, stats[0].sent, stats[0].failed), (2, 1, 1));
+        assert_eq!(stats[0].failure_rate(), 0.5);
+        let stats = db.email_stats(EmailStatsDimension::Recipient, None)?;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].key, "someone2@domain.com");
+        assert_eq!((stats[0].total, stats[0].failed), (1, 0));
+        assert_eq!(db.email_stats(EmailStatsDimension::Day, None)?.len(), 1);
+        assert!(
+            db.email_stats(EmailStatsDimension::Template, None)?
+                .is_empty()
+        );
 
         let start_end_time = (
             chrono::Utc::now()
@@ -181,17 +578,309 @@ mod tests {
                 .unwrap(),
         );
 
-        let records = db.list_email_records_by_criteria(Some(start_end_time), Some(nr.id))?;
+        let records = db.list_email_records_by_criteria(
+            Some(start_end_time),
+            Some(nr.id),
+            None,
+            None,
+            &Page::default(),
+        )?;
         assert!(!records.is_empty());
         assert_eq!(records[0].id, new_email_record.id);
         assert_eq!(records[0].subject, "Test Subject");
         assert_eq!(records[0].body, "Test Body");
 
-        let records_2 = db.list_email_records_by_criteria(None, Some(nr.id))?;
+        let records_2 =
+            db.list_email_records_by_criteria(None, Some(nr.id), None, None, &Page::default())?;
         assert_eq!(records, records_2);
 
-        let records_3 = db.list_email_records_by_criteria(Some(start_end_time), None)?;
-        assert_eq!(records, records_3);
+        let records_3 = db.list_email_records_by_criteria(
+            Some(start_end_time),
+            None,
+            None,
+            None,
+            &Page::default(),
+        )?;
+        // The reply has no recipient rows but is still listed
+        assert_eq!(records_3.len(), 2);
+        assert_eq!(records_3[0], records[0]);
+        assert_eq!(records_3[1].subject, "Re: Test Subject");
+
+        let records_4 = db.list_email_records_by_criteria(
+            None,
+            None,
+            Some("test@test.com".to_string()),
+            Some(EmailStatus::Sent),
+            &Page::default(),
+        )?;
+        assert_eq!(records, records_4);
+        let failed = db.list_email_records_by_criteria(
+            None,
+            None,
+            None,
+            Some(EmailStatus::Failed),
+            &Page::default(),
+        )?;
+        assert_eq!(failed, records_3[1..]);
+
+        Ok(())
+    }
+
+    fn test_script_for_email_reply(db: &mut Database) -> Result<(), MailerError> {
+        let record = db
+            .find_email_record_by_message_id("<1@domain.com>")?
+            .expect("Email record not found");
+        assert!(
+            db.find_email_record_by_message_id("<missing@domain.com>")?
+                .is_none()
+        );
+
+        // The failed reply is not waiting for an answer
+        let sent_before = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(1);
+        let unanswered = db.list_unanswered_email_records(sent_before, None, 10)?;
+        assert_eq!(
+            unanswered.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![record.id]
+        );
+        assert!(
+            db.list_unanswered_email_records(sent_before, Some("nobody@test.com".to_string()), 10)?
+                .is_empty()
+        );
+
+        let recipient = db.find_recipient_by_email("someone2@domain.com".to_string())?;
+        let new_reply = NewEmailReply {
+            email_history_id: record.id,
+            recipient_id: Some(recipient.id),
+            message_id: "<reply-1@other.com>".to_string(),
+            sender: "someone2@domain.com".to_string(),
+            subject: "Re: Test Subject".to_string(),
+        };
+        assert!(db.add_email_reply(new_reply.clone())?);
+        assert!(!db.add_email_reply(new_reply)?);
+        let replies = db.list_email_replies(record.id)?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].recipient_id, Some(recipient.id));
+        assert!(
+            db.list_unanswered_email_records(sent_before, None, 10)?
+                .is_empty()
+        );
+
+        assert!(
+            db.find_inbound_sync_state("test@test.com", "INBOX")?
+                .is_none()
+        );
+        let state = InboundSyncState {
+            uid_validity: 3,
+            last_uid: 42,
+        };
+        db.set_inbound_sync_state("test@test.com", "INBOX", state)?;
+        db.set_inbound_sync_state(
+            "test@test.com",
+            "INBOX",
+            InboundSyncState {
+                last_uid: 43,
+                ..state
+            },
+        )?;
+        assert_eq!(
+            db.find_inbound_sync_state("test@test.com", "INBOX")?,
+            Some(InboundSyncState {
+                uid_validity: 3,
+                last_uid: 43
+            })
+        );
+
+        Ok(())
+    }
+
+    fn test_script_for_inbound_message(db: &mut Database) -> Result<(), MailerError> {
+        let raw = b"From: Someone <Someone2@domain.com>\r\n\
+            To: test@test.com\r\n\
+            Subject: Re: Test Subject\r\n\
+            Message-ID: <reply-2@other.com>\r\n\
+            References: <1@domain.com>\r\n\
+            \r\n\
+            Thanks!\r\n";
+        let message = crate::inbound::receive_message(db, raw)?.expect("Message not stored");
+        let record = db
+            .find_email_record_by_message_id("<1@domain.com>")?
+            .expect("Email record not found");
+        let recipient = db.find_recipient_by_email("someone2@domain.com".to_string())?;
+        assert_eq!(message.sender, "someone2@domain.com");
+        assert_eq!(message.recipient_id, Some(recipient.id));
+        assert_eq!(message.email_history_id, Some(record.id));
+        assert_eq!(message.body.trim(), "Thanks!");
+        assert_eq!(db.find_inbound_message(message.id)?, message);
+        assert_eq!(db.find_inbound_raw_message(message.id)?, raw);
+        assert_eq!(db.list_email_replies(record.id)?.len(), 2);
+
+        // The same message is stored once
+        assert!(crate::inbound::receive_message(db, raw)?.is_none());
+
+        let unlinked = b"From: stranger@other.com\r\nSubject: Hello\r\n\r\nHi\r\n";
+        let unlinked = crate::inbound::receive_message(db, unlinked)?.expect("Message not stored");
+        assert_eq!(unlinked.message_id, None);
+        assert_eq!(unlinked.recipient_id, None);
+        assert_eq!(unlinked.email_history_id, None);
+
+        let messages = db.list_inbound_messages(None, None, &Page::default())?;
+        assert_eq!(
+            messages.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![message.id, unlinked.id]
+        );
+        let messages = db.list_inbound_messages(None, Some(recipient.id), &Page::default())?;
+        assert_eq!(messages, vec![message]);
+        let messages = db.list_inbound_messages(
+            Some("Stranger@other.com".to_string()),
+            None,
+            &Page::default(),
+        )?;
+        assert_eq!(messages, vec![unlinked]);
+
+        Ok(())
+    }
+
+    fn test_script_for_bounce(db: &mut Database) -> Result<(), MailerError> {
+        let config = BounceConfig {
+            mailbox: "test@test.com".to_string(),
+            folder: "Bounces".to_string(),
+            return_path: Some("bounces@test.com".to_string()),
+            soft_bounce_limit: 2,
+            sync_interval_minutes: 10,
+        };
+        let record = db
+            .find_email_record_by_message_id("<1@domain.com>")?
+            .expect("Email record not found");
+        let soft = db.new_recipient("Soft".to_string(), "soft@domain.com".to_string())?;
+        let hard = db.new_recipient("Hard".to_string(), "hard@domain.com".to_string())?;
+        let failed = |email: &str, kind| FailedRecipient {
+            email: email.to_string(),
+            kind,
+            status: "4.2.2".to_string(),
+            diagnostic: None,
+        };
+
+        // Mapped back to the record by the VERP return path
+        let dsn = DeliveryStatusNotification {
+            message_id: Some("<dsn-1@mx.domain.com>".to_string()),
+            original_message_id: None,
+            delivered_to: vec!["bounces+1@test.com".to_string()],
+            failed_recipients: vec![failed("soft@domain.com", BounceKind::Soft)],
+        };
+        assert_eq!(process_dsn(db, &config, &dsn)?, 1);
+        assert_eq!(process_dsn(db, &config, &dsn)?, 0);
+        let bounces = db.list_bounces(Some("soft@domain.com".to_string()), &Page::default())?;
+        assert_eq!(bounces.len(), 1);
+        assert_eq!(bounces[0].email_history_id, Some(record.id));
+        assert_eq!(bounces[0].recipient_id, Some(soft.id));
+        assert_eq!(db.count_bounces(soft.id, BounceKind::Soft)?, 1);
+        assert!(
+            db.find_recipient_by_email("soft@domain.com".to_string())
+                .is_ok()
+        );
+
+        // The second soft bounce reaches the limit
+        let dsn = DeliveryStatusNotification {
+            message_id: Some("<dsn-2@mx.domain.com>".to_string()),
+            original_message_id: Some("<1@domain.com>".to_string()),
+            delivered_to: vec![],
+            failed_recipients: vec![
+                failed("soft@domain.com", BounceKind::Soft),
+                failed("unknown@domain.com", BounceKind::Hard),
+            ],
+        };
+        assert_eq!(process_dsn(db, &config, &dsn)?, 2);
+        assert!(
+            db.find_recipient_by_email("soft@domain.com".to_string())
+                .is_err()
+        );
+
+        // A single hard bounce deactivates the recipient
+        let dsn = DeliveryStatusNotification {
+            message_id: Some("<dsn-3@mx.domain.com>".to_string()),
+            original_message_id: Some("<missing@domain.com>".to_string()),
+            delivered_to: vec![],
+            failed_recipients: vec![failed("hard@domain.com", BounceKind::Hard)],
+        };
+        assert_eq!(process_dsn(db, &config, &dsn)?, 1);
+        assert!(
+            db.find_recipient_by_email("hard@domain.com".to_string())
+                .is_err()
+        );
+        let bounces = db.list_bounces(None, &Page::default())?;
+        assert_eq!(bounces.len(), 4);
+        assert_eq!(bounces[3].recipient_id, Some(hard.id));
+        assert_eq!(bounces[3].email_history_id, None);
+
+        // Bounced recipients are told apart from removed ones, and come back
+        // only when added again
+        let status = schema::recipients::table
+            .find(hard.id)
+            .select(schema::recipients::status)
+            .first::<RecipientStatus>(&mut db.connection)?;
+        assert_eq!(status, RecipientStatus::Bounced);
+        let hard = db.reactivate_recipient("Hard".to_string(), "hard@domain.com".to_string())?;
+        assert_eq!(hard.map(|r| r.status), Some(RecipientStatus::Active));
+        assert!(
+            db.reactivate_recipient("Hard".to_string(), "hard@domain.com".to_string())?
+                .is_none()
+        );
+        assert!(
+            db.reactivate_recipient("Nobody".to_string(), "nobody@domain.com".to_string())?
+                .is_none()
+        );
+
+        Ok(())
+    }
+
+    fn test_script_for_unsubscribe(db: &mut Database) -> Result<(), MailerError> {
+        let group = db.new_group("newsletter".to_string())?;
+        let other_group = db.new_group("announcements".to_string())?;
+
+        assert!(db.add_unsubscribe("Dan@Domain.com", Some(group.id))?);
+        assert!(!db.add_unsubscribe("dan@domain.com", Some(group.id))?);
+        assert!(db.add_unsubscribe("erin@domain.com", None)?);
+        assert!(!db.add_unsubscribe("erin@domain.com", None)?);
+
+        let unsubscribed = db.find_unsubscribed_emails(Some(group.id))?;
+        assert_eq!(unsubscribed.len(), 2);
+        assert!(unsubscribed.contains("dan@domain.com"));
+        assert!(unsubscribed.contains("erin@domain.com"));
+        let unsubscribed = db.find_unsubscribed_emails(Some(other_group.id))?;
+        assert_eq!(unsubscribed.len(), 1);
+        assert!(unsubscribed.contains("erin@domain.com"));
+        assert_eq!(db.find_unsubscribed_emails(None)?.len(), 1);
+
+        assert_eq!(db.list_unsubscribes(None)?.len(), 2);
+        assert_eq!(db.list_unsubscribes(Some(other_group.id))?.len(), 0);
+
+        // Removing the group removes its opt-outs
+        db.remove_group(group.id)?;
+        db.remove_group(other_group.id)?;
+        assert_eq!(db.list_unsubscribes(None)?.len(), 1);
+
+        Ok(())
+    }
+
+    fn test_script_for_suppression(db: &mut Database) -> Result<(), MailerError> {
+        let suppression = |entry: &str, reason: Option<&str>| NewSuppression {
+            entry: entry.to_string(),
+            reason: reason.map(str::to_string),
+        };
+        assert!(db.add_suppression(suppression("Frank@Domain.com", Some("legal hold")))?);
+        assert!(!db.add_suppression(suppression("frank@domain.com", None))?);
+        assert!(db.add_suppression(suppression("competitor.com", None))?);
+
+        let suppressions = db.list_suppressions(&Page::default())?;
+        assert_eq!(suppressions.len(), 2);
+        assert_eq!(suppressions[0].entry, "frank@domain.com");
+        assert_eq!(suppressions[0].reason.as_deref(), Some("legal hold"));
+        assert!(suppressions[1].is_domain());
+
+        assert!(db.remove_suppression("Frank@domain.com")?);
+        assert!(!db.remove_suppression("frank@domain.com")?);
+        assert!(db.remove_suppression("competitor.com")?);
+        assert!(db.list_suppressions(&Page::default())?.is_empty());
 
         Ok(())
     }
@@ -234,6 +923,7 @@ mod tests {
             chrono::Utc::now()
                 .naive_utc()
                 .checked_add_days(Days::new(3)),
+            &Page::default(),
         )?;
         assert_eq!(events.len(), 2);
 
@@ -245,6 +935,7 @@ mod tests {
             chrono::Utc::now()
                 .naive_utc()
                 .checked_add_days(Days::new(1)),
+            &Page::default(),
         )?;
         assert_eq!(events.len(), 1);
 
@@ -268,4 +959,80 @@ mod tests {
 
         Ok(())
     }
+
+    fn test_script_for_retention(db: &mut Database) -> Result<(), MailerError> {
+        let rule = |action, after_days, sender: Option<&str>, group: Option<&str>| RetentionRule {
+            action,
+            after_days,
+            sender: sender.map(str::to_string),
+            group: group.map(str::to_string),
+        };
+        let records = db.list_email_records_by_criteria(
+            None,
+            None,
+            Some("test@test.com".to_string()),
+            None,
+            &Page::default(),
+        )?;
+        assert_eq!(records.len(), 2);
+        assert!(db.purge_history(true)?.is_empty());
+
+        // Nothing is old enough, or sent by this sender
+        assert_eq!(
+            db.apply_retention_rule(&rule(RetentionAction::DeleteBody, 1, None, None), false)?,
+            0
+        );
+        let by_other_sender = rule(
+            RetentionAction::DeleteBody,
+            0,
+            Some("nobody@test.com"),
+            None,
+        );
+        assert_eq!(db.apply_retention_rule(&by_other_sender, false)?, 0);
+
+        // Deleting the bodies keeps the records
+        let delete_body = rule(RetentionAction::DeleteBody, 0, None, None);
+        assert_eq!(db.apply_retention_rule(&delete_body, true)?, 2);
+        assert_eq!(db.apply_retention_rule(&delete_body, false)?, 2);
+        let purged = db.find_email_record_by_id(records[0].id)?;
+        assert!(purged.body.is_empty());
+        assert!(purged.body_purged_at.is_some());
+        assert!(db.find_raw_email(records[0].id).is_err());
+        assert!(
+            db.search_email_records("body:reply", None, None, None, 10)?
+                .is_empty()
+        );
+        assert_eq!(db.apply_retention_rule(&delete_body, false)?, 0);
+
+        let delete_record = rule(RetentionAction::DeleteRecord, 0, None, Some("missing"));
+        assert_eq!(db.apply_retention_rule(&delete_record, false)?, 0);
+        let delete_record = rule(RetentionAction::DeleteRecord, 0, None, None);
+        assert_eq!(db.apply_retention_rule(&delete_record, false)?, 2);
+        assert!(db.find_email_record_by_id(records[0].id).is_err());
+
+        // More records than are purged in one statement, replying to one another
+        // across the chunks they are purged in
+        let count = retention::PURGE_CHUNK_SIZE * 2 + 1;
+        diesel::sql_query(format!(
+            "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {count})
+            INSERT INTO email_history (subject, body, sender, sent_at)
+            SELECT 'Bulk', 'Body', 'bulk@test.com', datetime('now', '-1 day') FROM n"
+        ))
+        .execute(&mut db.connection)?;
+        diesel::sql_query(
+            "UPDATE email_history SET in_reply_to_id = id - 1, thread_id = id - 1
+            WHERE sender = 'bulk@test.com' AND id - 1 IN (SELECT id FROM email_history WHERE sender = 'bulk@test.com')",
+        )
+        .execute(&mut db.connection)?;
+        let bulk = rule(
+            RetentionAction::DeleteRecord,
+            0,
+            Some("bulk@test.com"),
+            None,
+        );
+        assert_eq!(db.apply_retention_rule(&bulk, false)?, count);
+        assert_eq!(db.apply_retention_rule(&bulk, true)?, 0);
+
+        Ok(())
+    }
 }
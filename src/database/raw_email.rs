@@ -0,0 +1,49 @@
+use crate::{error::MailerError, model::raw_email::RawEmail};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Stores the raw message of the email record, compressed.
+    pub fn add_raw_email(
+        &mut self,
+        record_id: i32,
+        raw_message: &[u8],
+    ) -> Result<RawEmail, MailerError> {
+        use schema::raw_emails::dsl::*;
+
+        diesel::insert_into(raw_emails)
+            .values((
+                email_history_id.eq(record_id),
+                message.eq(RawEmail::compress(raw_message)?),
+                created_at.eq(diesel::dsl::now),
+            ))
+            .returning(RawEmail::as_returning())
+            .get_result(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Deletes the raw messages older than the configured retention. Returns
+    /// the number of messages deleted.
+    pub fn purge_raw_emails(&mut self) -> Result<usize, MailerError> {
+        use schema::raw_emails::dsl::*;
+
+        let Some(retention_days) = self.raw_email_retention_days else {
+            return Ok(0);
+        };
+        let cutoff =
+            chrono::Utc::now().naive_utc() - chrono::Duration::days(i64::from(retention_days));
+        diesel::delete(raw_emails.filter(created_at.lt(cutoff)))
+            .execute(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn find_raw_email(&mut self, record_id: i32) -> Result<RawEmail, MailerError> {
+        use schema::raw_emails::dsl::*;
+
+        raw_emails
+            .filter(email_history_id.eq(record_id))
+            .first::<RawEmail>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+}
@@ -1,17 +1,20 @@
 use crate::{
     error::MailerError,
     model::recipient::{Recipient, RecipientStatus},
+    pagination::Page,
 };
 use diesel::prelude::*;
 
 use super::{Database, schema};
 
 impl Database {
-    pub fn list_recipients(&mut self) -> Result<Vec<Recipient>, MailerError> {
+    pub fn list_recipients(&mut self, page: &Page) -> Result<Vec<Recipient>, MailerError> {
         use schema::recipients::dsl::*;
 
-        recipients
+        let query = recipients
             .filter(status.eq(RecipientStatus::Active))
+            .into_boxed();
+        paginate!(query, id, page)
             .load::<Recipient>(&mut self.connection)
             .map_err(MailerError::from)
     }
@@ -58,14 +61,77 @@ impl Database {
             .map_err(MailerError::from)
     }
 
-    pub fn remove_recipient(&mut self, recipient_id: i32) -> Result<Recipient, MailerError> {
+    pub fn set_recipient_pgp_key(
+        &mut self,
+        recipient_id: i32,
+        new_pgp_public_key: Option<String>,
+        new_encryption_required: bool,
+    ) -> Result<Recipient, MailerError> {
+        use schema::recipients::dsl::*;
+
+        diesel::update(recipients.filter(id.eq(recipient_id)))
+            .set((
+                pgp_public_key.eq(new_pgp_public_key),
+                encryption_required.eq(new_encryption_required),
+            ))
+            .returning(Recipient::as_returning())
+            .get_result(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn set_recipient_smime_certificate(
+        &mut self,
+        recipient_id: i32,
+        new_smime_certificate: Option<String>,
+        new_encryption_required: bool,
+    ) -> Result<Recipient, MailerError> {
         use schema::recipients::dsl::*;
 
+        diesel::update(recipients.filter(id.eq(recipient_id)))
+            .set((
+                smime_certificate.eq(new_smime_certificate),
+                encryption_required.eq(new_encryption_required),
+            ))
+            .returning(Recipient::as_returning())
+            .get_result(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    pub fn remove_recipient(&mut self, recipient_id: i32) -> Result<Recipient, MailerError> {
         // inactive the recipient instead of deleting
+        self.set_recipient_status(recipient_id, RecipientStatus::Inactive)
+    }
+
+    pub fn set_recipient_status(
+        &mut self,
+        recipient_id: i32,
+        new_status: RecipientStatus,
+    ) -> Result<Recipient, MailerError> {
+        use schema::recipients::dsl::*;
+
         diesel::update(recipients.filter(id.eq(recipient_id)))
-            .set(status.eq(RecipientStatus::Inactive))
+            .set(status.eq(new_status))
             .returning(Recipient::as_returning())
             .get_result(&mut self.connection)
             .map_err(MailerError::from)
     }
+
+    /// Makes a removed or bounced recipient active again, under a new name.
+    /// Returns `None` if there's no such recipient.
+    pub fn reactivate_recipient(
+        &mut self,
+        name_str: String,
+        email_str: String,
+    ) -> Result<Option<Recipient>, MailerError> {
+        use schema::recipients::dsl::*;
+
+        diesel::update(
+            recipients.filter(email.eq(email_str).and(status.ne(RecipientStatus::Active))),
+        )
+        .set((name.eq(name_str), status.eq(RecipientStatus::Active)))
+        .returning(Recipient::as_returning())
+        .get_result(&mut self.connection)
+        .optional()
+        .map_err(MailerError::from)
+    }
 }
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use crate::error::MailerError;
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Adds or updates the attributes of the recipient. An empty value removes
+    /// the attribute.
+    pub fn set_recipient_attributes(
+        &mut self,
+        by_recipient_id: i32,
+        attributes: &HashMap<String, String>,
+    ) -> Result<(), MailerError> {
+        use schema::recipient_attributes::dsl::*;
+
+        self.connection
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                for (attribute_name, attribute_value) in attributes {
+                    if attribute_value.is_empty() {
+                        diesel::delete(
+                            recipient_attributes
+                                .filter(recipient_id.eq(by_recipient_id))
+                                .filter(name.eq(attribute_name)),
+                        )
+                        .execute(conn)?;
+                    } else {
+                        diesel::replace_into(recipient_attributes)
+                            .values((
+                                recipient_id.eq(by_recipient_id),
+                                name.eq(attribute_name),
+                                value.eq(attribute_value),
+                            ))
+                            .execute(conn)?;
+                    }
+                }
+
+                Ok(())
+            })
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the attributes of the recipient, keyed by name.
+    pub fn find_recipient_attributes(
+        &mut self,
+        by_recipient_id: i32,
+    ) -> Result<HashMap<String, String>, MailerError> {
+        use schema::recipient_attributes::dsl::*;
+
+        recipient_attributes
+            .filter(recipient_id.eq(by_recipient_id))
+            .select((name, value))
+            .load::<(String, String)>(&mut self.connection)
+            .map(|attributes| attributes.into_iter().collect())
+            .map_err(MailerError::from)
+    }
+}
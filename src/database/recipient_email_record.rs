@@ -1,4 +1,4 @@
-use crate::error::MailerError;
+use crate::{error::MailerError, model::recipient::Recipient};
 use diesel::prelude::*;
 
 use super::{Database, schema};
@@ -21,4 +21,16 @@ impl Database {
 
         Ok(())
     }
+
+    pub fn find_recipients_by_email_record_id(
+        &mut self,
+        email_history_id: i32,
+    ) -> Result<Vec<Recipient>, MailerError> {
+        schema::email_history_recipients::table
+            .filter(schema::email_history_recipients::email_history_id.eq(email_history_id))
+            .inner_join(schema::recipients::table)
+            .select(Recipient::as_select())
+            .load::<Recipient>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
 }
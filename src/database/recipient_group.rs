@@ -1,6 +1,10 @@
 use crate::{
     error::MailerError,
-    model::{group::Group, recipient::Recipient, recipient_group::RecipientGroup},
+    model::{
+        group::Group,
+        recipient::{Recipient, RecipientStatus},
+        recipient_group::RecipientGroup,
+    },
 };
 use diesel::prelude::*;
 
@@ -29,6 +33,7 @@ impl Database {
         schema::group_recipients::table
             .filter(schema::group_recipients::group_id.eq(group_id))
             .inner_join(schema::recipients::table)
+            .filter(schema::recipients::status.eq(RecipientStatus::Active))
             .select(Recipient::as_select())
             .load::<Recipient>(&mut self.connection)
             .map_err(MailerError::from)
@@ -0,0 +1,100 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::MailerError,
+    model::recipient::{Recipient, RecipientStatus},
+    tag_expression::TagExpression,
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Adds the tags to the recipients. Returns the number of tags added, the
+    /// ones the recipients already had are skipped.
+    pub fn tag_recipients(
+        &mut self,
+        recipient_ids: &[i32],
+        tags: &[String],
+    ) -> Result<usize, MailerError> {
+        use schema::recipient_tags::dsl::*;
+
+        if recipient_ids.is_empty() || tags.is_empty() {
+            return Ok(0);
+        }
+        let rows = recipient_ids
+            .iter()
+            .flat_map(|id| tags.iter().map(move |t| (recipient_id.eq(*id), tag.eq(t))))
+            .collect::<Vec<_>>();
+
+        diesel::insert_or_ignore_into(recipient_tags)
+            .values(rows)
+            .execute(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Removes the tags from the recipients. Returns the number of tags removed.
+    pub fn untag_recipients(
+        &mut self,
+        recipient_ids: &[i32],
+        tags: &[String],
+    ) -> Result<usize, MailerError> {
+        use schema::recipient_tags::dsl::*;
+
+        diesel::delete(
+            recipient_tags
+                .filter(recipient_id.eq_any(recipient_ids))
+                .filter(tag.eq_any(tags)),
+        )
+        .execute(&mut self.connection)
+        .map_err(MailerError::from)
+    }
+
+    /// Returns every tag with its number of active recipients, by name.
+    pub fn list_tags(&mut self) -> Result<Vec<(String, i64)>, MailerError> {
+        use schema::recipient_tags::dsl::*;
+
+        recipient_tags
+            .inner_join(schema::recipients::table)
+            .filter(schema::recipients::status.eq(RecipientStatus::Active))
+            .group_by(tag)
+            .select((tag, diesel::dsl::count_star()))
+            .order(tag.asc())
+            .load::<(String, i64)>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the active recipients whose tags match the expression.
+    pub fn find_recipients_by_tag_expression(
+        &mut self,
+        expression: &TagExpression,
+    ) -> Result<Vec<Recipient>, MailerError> {
+        use schema::recipients::dsl::*;
+
+        // Every recipient is loaded, since those without tags can match a `NOT`
+        let active_recipients = recipients
+            .filter(status.eq(RecipientStatus::Active))
+            .order(id.asc())
+            .select(Recipient::as_select())
+            .load::<Recipient>(&mut self.connection)?;
+        let mut tags_by_recipient = HashMap::<i32, HashSet<String>>::new();
+        for (tagged_id, recipient_tag) in schema::recipient_tags::table
+            .select((
+                schema::recipient_tags::recipient_id,
+                schema::recipient_tags::tag,
+            ))
+            .load::<(i32, String)>(&mut self.connection)?
+        {
+            tags_by_recipient
+                .entry(tagged_id)
+                .or_default()
+                .insert(recipient_tag);
+        }
+
+        let no_tags = HashSet::new();
+        Ok(active_recipients
+            .into_iter()
+            .filter(|r| expression.matches(tags_by_recipient.get(&r.id).unwrap_or(&no_tags)))
+            .collect())
+    }
+}
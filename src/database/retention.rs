@@ -0,0 +1,135 @@
+use crate::{
+    config::{RetentionAction, RetentionRule},
+    error::MailerError,
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+/// How many records are purged per statement, to stay below the number of
+/// variables SQLite binds in one statement.
+pub(crate) const PURGE_CHUNK_SIZE: usize = 500;
+
+impl Database {
+    /// Applies the configured retention rules in order. Returns each rule with
+    /// the number of records it purged, or would purge with `dry_run`.
+    pub fn purge_history(
+        &mut self,
+        dry_run: bool,
+    ) -> Result<Vec<(RetentionRule, usize)>, MailerError> {
+        let rules = self.retention_rules.clone();
+        rules
+            .into_iter()
+            .map(|rule| {
+                let count = self.apply_retention_rule(&rule, dry_run)?;
+                Ok((rule, count))
+            })
+            .collect()
+    }
+
+    pub fn apply_retention_rule(
+        &mut self,
+        rule: &RetentionRule,
+        dry_run: bool,
+    ) -> Result<usize, MailerError> {
+        use schema::email_history::dsl::*;
+
+        let record_ids = self.find_expired_email_record_ids(rule)?;
+        if dry_run || record_ids.is_empty() {
+            return Ok(record_ids.len());
+        }
+
+        self.connection
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                let mut purged = 0;
+                for record_ids in record_ids.chunks(PURGE_CHUNK_SIZE) {
+                    purged += match rule.action {
+                        RetentionAction::DeleteBody => {
+                            diesel::delete(
+                                schema::raw_emails::table.filter(
+                                    schema::raw_emails::email_history_id.eq_any(record_ids),
+                                ),
+                            )
+                            .execute(conn)?;
+                            diesel::update(email_history.filter(id.eq_any(record_ids)))
+                                .set((
+                                    body.eq(""),
+                                    body_purged_at.eq(chrono::Utc::now().naive_utc()),
+                                ))
+                                .execute(conn)?
+                        }
+                        RetentionAction::DeleteRecord => {
+                            // Detach the replies and resends of the purged records
+                            diesel::update(email_history.filter(in_reply_to_id.eq_any(record_ids)))
+                                .set(in_reply_to_id.eq(None::<i32>))
+                                .execute(conn)?;
+                            diesel::update(email_history.filter(thread_id.eq_any(record_ids)))
+                                .set(thread_id.eq(None::<i32>))
+                                .execute(conn)?;
+                            diesel::update(email_history.filter(resent_from_id.eq_any(record_ids)))
+                                .set(resent_from_id.eq(None::<i32>))
+                                .execute(conn)?;
+                            diesel::delete(
+                                schema::email_history_recipients::table.filter(
+                                    schema::email_history_recipients::email_history_id
+                                        .eq_any(record_ids),
+                                ),
+                            )
+                            .execute(conn)?;
+                            diesel::delete(email_history.filter(id.eq_any(record_ids)))
+                                .execute(conn)?
+                        }
+                    };
+                }
+
+                Ok(purged)
+            })
+            .map_err(MailerError::from)
+    }
+
+    fn find_expired_email_record_ids(
+        &mut self,
+        rule: &RetentionRule,
+    ) -> Result<Vec<i32>, MailerError> {
+        use schema::email_history::dsl::*;
+
+        let cutoff =
+            chrono::Utc::now().naive_utc() - chrono::Duration::days(i64::from(rule.after_days));
+        let mut query = email_history
+            .select(id)
+            .filter(sent_at.lt(cutoff))
+            .into_boxed();
+
+        if rule.action == RetentionAction::DeleteBody {
+            query = query.filter(body_purged_at.is_null());
+        }
+
+        if let Some(by_sender) = &rule.sender {
+            query = query.filter(sender.eq(by_sender.clone()));
+        }
+
+        if let Some(group_name) = &rule.group {
+            // A rule of a group that doesn't exist matches no email
+            let Some(by_group_id) = schema::groups::table
+                .filter(schema::groups::name.eq(group_name))
+                .select(schema::groups::id)
+                .first::<i32>(&mut self.connection)
+                .optional()?
+            else {
+                return Ok(vec![]);
+            };
+
+            let member_ids = schema::group_recipients::table
+                .filter(schema::group_recipients::group_id.eq(by_group_id))
+                .select(schema::group_recipients::recipient_id);
+            let member_record_ids = schema::email_history_recipients::table
+                .filter(schema::email_history_recipients::recipient_id.eq_any(member_ids))
+                .select(schema::email_history_recipients::email_history_id);
+            query = query.filter(id.eq_any(member_record_ids));
+        }
+
+        query
+            .load::<i32>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+}
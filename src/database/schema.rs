@@ -4,6 +4,9 @@ diesel::table! {
         name -> Text,
         email -> Text,
         status -> Text,
+        pgp_public_key -> Nullable<Text>,
+        smime_certificate -> Nullable<Text>,
+        encryption_required -> Bool,
     }
 }
 
@@ -21,6 +24,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    recipient_attributes (recipient_id, name) {
+        recipient_id -> Integer,
+        name -> Text,
+        value -> Text,
+    }
+}
+
+diesel::table! {
+    recipient_tags (recipient_id, tag) {
+        recipient_id -> Integer,
+        tag -> Text,
+    }
+}
+
 diesel::table! {
     templates {
         id -> Integer,
@@ -35,6 +53,96 @@ diesel::table! {
         subject -> Text,
         body -> Text,
         sent_at -> Timestamp,
+        sandboxed -> Bool,
+        message_id -> Text,
+        sender -> Text,
+        headers -> Text,
+        in_reply_to_id -> Nullable<Integer>,
+        thread_id -> Nullable<Integer>,
+        reply_to -> Nullable<Text>,
+        transport -> Text,
+        smtp_code -> Nullable<Integer>,
+        smtp_message -> Nullable<Text>,
+        status -> Text,
+        resent_from_id -> Nullable<Integer>,
+        template_id -> Nullable<Integer>,
+        body_purged_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    raw_emails (email_history_id) {
+        email_history_id -> Integer,
+        message -> Binary,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_replies {
+        id -> Integer,
+        email_history_id -> Integer,
+        recipient_id -> Nullable<Integer>,
+        message_id -> Text,
+        sender -> Text,
+        subject -> Text,
+        received_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    inbound_sync_state (account, folder) {
+        account -> Text,
+        folder -> Text,
+        uid_validity -> BigInt,
+        last_uid -> BigInt,
+    }
+}
+
+diesel::table! {
+    inbound_messages {
+        id -> Integer,
+        message_id -> Nullable<Text>,
+        sender -> Text,
+        recipient_id -> Nullable<Integer>,
+        to_addresses -> Text,
+        subject -> Text,
+        body -> Text,
+        email_history_id -> Nullable<Integer>,
+        raw_message -> Binary,
+        received_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    bounces {
+        id -> Integer,
+        email_history_id -> Nullable<Integer>,
+        recipient_id -> Nullable<Integer>,
+        email -> Text,
+        kind -> Text,
+        status -> Text,
+        diagnostic -> Nullable<Text>,
+        notification_id -> Nullable<Text>,
+        received_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    unsubscribes {
+        id -> Integer,
+        email -> Text,
+        group_id -> Nullable<Integer>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    suppressions {
+        id -> Integer,
+        entry -> Text,
+        reason -> Nullable<Text>,
+        created_at -> Timestamp,
     }
 }
 
@@ -66,8 +174,19 @@ diesel::table! {
 
 diesel::joinable!(group_recipients -> groups (group_id));
 diesel::joinable!(group_recipients -> recipients (recipient_id));
+diesel::joinable!(recipient_attributes -> recipients (recipient_id));
+diesel::joinable!(recipient_tags -> recipients (recipient_id));
 diesel::joinable!(email_history_recipients -> recipients (recipient_id));
 diesel::joinable!(email_history_recipients -> email_history (email_history_id));
+diesel::joinable!(raw_emails -> email_history (email_history_id));
+diesel::joinable!(email_history -> templates (template_id));
+diesel::joinable!(email_replies -> email_history (email_history_id));
+diesel::joinable!(email_replies -> recipients (recipient_id));
+diesel::joinable!(inbound_messages -> email_history (email_history_id));
+diesel::joinable!(inbound_messages -> recipients (recipient_id));
+diesel::joinable!(bounces -> email_history (email_history_id));
+diesel::joinable!(bounces -> recipients (recipient_id));
+diesel::joinable!(unsubscribes -> groups (group_id));
 diesel::joinable!(event_attendees -> events (event_id));
 diesel::joinable!(event_attendees -> recipients (recipient_id));
 
@@ -75,8 +194,17 @@ diesel::allow_tables_to_appear_in_same_query!(
     recipients,
     groups,
     group_recipients,
+    recipient_attributes,
+    recipient_tags,
     email_history,
     email_history_recipients,
+    raw_emails,
+    email_replies,
+    inbound_sync_state,
+    inbound_messages,
+    bounces,
+    unsubscribes,
+    suppressions,
     templates,
     events,
     event_attendees,
@@ -89,7 +217,10 @@ pub(crate) fn create_all_tables_sqls() -> Vec<&'static str> {
                 id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
                 name TEXT NOT NULL,
                 email TEXT NOT NULL UNIQUE,
-                status TEXT NOT NULL CHECK (status IN ('Active', 'Inactive'))
+                status TEXT NOT NULL CHECK (status IN ('Active', 'Inactive', 'Bounced')),
+                pgp_public_key TEXT,
+                smime_certificate TEXT,
+                encryption_required BOOLEAN NOT NULL DEFAULT 0
             );",
         "CREATE TABLE IF NOT EXISTS groups (
                 id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
@@ -102,6 +233,19 @@ pub(crate) fn create_all_tables_sqls() -> Vec<&'static str> {
                 FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE,
                 FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE CASCADE
             );",
+        "CREATE TABLE IF NOT EXISTS recipient_attributes (
+                recipient_id INTEGER NOT NULL, 
+                name TEXT NOT NULL, 
+                value TEXT NOT NULL, 
+                PRIMARY KEY (recipient_id, name), 
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE CASCADE
+            );",
+        "CREATE TABLE IF NOT EXISTS recipient_tags (
+                recipient_id INTEGER NOT NULL, 
+                tag TEXT NOT NULL, 
+                PRIMARY KEY (recipient_id, tag), 
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE CASCADE
+            );",
         "CREATE TABLE IF NOT EXISTS templates (
                 id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
                 name TEXT NOT NULL UNIQUE, 
@@ -111,7 +255,91 @@ pub(crate) fn create_all_tables_sqls() -> Vec<&'static str> {
                 id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
                 subject TEXT NOT NULL, 
                 body TEXT NOT NULL, 
-                sent_at DATETIME DEFAULT CURRENT_TIMESTAMP
+                sent_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                sandboxed BOOLEAN NOT NULL DEFAULT 0,
+                message_id TEXT NOT NULL DEFAULT '',
+                sender TEXT NOT NULL DEFAULT '',
+                headers TEXT NOT NULL DEFAULT '',
+                in_reply_to_id INTEGER,
+                thread_id INTEGER,
+                reply_to TEXT,
+                transport TEXT NOT NULL DEFAULT '',
+                smtp_code INTEGER,
+                smtp_message TEXT,
+                status TEXT NOT NULL DEFAULT 'sent' CHECK (status IN ('sent', 'failed', 'queued')),
+                resent_from_id INTEGER,
+                template_id INTEGER,
+                body_purged_at DATETIME,
+                FOREIGN KEY (in_reply_to_id) REFERENCES email_history(id),
+                FOREIGN KEY (thread_id) REFERENCES email_history(id),
+                FOREIGN KEY (resent_from_id) REFERENCES email_history(id),
+                FOREIGN KEY (template_id) REFERENCES templates(id) ON DELETE SET NULL
+            );",
+        "CREATE TABLE IF NOT EXISTS raw_emails (
+                email_history_id INTEGER NOT NULL PRIMARY KEY, 
+                message BLOB NOT NULL, 
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (email_history_id) REFERENCES email_history(id) ON DELETE CASCADE
+            );",
+        "CREATE TABLE IF NOT EXISTS email_replies (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                email_history_id INTEGER NOT NULL, 
+                recipient_id INTEGER, 
+                message_id TEXT NOT NULL UNIQUE, 
+                sender TEXT NOT NULL, 
+                subject TEXT NOT NULL, 
+                received_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (email_history_id) REFERENCES email_history(id) ON DELETE CASCADE,
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE SET NULL
+            );",
+        "CREATE TABLE IF NOT EXISTS inbound_sync_state (
+                account TEXT NOT NULL, 
+                folder TEXT NOT NULL, 
+                uid_validity INTEGER NOT NULL, 
+                last_uid INTEGER NOT NULL, 
+                PRIMARY KEY (account, folder)
+            );",
+        "CREATE TABLE IF NOT EXISTS inbound_messages (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                message_id TEXT UNIQUE, 
+                sender TEXT NOT NULL, 
+                recipient_id INTEGER, 
+                to_addresses TEXT NOT NULL, 
+                subject TEXT NOT NULL, 
+                body TEXT NOT NULL, 
+                email_history_id INTEGER, 
+                raw_message BLOB NOT NULL, 
+                received_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE SET NULL,
+                FOREIGN KEY (email_history_id) REFERENCES email_history(id) ON DELETE SET NULL
+            );",
+        "CREATE TABLE IF NOT EXISTS bounces (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                email_history_id INTEGER, 
+                recipient_id INTEGER, 
+                email TEXT NOT NULL, 
+                kind TEXT NOT NULL CHECK (kind IN ('hard', 'soft')), 
+                status TEXT NOT NULL, 
+                diagnostic TEXT, 
+                notification_id TEXT, 
+                received_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (notification_id, email),
+                FOREIGN KEY (email_history_id) REFERENCES email_history(id) ON DELETE SET NULL,
+                FOREIGN KEY (recipient_id) REFERENCES recipients(id) ON DELETE SET NULL
+            );",
+        "CREATE TABLE IF NOT EXISTS unsubscribes (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                email TEXT NOT NULL, 
+                group_id INTEGER, 
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (email, group_id),
+                FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+            );",
+        "CREATE TABLE IF NOT EXISTS suppressions (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                entry TEXT NOT NULL UNIQUE, 
+                reason TEXT, 
+                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
             );",
         "CREATE TABLE IF NOT EXISTS email_history_recipients (
                 email_history_id INTEGER, 
@@ -137,3 +365,140 @@ pub(crate) fn create_all_tables_sqls() -> Vec<&'static str> {
             );",
     ]
 }
+
+/// A column added to a table after its first release.
+pub(crate) struct AddedColumn {
+    pub table: &'static str,
+    pub name: &'static str,
+    pub definition: &'static str,
+}
+
+/// The columns added since the baseline schema. `CREATE TABLE IF NOT EXISTS`
+/// leaves the tables of an existing database unchanged, so the missing ones
+/// are added to it.
+pub(crate) fn added_columns() -> Vec<AddedColumn> {
+    let column = |table, name, definition| AddedColumn {
+        table,
+        name,
+        definition,
+    };
+
+    vec![
+        column("recipients", "pgp_public_key", "TEXT"),
+        column("recipients", "smime_certificate", "TEXT"),
+        column(
+            "recipients",
+            "encryption_required",
+            "BOOLEAN NOT NULL DEFAULT 0",
+        ),
+        column("email_history", "sandboxed", "BOOLEAN NOT NULL DEFAULT 0"),
+        column("email_history", "message_id", "TEXT NOT NULL DEFAULT ''"),
+        column("email_history", "sender", "TEXT NOT NULL DEFAULT ''"),
+        column("email_history", "headers", "TEXT NOT NULL DEFAULT ''"),
+        column(
+            "email_history",
+            "in_reply_to_id",
+            "INTEGER REFERENCES email_history(id)",
+        ),
+        column(
+            "email_history",
+            "thread_id",
+            "INTEGER REFERENCES email_history(id)",
+        ),
+        column("email_history", "reply_to", "TEXT"),
+        column("email_history", "transport", "TEXT NOT NULL DEFAULT ''"),
+        column("email_history", "smtp_code", "INTEGER"),
+        column("email_history", "smtp_message", "TEXT"),
+        column(
+            "email_history",
+            "status",
+            "TEXT NOT NULL DEFAULT 'sent' CHECK (status IN ('sent', 'failed', 'queued'))",
+        ),
+        column(
+            "email_history",
+            "resent_from_id",
+            "INTEGER REFERENCES email_history(id)",
+        ),
+        column(
+            "email_history",
+            "template_id",
+            "INTEGER REFERENCES templates(id) ON DELETE SET NULL",
+        ),
+        column("email_history", "body_purged_at", "DATETIME"),
+    ]
+}
+
+/// Rebuilds the `recipients` table of a database whose status check predates
+/// the `Bounced` status. SQLite can't alter a check, so the table is copied
+/// into a new one, with the foreign keys off so its references are kept.
+/// The search index trigger reading the table is dropped, and created again
+/// along with the index.
+pub(crate) fn rebuild_recipients_sqls() -> Vec<&'static str> {
+    vec![
+        "PRAGMA foreign_keys = OFF;",
+        "BEGIN;",
+        "DROP TRIGGER IF EXISTS email_search_add_recipient;",
+        "CREATE TABLE recipients_rebuilt (
+                id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT, 
+                name TEXT NOT NULL,
+                email TEXT NOT NULL UNIQUE,
+                status TEXT NOT NULL CHECK (status IN ('Active', 'Inactive', 'Bounced')),
+                pgp_public_key TEXT,
+                smime_certificate TEXT,
+                encryption_required BOOLEAN NOT NULL DEFAULT 0
+            );",
+        "INSERT INTO recipients_rebuilt
+                (id, name, email, status, pgp_public_key, smime_certificate, encryption_required)
+            SELECT id, name, email, status, pgp_public_key, smime_certificate, encryption_required
+            FROM recipients;",
+        "DROP TABLE recipients;",
+        "ALTER TABLE recipients_rebuilt RENAME TO recipients;",
+        "COMMIT;",
+        "PRAGMA foreign_keys = ON;",
+    ]
+}
+
+/// Creates the full-text index over the email history. Run after the columns
+/// are added, since its triggers read them.
+pub(crate) fn create_search_index_sqls() -> Vec<&'static str> {
+    vec![
+        // Full-text index over the email history, kept in sync by triggers.
+        // The rowid of an entry is the id of its email record
+        "CREATE VIRTUAL TABLE IF NOT EXISTS email_search USING fts5(
+                subject, 
+                body, 
+                sender, 
+                recipients
+            );",
+        "CREATE TRIGGER IF NOT EXISTS email_search_insert AFTER INSERT ON email_history BEGIN
+                INSERT INTO email_search (rowid, subject, body, sender, recipients)
+                VALUES (new.id, new.subject, new.body, new.sender, '');
+            END;",
+        "CREATE TRIGGER IF NOT EXISTS email_search_update_body AFTER UPDATE OF body ON email_history BEGIN
+                UPDATE email_search SET body = new.body WHERE rowid = new.id;
+            END;",
+        "CREATE TRIGGER IF NOT EXISTS email_search_delete AFTER DELETE ON email_history BEGIN
+                DELETE FROM email_search WHERE rowid = old.id;
+            END;",
+        "CREATE TRIGGER IF NOT EXISTS email_search_add_recipient AFTER INSERT ON email_history_recipients BEGIN
+                UPDATE email_search
+                SET recipients = recipients || ' ' || (
+                    SELECT name || ' ' || email FROM recipients WHERE id = new.recipient_id
+                )
+                WHERE rowid = new.email_history_id;
+            END;",
+    ]
+}
+
+/// Indexes the records saved before the index existed. Run only when the
+/// index is created, since the triggers keep it in sync afterwards.
+pub(crate) fn backfill_search_index_sql() -> &'static str {
+    "INSERT INTO email_search (rowid, subject, body, sender, recipients)
+        SELECT h.id, h.subject, h.body, h.sender, COALESCE((
+            SELECT group_concat(r.name || ' ' || r.email, ' ')
+            FROM email_history_recipients hr
+            JOIN recipients r ON r.id = hr.recipient_id
+            WHERE hr.email_history_id = h.id
+        ), '')
+        FROM email_history h;"
+}
@@ -0,0 +1,49 @@
+use crate::{
+    error::MailerError,
+    model::suppression::{NewSuppression, Suppression},
+    pagination::Page,
+};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Suppresses an address or domain. Returns `false` if it already was.
+    pub fn add_suppression(
+        &mut self,
+        new_suppression: NewSuppression,
+    ) -> Result<bool, MailerError> {
+        use schema::suppressions::dsl::*;
+
+        diesel::insert_into(suppressions)
+            .values((
+                entry.eq(new_suppression.entry.to_lowercase()),
+                reason.eq(new_suppression.reason),
+                created_at.eq(diesel::dsl::now),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut self.connection)
+            .map(|inserted| inserted > 0)
+            .map_err(MailerError::from)
+    }
+
+    /// Lifts the suppression of an address or domain. Returns `false` if it
+    /// wasn't suppressed.
+    pub fn remove_suppression(&mut self, by_entry: &str) -> Result<bool, MailerError> {
+        use schema::suppressions::dsl::*;
+
+        diesel::delete(suppressions.filter(entry.eq(by_entry.to_lowercase())))
+            .execute(&mut self.connection)
+            .map(|deleted| deleted > 0)
+            .map_err(MailerError::from)
+    }
+
+    pub fn list_suppressions(&mut self, page: &Page) -> Result<Vec<Suppression>, MailerError> {
+        use schema::suppressions::dsl::*;
+
+        paginate!(suppressions.into_boxed(), id, page)
+            .select(Suppression::as_select())
+            .load::<Suppression>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+}
@@ -1,13 +1,13 @@
-use crate::{error::MailerError, model::template::Template};
+use crate::{error::MailerError, model::template::Template, pagination::Page};
 use diesel::prelude::*;
 
 use super::{Database, schema};
 
 impl Database {
-    pub fn list_templates(&mut self) -> Result<Vec<Template>, MailerError> {
+    pub fn list_templates(&mut self, page: &Page) -> Result<Vec<Template>, MailerError> {
         use schema::templates::dsl::*;
 
-        templates
+        paginate!(templates.into_boxed(), id, page)
             .load::<Template>(&mut self.connection)
             .map_err(MailerError::from)
     }
@@ -0,0 +1,76 @@
+use std::collections::HashSet;
+
+use crate::{error::MailerError, model::unsubscribe::Unsubscribe};
+use diesel::prelude::*;
+
+use super::{Database, schema};
+
+impl Database {
+    /// Opts the address out of the group, or of all groups if `by_group_id`
+    /// is `None`. Returns `false` if it already was.
+    pub fn add_unsubscribe(
+        &mut self,
+        email_str: &str,
+        by_group_id: Option<i32>,
+    ) -> Result<bool, MailerError> {
+        use schema::unsubscribes::dsl::*;
+
+        // NULLs are distinct in a UNIQUE constraint, so the global opt-outs are deduplicated here
+        let existing = unsubscribes
+            .filter(email.eq(email_str.to_lowercase()))
+            .filter(group_id.is(by_group_id))
+            .count()
+            .get_result::<i64>(&mut self.connection)?;
+        if existing > 0 {
+            return Ok(false);
+        }
+
+        diesel::insert_into(unsubscribes)
+            .values((
+                email.eq(email_str.to_lowercase()),
+                group_id.eq(by_group_id),
+                created_at.eq(diesel::dsl::now),
+            ))
+            .execute(&mut self.connection)
+            .map(|inserted| inserted > 0)
+            .map_err(MailerError::from)
+    }
+
+    pub fn list_unsubscribes(
+        &mut self,
+        by_group_id: Option<i32>,
+    ) -> Result<Vec<Unsubscribe>, MailerError> {
+        use schema::unsubscribes::dsl::*;
+
+        let mut query = unsubscribes.into_boxed();
+        if let Some(by_group_id) = by_group_id {
+            query = query.filter(group_id.eq(by_group_id));
+        }
+
+        query
+            .order(id.asc())
+            .select(Unsubscribe::as_select())
+            .load::<Unsubscribe>(&mut self.connection)
+            .map_err(MailerError::from)
+    }
+
+    /// Returns the lowercased addresses that opted out of the group or of all
+    /// groups. Only the latter if no group is given.
+    pub fn find_unsubscribed_emails(
+        &mut self,
+        by_group_id: Option<i32>,
+    ) -> Result<HashSet<String>, MailerError> {
+        use schema::unsubscribes::dsl::*;
+
+        let mut query = unsubscribes.filter(group_id.is_null()).into_boxed();
+        if let Some(by_group_id) = by_group_id {
+            query = query.or_filter(group_id.eq(by_group_id));
+        }
+
+        query
+            .select(email)
+            .load::<String>(&mut self.connection)
+            .map(|emails| emails.into_iter().collect())
+            .map_err(MailerError::from)
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use lettre::message::{
+    dkim::{
+        DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm,
+        DkimSigningKey,
+    },
+    header::HeaderName,
+};
+use rsa::{RsaPrivateKey, pkcs1::DecodeRsaPrivateKey, pkcs8::EncodePublicKey};
+
+use crate::{
+    config::{DkimAlgorithm, DkimDomainConfig},
+    error::{MailerError, new_rmcp_error},
+};
+
+/// Loads the signing keys of all configured domains. Returns the DKIM configs keyed by domain.
+pub fn load_dkim_configs(
+    configs: &[DkimDomainConfig],
+) -> Result<HashMap<String, DkimConfig>, MailerError> {
+    configs
+        .iter()
+        .map(|config| Ok((config.domain.to_lowercase(), build_dkim_config(config)?)))
+        .collect()
+}
+
+fn build_dkim_config(config: &DkimDomainConfig) -> Result<DkimConfig, MailerError> {
+    let private_key = std::fs::read_to_string(&config.private_key_path)?;
+    let algorithm = match config.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let signing_key = DkimSigningKey::new(private_key.trim(), algorithm).map_err(|e| {
+        new_rmcp_error(&format!(
+            "Invalid DKIM private key for {}: {}",
+            config.domain, e
+        ))
+    })?;
+
+    let headers = config
+        .signed_headers
+        .iter()
+        .map(|header| {
+            HeaderName::new_from_ascii(header.clone())
+                .map_err(|_| new_rmcp_error(&format!("Invalid DKIM signed header: {}", header)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(DkimConfig::new(
+        config.selector.clone(),
+        config.domain.clone(),
+        signing_key,
+        headers,
+        parse_canonicalization(&config.canonicalization)?,
+    ))
+}
+
+/// Parses a canonicalization in the `header/body` form of the DKIM `c=` tag,
+/// e.g. `relaxed/simple`.
+fn parse_canonicalization(canonicalization: &str) -> Result<DkimCanonicalization, MailerError> {
+    let parse_type = |value: &str| match value {
+        "simple" => Ok(DkimCanonicalizationType::Simple),
+        "relaxed" => Ok(DkimCanonicalizationType::Relaxed),
+        _ => Err(new_rmcp_error(&format!(
+            "Invalid DKIM canonicalization: {}",
+            canonicalization
+        ))),
+    };
+
+    let (header, body) = canonicalization
+        .split_once('/')
+        .unwrap_or((canonicalization, "simple"));
+
+    Ok(DkimCanonicalization {
+        header: parse_type(header)?,
+        body: parse_type(body)?,
+    })
+}
+
+/// The maximum length of a single character-string of a TXT record.
+const TXT_STRING_MAX_LEN: usize = 255;
+
+/// Returns the DNS TXT record to publish for the configured key, in zone file
+/// format. Values too long for a single string, like RSA-2048 keys, are split
+/// into several quoted strings that resolvers concatenate.
+pub fn dns_record(config: &DkimDomainConfig) -> Result<String, MailerError> {
+    let private_key = std::fs::read_to_string(&config.private_key_path)?;
+
+    let (key_type, public_key) = match config.algorithm {
+        DkimAlgorithm::Rsa => {
+            let public_key = RsaPrivateKey::from_pkcs1_pem(private_key.trim())
+                .map_err(|e| new_rmcp_error(&format!("Invalid DKIM RSA key: {}", e)))?
+                .to_public_key()
+                .to_public_key_der()
+                .map_err(|e| new_rmcp_error(&format!("Invalid DKIM RSA key: {}", e)))?;
+            ("rsa", public_key.as_bytes().to_vec())
+        }
+        DkimAlgorithm::Ed25519 => {
+            let secret_key: [u8; 32] = BASE64
+                .decode(private_key.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| new_rmcp_error("Invalid DKIM Ed25519 key"))?;
+            let signing_key = ed25519_dalek::SigningKey::from_bytes(&secret_key);
+            ("ed25519", signing_key.verifying_key().to_bytes().to_vec())
+        }
+    };
+
+    let value = format!("v=DKIM1; k={}; p={}", key_type, BASE64.encode(public_key));
+    // The value is ASCII, so any byte boundary is a char boundary
+    let strings = value
+        .as_bytes()
+        .chunks(TXT_STRING_MAX_LEN)
+        .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk)))
+        .collect::<Vec<_>>();
+
+    Ok(format!(
+        "{}._domainkey.{} IN TXT {}",
+        config.selector,
+        config.domain,
+        strings.join(" ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dkim_ed25519() {
+        const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_dkim.key");
+        std::fs::write(KEY_PATH, BASE64.encode([7u8; 32])).unwrap();
+
+        let config = DkimDomainConfig {
+            domain: "test.com".to_string(),
+            selector: "mail".to_string(),
+            private_key_path: KEY_PATH.to_string(),
+            algorithm: DkimAlgorithm::Ed25519,
+            signed_headers: vec!["From".to_string(), "Subject".to_string()],
+            canonicalization: "relaxed/relaxed".to_string(),
+        };
+
+        let configs = load_dkim_configs(std::slice::from_ref(&config)).unwrap();
+        assert!(configs.contains_key("test.com"));
+
+        let record = dns_record(&config).unwrap();
+        assert!(record.starts_with("mail._domainkey.test.com IN TXT \"v=DKIM1; k=ed25519; p="));
+
+        std::fs::remove_file(KEY_PATH).unwrap();
+    }
+
+    #[test]
+    fn test_dkim_rsa_2048() {
+        const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_dkim_rsa.key");
+        let private_key = openssl::rsa::Rsa::generate(2048)
+            .unwrap()
+            .private_key_to_pem()
+            .unwrap();
+        std::fs::write(KEY_PATH, private_key).unwrap();
+
+        let config = DkimDomainConfig {
+            domain: "test.com".to_string(),
+            selector: "mail".to_string(),
+            private_key_path: KEY_PATH.to_string(),
+            algorithm: DkimAlgorithm::Rsa,
+            signed_headers: vec!["From".to_string(), "Subject".to_string()],
+            canonicalization: "relaxed/relaxed".to_string(),
+        };
+
+        let configs = load_dkim_configs(std::slice::from_ref(&config)).unwrap();
+        assert!(configs.contains_key("test.com"));
+
+        // The value is split into quoted strings of at most 255 characters
+        let record = dns_record(&config).unwrap();
+        let strings = record
+            .strip_prefix("mail._domainkey.test.com IN TXT \"")
+            .and_then(|strings| strings.strip_suffix('"'))
+            .unwrap()
+            .split("\" \"")
+            .collect::<Vec<_>>();
+        assert!(strings.len() > 1);
+        assert!(strings.iter().all(|s| s.len() <= TXT_STRING_MAX_LEN));
+
+        let value = strings.concat();
+        let public_key = value.strip_prefix("v=DKIM1; k=rsa; p=").unwrap();
+        let public_key =
+            openssl::pkey::PKey::public_key_from_der(&BASE64.decode(public_key).unwrap()).unwrap();
+        assert_eq!(public_key.bits(), 2048);
+
+        std::fs::remove_file(KEY_PATH).unwrap();
+    }
+
+    #[test]
+    fn test_parse_canonicalization() {
+        let canonicalization = parse_canonicalization("relaxed/simple").unwrap();
+        assert_eq!(canonicalization.to_string(), "relaxed/simple");
+
+        let canonicalization = parse_canonicalization("relaxed").unwrap();
+        assert_eq!(canonicalization.to_string(), "relaxed/simple");
+
+        assert!(parse_canonicalization("strict/simple").is_err());
+    }
+}
@@ -27,6 +27,30 @@ impl From<lettre::transport::smtp::Error> for MailerError {
     }
 }
 
+impl From<std::io::Error> for MailerError {
+    fn from(error: std::io::Error) -> Self {
+        MailerError {
+            message: format!("IO error: {}", error),
+        }
+    }
+}
+
+impl From<imap::error::Error> for MailerError {
+    fn from(error: imap::error::Error) -> Self {
+        MailerError {
+            message: format!("IMAP error: {}", error),
+        }
+    }
+}
+
+impl From<openssl::error::ErrorStack> for MailerError {
+    fn from(error: openssl::error::ErrorStack) -> Self {
+        MailerError {
+            message: format!("OpenSSL error: {}", error),
+        }
+    }
+}
+
 impl From<diesel::result::Error> for MailerError {
     fn from(error: diesel::result::Error) -> Self {
         MailerError {
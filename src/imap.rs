@@ -0,0 +1,673 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use chrono::NaiveDate;
+use imap::Session;
+use mail_parser::{Address, MessageParser, MimeHeaders};
+
+use crate::{
+    config::{ImapAccount, MailSender, SMTPCredentials},
+    error::{MailerError, new_rmcp_error},
+};
+
+/// The folder used when the caller doesn't name one.
+pub const DEFAULT_FOLDER: &str = "INBOX";
+
+trait ImapStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> ImapStream for T {}
+
+/// Reads the mailbox of a sender over IMAP. Each call opens its own
+/// connection, so the methods block and should run on a blocking thread.
+#[derive(Debug, Clone)]
+pub struct ImapClient {
+    /// The email address of the sender the account belongs to.
+    pub email: String,
+    account: ImapAccount,
+    credentials: SMTPCredentials,
+}
+
+/// A folder of the mailbox.
+#[derive(Debug, Clone)]
+pub struct Folder {
+    pub name: String,
+    pub delimiter: Option<String>,
+    pub attributes: Vec<String>,
+}
+
+/// Search criteria of the messages of a folder. All set criteria must match.
+#[derive(Debug, Clone, Default)]
+pub struct MessageQuery {
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    /// Only the messages received on or after this date.
+    pub since: Option<NaiveDate>,
+    /// Only the messages received before this date.
+    pub before: Option<NaiveDate>,
+    pub unread_only: bool,
+}
+
+/// The headers of a message listed by a search.
+#[derive(Debug, Clone)]
+pub struct MessageSummary {
+    pub uid: u32,
+    pub from: String,
+    pub subject: String,
+    pub date: Option<String>,
+    pub flags: Vec<String>,
+}
+
+/// A fetched message, parsed.
+#[derive(Debug, Clone)]
+pub struct ImapMessage {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    /// All headers of the message, as received.
+    pub headers: String,
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub date: Option<String>,
+    pub text_body: Option<String>,
+    pub html_body: Option<String>,
+    pub attachments: Vec<AttachmentInfo>,
+}
+
+/// The headers of a received message linking it to the messages it replies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundHeaders {
+    pub uid: u32,
+    /// The `Message-ID` header, including the angle brackets.
+    pub message_id: Option<String>,
+    /// The address of the sender, without the display name.
+    pub from: Option<String>,
+    pub subject: String,
+    /// The `Message-ID`s of the `In-Reply-To` header.
+    pub in_reply_to: Vec<String>,
+    /// The `Message-ID`s of the `References` header, oldest first.
+    pub references: Vec<String>,
+}
+
+/// The messages of a folder received since the last sync.
+#[derive(Debug, Clone)]
+pub struct NewMessages {
+    /// The current `UIDVALIDITY` of the folder.
+    pub uid_validity: u32,
+    /// The UID and the raw content of each message, by UID.
+    pub messages: Vec<(u32, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AttachmentInfo {
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+}
+
+impl MessageQuery {
+    /// Returns the criteria as an IMAP `SEARCH` query.
+    fn to_imap(&self) -> String {
+        let mut criteria = Vec::new();
+        if let Some(from) = &self.from {
+            criteria.push(format!("FROM {}", quote(from)));
+        }
+        if let Some(subject) = &self.subject {
+            criteria.push(format!("SUBJECT {}", quote(subject)));
+        }
+        if let Some(since) = self.since {
+            criteria.push(format!("SINCE {}", since.format("%-d-%b-%Y")));
+        }
+        if let Some(before) = self.before {
+            criteria.push(format!("BEFORE {}", before.format("%-d-%b-%Y")));
+        }
+        if self.unread_only {
+            criteria.push("UNSEEN".to_string());
+        }
+
+        if criteria.is_empty() {
+            "ALL".to_string()
+        } else {
+            criteria.join(" ")
+        }
+    }
+}
+
+impl ImapClient {
+    /// Returns the IMAP client of the sender, if it has an IMAP account with credentials.
+    pub fn for_sender(sender: &MailSender) -> Option<Self> {
+        let account = sender.imap.clone()?;
+        let credentials = account
+            .credentials
+            .clone()
+            .or_else(|| sender.credentials.clone())?;
+
+        Some(Self {
+            email: sender.email.clone(),
+            account,
+            credentials,
+        })
+    }
+
+    pub fn list_folders(&self) -> Result<Vec<Folder>, MailerError> {
+        self.with_session(|session| {
+            let names = session.list(Some(""), Some("*"))?;
+            Ok(names
+                .iter()
+                .map(|name| Folder {
+                    name: name.name().to_string(),
+                    delimiter: name.delimiter().map(str::to_string),
+                    attributes: name
+                        .attributes()
+                        .iter()
+                        .map(|attribute| format!("{attribute:?}"))
+                        .collect(),
+                })
+                .collect())
+        })
+    }
+
+    /// Returns the headers of the messages of the folder matching the query,
+    /// newest first. Doesn't mark the messages as read.
+    pub fn search(
+        &self,
+        folder: &str,
+        query: &MessageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageSummary>, MailerError> {
+        self.with_session(|session| {
+            session.examine(folder)?;
+
+            let mut uids = session
+                .uid_search(query.to_imap())?
+                .into_iter()
+                .collect::<Vec<_>>();
+            uids.sort_unstable_by(|a, b| b.cmp(a));
+            uids.truncate(limit);
+            if uids.is_empty() {
+                return Ok(vec![]);
+            }
+
+            let fetches = session.uid_fetch(uid_set(&uids), "(UID FLAGS BODY.PEEK[HEADER])")?;
+            let mut summaries = fetches
+                .iter()
+                .filter_map(|fetch| {
+                    let message = MessageParser::default().parse(fetch.header()?)?;
+                    Some(MessageSummary {
+                        uid: fetch.uid?,
+                        from: format_address(message.from()),
+                        subject: message.subject().unwrap_or_default().to_string(),
+                        date: message.date().map(|date| date.to_rfc3339()),
+                        flags: fetch.flags().iter().map(|flag| flag.to_string()).collect(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            summaries.sort_unstable_by_key(|summary| std::cmp::Reverse(summary.uid));
+            Ok(summaries)
+        })
+    }
+
+    /// Fetches and parses a message. Doesn't mark the message as read.
+    pub fn fetch(&self, folder: &str, uid: u32) -> Result<ImapMessage, MailerError> {
+        let message = self.with_session(|session| {
+            session.examine(folder)?;
+
+            let fetches = session.uid_fetch(uid.to_string(), "(UID FLAGS BODY.PEEK[])")?;
+            Ok(fetches
+                .iter()
+                .find(|fetch| fetch.uid == Some(uid))
+                .map(|fetch| {
+                    (
+                        fetch
+                            .flags()
+                            .iter()
+                            .map(|flag| flag.to_string())
+                            .collect::<Vec<_>>(),
+                        fetch.body().unwrap_or_default().to_vec(),
+                    )
+                }))
+        })?;
+        let (flags, raw) = message.ok_or_else(|| new_rmcp_error("Message not found"))?;
+
+        parse_message(uid, flags, &raw)
+    }
+
+    /// Returns the headers of the messages received in the folder after
+    /// `last_uid`, with the current UIDVALIDITY of the folder. All messages are
+    /// returned if the UIDVALIDITY differs from `uid_validity`.
+    pub fn fetch_new_headers(
+        &self,
+        folder: &str,
+        uid_validity: Option<u32>,
+        last_uid: u32,
+    ) -> Result<(u32, Vec<InboundHeaders>), MailerError> {
+        let NewMessages {
+            uid_validity,
+            messages,
+        } = self.fetch_new(folder, uid_validity, last_uid, false)?;
+
+        Ok((
+            uid_validity,
+            messages
+                .into_iter()
+                .filter_map(|(uid, header)| parse_inbound_headers(uid, &header))
+                .collect(),
+        ))
+    }
+
+    /// Like `fetch_new_headers`, but returns the whole raw messages.
+    pub fn fetch_new_messages(
+        &self,
+        folder: &str,
+        uid_validity: Option<u32>,
+        last_uid: u32,
+    ) -> Result<NewMessages, MailerError> {
+        self.fetch_new(folder, uid_validity, last_uid, true)
+    }
+
+    /// Returns the header section, or the whole message if `full`, of the
+    /// messages received after `last_uid`, by UID.
+    fn fetch_new(
+        &self,
+        folder: &str,
+        uid_validity: Option<u32>,
+        last_uid: u32,
+        full: bool,
+    ) -> Result<NewMessages, MailerError> {
+        self.with_session(|session| {
+            let mailbox = session.examine(folder)?;
+            let current_uid_validity = mailbox.uid_validity.unwrap_or_default();
+            let last_uid = if uid_validity == Some(current_uid_validity) {
+                last_uid
+            } else {
+                0
+            };
+            if mailbox.exists == 0 {
+                return Ok(NewMessages {
+                    uid_validity: current_uid_validity,
+                    messages: vec![],
+                });
+            }
+
+            // `n:*` always matches the last message, even if its UID is lower than n
+            let query = if full {
+                "(UID BODY.PEEK[])"
+            } else {
+                "(UID BODY.PEEK[HEADER])"
+            };
+            let fetches = session.uid_fetch(format!("{}:*", last_uid.saturating_add(1)), query)?;
+            let mut messages = fetches
+                .iter()
+                .filter_map(|fetch| {
+                    let content = if full { fetch.body() } else { fetch.header() };
+                    Some((fetch.uid?, content?.to_vec()))
+                })
+                .filter(|(uid, _)| *uid > last_uid)
+                .collect::<Vec<_>>();
+            messages.sort_unstable_by_key(|(uid, _)| *uid);
+            Ok(NewMessages {
+                uid_validity: current_uid_validity,
+                messages,
+            })
+        })
+    }
+
+    /// Adds or removes the `\Seen` and `\Flagged` flags of the messages.
+    pub fn set_flags(
+        &self,
+        folder: &str,
+        uids: &[u32],
+        read: Option<bool>,
+        flagged: Option<bool>,
+    ) -> Result<(), MailerError> {
+        self.with_session(|session| {
+            session.select(folder)?;
+
+            for (flag, set) in [("\\Seen", read), ("\\Flagged", flagged)] {
+                if let Some(set) = set {
+                    let operation = if set {
+                        "+FLAGS.SILENT"
+                    } else {
+                        "-FLAGS.SILENT"
+                    };
+                    session.uid_store(uid_set(uids), format!("{operation} ({flag})"))?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn with_session<R>(
+        &self,
+        f: impl FnOnce(&mut Session<Box<dyn ImapStream>>) -> imap::error::Result<R>,
+    ) -> Result<R, MailerError> {
+        let tcp_stream = TcpStream::connect((self.account.host.as_str(), self.account.port))?;
+        let stream: Box<dyn ImapStream> = if self.account.tls {
+            let connector = native_tls::TlsConnector::new()
+                .map_err(|e| new_rmcp_error(&format!("Failed to set up TLS: {}", e)))?;
+            Box::new(
+                connector
+                    .connect(&self.account.host, tcp_stream)
+                    .map_err(|e| new_rmcp_error(&format!("IMAP TLS handshake failed: {}", e)))?,
+            )
+        } else {
+            Box::new(tcp_stream)
+        };
+
+        let mut client = imap::Client::new(stream);
+        client.read_greeting()?;
+        let mut session = client
+            .login(&self.credentials.username, &self.credentials.password)
+            .map_err(|(e, _)| e)?;
+
+        let result = f(&mut session);
+        _ = session.logout();
+        Ok(result?)
+    }
+}
+
+fn parse_message(uid: u32, flags: Vec<String>, raw: &[u8]) -> Result<ImapMessage, MailerError> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| new_rmcp_error("Failed to parse the message"))?;
+
+    // The headers end at the first empty line
+    let header_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(raw.len());
+
+    Ok(ImapMessage {
+        uid,
+        flags,
+        headers: String::from_utf8_lossy(&raw[..header_end]).to_string(),
+        from: format_address(message.from()),
+        to: format_address(message.to()),
+        subject: message.subject().unwrap_or_default().to_string(),
+        date: message.date().map(|date| date.to_rfc3339()),
+        text_body: message.body_text(0).map(|body| body.to_string()),
+        html_body: message.body_html(0).map(|body| body.to_string()),
+        attachments: message
+            .attachments()
+            .map(|attachment| AttachmentInfo {
+                name: attachment
+                    .attachment_name()
+                    .unwrap_or("unnamed")
+                    .to_string(),
+                content_type: attachment
+                    .content_type()
+                    .map(|content_type| match content_type.subtype() {
+                        Some(subtype) => format!("{}/{}", content_type.ctype(), subtype),
+                        None => content_type.ctype().to_string(),
+                    })
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                size: attachment.len(),
+            })
+            .collect(),
+    })
+}
+
+fn parse_inbound_headers(uid: u32, header: &[u8]) -> Option<InboundHeaders> {
+    let message = MessageParser::default().parse(header)?;
+    let raw_headers = String::from_utf8_lossy(header);
+
+    Some(InboundHeaders {
+        uid,
+        message_id: message.message_id().map(|id| format!("<{id}>")),
+        from: message
+            .from()
+            .and_then(|from| from.first())
+            .and_then(|from| from.address())
+            .map(str::to_lowercase),
+        subject: message.subject().unwrap_or_default().to_string(),
+        in_reply_to: message_ids(&raw_headers, "In-Reply-To"),
+        references: message_ids(&raw_headers, "References"),
+    })
+}
+
+/// Returns the `<...>` message ids of the header `name`, unfolding its continuation lines.
+pub(crate) fn message_ids(raw_headers: &str, name: &str) -> Vec<String> {
+    let mut value = String::new();
+    let mut in_header = false;
+    for line in raw_headers.lines() {
+        if line.starts_with([' ', '\t']) {
+            if in_header {
+                value.push_str(line);
+            }
+            continue;
+        }
+
+        in_header = line
+            .split_once(':')
+            .is_some_and(|(header, _)| header.trim().eq_ignore_ascii_case(name));
+        if in_header {
+            value.push_str(line.split_once(':').map_or("", |(_, v)| v));
+        }
+    }
+
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|id| id.split_once('>'))
+        .map(|(id, _)| format!("<{}>", id.trim()))
+        .collect()
+}
+
+pub(crate) fn format_address(address: Option<&Address>) -> String {
+    address
+        .and_then(|address| address.as_list())
+        .unwrap_or_default()
+        .iter()
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(email)) => format!("{name} <{email}>"),
+            (None, Some(email)) => email.to_string(),
+            (Some(name), None) => name.to_string(),
+            (None, None) => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn uid_set(uids: &[u32]) -> String {
+    uids.iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Quotes a string for an IMAP command.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: Alice <alice@domain.com>\r\n\
+        To: test@test.com\r\n\
+        Subject: Re: Hello\r\n\
+        Date: Mon, 1 Jan 2024 10:00:00 +0000\r\n\
+        Message-ID: <reply@domain.com>\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        Thanks!\r\n\
+        --b\r\n\
+        Content-Type: application/pdf\r\n\
+        Content-Disposition: attachment; filename=\"invoice.pdf\"\r\n\
+        \r\n\
+        PDF\r\n\
+        --b--\r\n";
+
+    /// A local IMAP stand-in answering with a single message of uid 7.
+    /// Returns its port and the commands it received.
+    fn spawn_imap_server() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let commands = Arc::new(Mutex::new(Vec::new()));
+
+        let received = commands.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                writer
+                    .write_all(b"* OK IMAP4rev1 stand-in ready\r\n")
+                    .unwrap();
+
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let (tag, command) = line.trim_end().split_once(' ').unwrap();
+                    received.lock().unwrap().push(command.to_string());
+
+                    let mut reply = Vec::new();
+                    if command.starts_with("LIST") {
+                        reply.extend(b"* LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n");
+                        reply.extend(b"* LIST (\\HasNoChildren) \"/\" \"Sent\"\r\n");
+                    } else if command.starts_with("EXAMINE") || command.starts_with("SELECT") {
+                        reply
+                            .extend(b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n");
+                        reply.extend(b"* 1 EXISTS\r\n");
+                        reply.extend(b"* 0 RECENT\r\n");
+                        reply.extend(b"* OK [UIDVALIDITY 1] UIDs valid\r\n");
+                        reply.extend(b"* OK [UIDNEXT 8] Predicted next UID\r\n");
+                    } else if command.starts_with("UID SEARCH") {
+                        reply.extend(b"* SEARCH 7\r\n");
+                    } else if command.starts_with("UID FETCH") {
+                        let header_end = MESSAGE.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+                        let (section, data) = if command.contains("HEADER") {
+                            ("HEADER", &MESSAGE[..header_end + 4])
+                        } else {
+                            ("", MESSAGE)
+                        };
+                        reply.extend(
+                            format!(
+                                "* 1 FETCH (UID 7 FLAGS (\\Seen) BODY[{section}] {{{}}}\r\n",
+                                data.len()
+                            )
+                            .as_bytes(),
+                        );
+                        reply.extend(data);
+                        reply.extend(b")\r\n");
+                    } else if command.starts_with("LOGOUT") {
+                        reply.extend(b"* BYE\r\n");
+                    }
+                    reply.extend(format!("{tag} OK done\r\n").as_bytes());
+                    writer.write_all(&reply).unwrap();
+                    line.clear();
+                }
+            }
+        });
+
+        (port, commands)
+    }
+
+    fn client(port: u16) -> ImapClient {
+        ImapClient::for_sender(&MailSender {
+            email: "test@test.com".to_string(),
+            credentials: Some(SMTPCredentials {
+                username: "user".to_string(),
+                password: "password".to_string(),
+            }),
+            imap: Some(ImapAccount {
+                host: "127.0.0.1".to_string(),
+                port,
+                tls: false,
+                credentials: None,
+                sync_interval_minutes: None,
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_search_query() {
+        assert_eq!(MessageQuery::default().to_imap(), "ALL");
+        let query = MessageQuery {
+            from: Some("alice@domain.com".to_string()),
+            subject: Some("say \"hi\"".to_string()),
+            since: NaiveDate::from_ymd_opt(2024, 1, 5),
+            before: None,
+            unread_only: true,
+        };
+        assert_eq!(
+            query.to_imap(),
+            "FROM \"alice@domain.com\" SUBJECT \"say \\\"hi\\\"\" SINCE 5-Jan-2024 UNSEEN"
+        );
+    }
+
+    #[test]
+    fn test_parse_inbound_headers() {
+        let header = b"From: Bob <Bob@Domain.com>\r\n\
+            Subject: Re: Hello\r\n\
+            Message-ID: <reply@domain.com>\r\n\
+            In-Reply-To: <sent-2@test.com>\r\n\
+            References: <sent-1@test.com>\r\n\
+            \t<sent-2@test.com>\r\n\
+            \r\n";
+
+        assert_eq!(
+            parse_inbound_headers(3, header),
+            Some(InboundHeaders {
+                uid: 3,
+                message_id: Some("<reply@domain.com>".to_string()),
+                from: Some("bob@domain.com".to_string()),
+                subject: "Re: Hello".to_string(),
+                in_reply_to: vec!["<sent-2@test.com>".to_string()],
+                references: vec![
+                    "<sent-1@test.com>".to_string(),
+                    "<sent-2@test.com>".to_string()
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_imap_client() {
+        let (port, commands) = spawn_imap_server();
+        let client = client(port);
+
+        let folders = client.list_folders().unwrap();
+        assert_eq!(
+            folders.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+            vec!["INBOX", "Sent"]
+        );
+
+        let summaries = client
+            .search(DEFAULT_FOLDER, &MessageQuery::default(), 10)
+            .unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].uid, 7);
+        assert_eq!(summaries[0].from, "Alice <alice@domain.com>");
+        assert_eq!(summaries[0].subject, "Re: Hello");
+        assert_eq!(summaries[0].flags, vec!["\\Seen"]);
+
+        let message = client.fetch(DEFAULT_FOLDER, 7).unwrap();
+        assert_eq!(message.to, "test@test.com");
+        assert!(message.headers.contains("Message-ID: <reply@domain.com>"));
+        assert_eq!(message.text_body.as_deref().map(str::trim), Some("Thanks!"));
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].name, "invoice.pdf");
+        assert_eq!(message.attachments[0].content_type, "application/pdf");
+
+        client
+            .set_flags(DEFAULT_FOLDER, &[7], Some(true), Some(false))
+            .unwrap();
+        let commands = commands.lock().unwrap();
+        assert!(commands.contains(&"UID STORE 7 +FLAGS.SILENT (\\Seen)".to_string()));
+        assert!(commands.contains(&"UID STORE 7 -FLAGS.SILENT (\\Flagged)".to_string()));
+        assert!(commands.iter().all(|c| !c.starts_with("FETCH")));
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, State},
+    http::{HeaderMap, StatusCode, header::AUTHORIZATION},
+    routing::post,
+};
+use log::{error, info};
+use mail_parser::MessageParser;
+use openssl::memcmp;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{DatabaseConfig, InboundConfig},
+    database::Database,
+    error::{MailerError, new_rmcp_error},
+    imap::{DEFAULT_FOLDER, ImapClient, format_address, message_ids},
+    model::{
+        email_record::EmailRecord,
+        email_reply::{InboundSyncState, NewEmailReply},
+        inbound_message::{InboundMessage, NewInboundMessage},
+        raw_email::RawEmail,
+    },
+};
+
+struct WebhookState {
+    token: String,
+    db: Mutex<Database>,
+}
+
+/// Fetches the messages received in the inbox of the account since the last
+/// sync, and records the ones replying to a sent email. Returns the number of
+/// replies recorded. Blocks on the IMAP connection.
+pub fn sync_replies(db: &mut Database, client: &ImapClient) -> Result<usize, MailerError> {
+    let state = db.find_inbound_sync_state(&client.email, DEFAULT_FOLDER)?;
+    let (uid_validity, messages) = client.fetch_new_headers(
+        DEFAULT_FOLDER,
+        state.map(|state| state.uid_validity),
+        state.map_or(0, |state| state.last_uid),
+    )?;
+
+    let Some(last_uid) = messages.iter().map(|message| message.uid).max() else {
+        return Ok(0);
+    };
+
+    let mut recorded = 0;
+    for message in messages {
+        let Some(message_id) = message.message_id else {
+            continue;
+        };
+
+        let Some(original) = find_replied_record(db, &message.in_reply_to, &message.references)?
+        else {
+            continue;
+        };
+
+        let recipient_id = message
+            .from
+            .as_ref()
+            .and_then(|from| find_recipient_id(db, from));
+
+        if db.add_email_reply(NewEmailReply {
+            email_history_id: original.id,
+            recipient_id,
+            message_id,
+            sender: message.from.unwrap_or_default(),
+            subject: message.subject,
+        })? {
+            recorded += 1;
+        }
+    }
+
+    db.set_inbound_sync_state(
+        &client.email,
+        DEFAULT_FOLDER,
+        InboundSyncState {
+            uid_validity,
+            last_uid,
+        },
+    )?;
+    if recorded > 0 {
+        info!("Recorded {recorded} replies received by {}", client.email);
+    }
+
+    Ok(recorded)
+}
+
+/// Returns the router of the inbound webhook. It keeps a database connection
+/// of its own.
+pub fn webhook_router(config: &InboundConfig, db_config: &DatabaseConfig) -> Router {
+    let state = Arc::new(WebhookState {
+        token: config.token.clone(),
+        db: Mutex::new(Database::new(db_config.clone())),
+    });
+
+    Router::new()
+        .route(&config.path, post(receive_webhook))
+        .layer(DefaultBodyLimit::max(config.max_message_bytes))
+        .with_state(state)
+}
+
+async fn receive_webhook(
+    State(state): State<Arc<WebhookState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, String) {
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| {
+            // Compared in constant time, so that the token can't be guessed from the timing
+            token.len() == state.token.len() && memcmp::eq(token.as_bytes(), state.token.as_bytes())
+        });
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "Invalid token".to_string());
+    }
+    if body.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Empty message".to_string());
+    }
+
+    let mut db = state.db.lock().await;
+    match receive_message(&mut db, &body) {
+        Ok(Some(message)) => (
+            StatusCode::CREATED,
+            format!("Stored inbound message {}", message.id),
+        ),
+        Ok(None) => (StatusCode::OK, "Message already stored".to_string()),
+        Err(e) => {
+            error!("Failed to store an inbound message: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to store the message".to_string(),
+            )
+        }
+    }
+}
+
+/// Stores a raw MIME message, linked to the recipient who sent it and to the
+/// sent email it replies to. Replies are recorded as such too. Returns `None`
+/// if the message was already stored.
+pub fn receive_message(
+    db: &mut Database,
+    raw: &[u8],
+) -> Result<Option<InboundMessage>, MailerError> {
+    let message = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| new_rmcp_error("Failed to parse the message"))?;
+    let header_end = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(raw.len());
+    let raw_headers = String::from_utf8_lossy(&raw[..header_end]);
+
+    let message_id = message.message_id().map(|id| format!("<{id}>"));
+    let sender = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.address())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+    let subject = message.subject().unwrap_or_default().to_string();
+    let original = find_replied_record(
+        db,
+        &message_ids(&raw_headers, "In-Reply-To"),
+        &message_ids(&raw_headers, "References"),
+    )?;
+    let recipient_id = find_recipient_id(db, &sender);
+
+    let Some(stored) = db.add_inbound_message(NewInboundMessage {
+        message_id: message_id.clone(),
+        sender: sender.clone(),
+        recipient_id,
+        to_addresses: format_address(message.to()),
+        subject: subject.clone(),
+        body: message
+            .body_text(0)
+            .or_else(|| message.body_html(0))
+            .unwrap_or_default()
+            .to_string(),
+        email_history_id: original.as_ref().map(|original| original.id),
+        raw_message: RawEmail::compress(raw)?,
+    })?
+    else {
+        return Ok(None);
+    };
+
+    if let (Some(original), Some(message_id)) = (original, message_id) {
+        db.add_email_reply(NewEmailReply {
+            email_history_id: original.id,
+            recipient_id,
+            message_id,
+            sender,
+            subject,
+        })?;
+    }
+
+    Ok(Some(stored))
+}
+
+/// Returns the sent email replied to. In-Reply-To names the direct parent,
+/// References the thread oldest first.
+fn find_replied_record(
+    db: &mut Database,
+    in_reply_to: &[String],
+    references: &[String],
+) -> Result<Option<EmailRecord>, MailerError> {
+    for referenced_id in in_reply_to.iter().chain(references.iter().rev()) {
+        if let Some(original) = db.find_email_record_by_message_id(referenced_id)? {
+            return Ok(Some(original));
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_recipient_id(db: &mut Database, email: &str) -> Option<i32> {
+    db.find_recipient_by_email(email.to_string())
+        .ok()
+        .map(|recipient| recipient.id)
+}
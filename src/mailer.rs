@@ -1,49 +1,394 @@
+use std::{collections::HashMap, sync::Arc};
+
 use crate::{
+    bounce,
     config::{MailSender, MailerConfig},
+    dkim,
     error::{MailerError, new_rmcp_error},
+    model::{recipient::Recipient, suppression::Suppression},
+    pgp,
+    rate_limit::{RateLimitStatus, RateLimiter},
     request::SendEmailRequest,
+    smime,
+    transport::{self, Delivery, MailTransport},
 };
 use lettre::{
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor, message::header::ContentType,
-    transport::smtp::authentication::Credentials,
+    Address, Message,
+    address::Envelope,
+    message::{
+        Mailbox, SinglePart,
+        dkim::DkimConfig,
+        header::{ContentType, HeaderName, HeaderValue},
+    },
 };
+use mail_parser::{MessageParser, MimeHeaders};
+
+/// Header keeping the original recipients of a message redirected by the sandbox.
+const SANDBOX_ORIGINAL_TO: HeaderName = HeaderName::new_from_ascii_str("X-Sandbox-Original-To");
+const LIST_UNSUBSCRIBE: HeaderName = HeaderName::new_from_ascii_str("List-Unsubscribe");
+const LIST_UNSUBSCRIBE_POST: HeaderName = HeaderName::new_from_ascii_str("List-Unsubscribe-Post");
 
 #[derive(Debug, Clone)]
 pub struct Mailer {
     config: MailerConfig,
+    /// The delivery backend, shared between clones.
+    transport: Arc<dyn MailTransport>,
+    /// DKIM signing configs keyed by sender domain.
+    dkim: Arc<HashMap<String, DkimConfig>>,
+    /// The outbound rate limits, shared between clones.
+    rate_limiter: Arc<RateLimiter>,
+}
+
+/// The keys a message is encrypted with, borrowed from the recipients.
+enum Encryption<'a> {
+    /// ASCII armored OpenPGP public keys.
+    Pgp(Vec<&'a str>),
+    /// PEM encoded S/MIME certificates.
+    Smime(Vec<&'a str>),
+}
+
+/// A message built, filtered and signed exactly as `send` hands it over to
+/// the delivery backend.
+#[derive(Debug, Clone)]
+pub struct PreparedEmail {
+    pub message: Message,
+    /// The formatted RFC 5322 message.
+    pub raw: Vec<u8>,
+    pub envelope: Envelope,
+    /// The requested recipients that aren't suppressed. In sandbox mode these
+    /// differ from `envelope`.
+    pub recipients: Vec<Address>,
+    /// The requested recipients left out because they are suppressed.
+    pub suppressed: Vec<String>,
+    /// The `Message-ID` header of the message.
+    pub message_id: String,
+}
+
+impl PreparedEmail {
+    /// Returns the decoded plain text body, or `None` if the message is encrypted.
+    pub fn text_body(&self) -> Option<String> {
+        if Mailer::is_encrypted(&self.raw) {
+            return None;
+        }
+
+        MessageParser::default()
+            .parse(&self.raw)?
+            .body_text(0)
+            .map(|body| body.to_string())
+    }
+}
+
+/// A message handed over to the delivery backend.
+#[derive(Debug, Clone)]
+pub struct SentEmail {
+    /// The formatted RFC 5322 message, exactly as it was handed over.
+    pub raw: Vec<u8>,
+    /// The header section of `raw`.
+    pub headers: String,
+    pub envelope: Envelope,
+    /// The recipients requested by the caller. In sandbox mode these differ
+    /// from `envelope`.
+    pub recipients: Vec<Address>,
+    /// The requested recipients left out because they are suppressed.
+    pub suppressed: Vec<String>,
+    /// Whether the message was redirected by the sandbox.
+    pub sandboxed: bool,
+    /// The `Message-ID` header of the message.
+    pub message_id: String,
+    /// The email address of the sender.
+    pub sender: String,
+    pub reply_to: Option<String>,
+    /// The name of the transport the message was handed to.
+    pub transport: String,
+    /// The outcome of the delivery, which may have failed.
+    pub delivery: Delivery,
 }
 
 impl Mailer {
-    pub fn new(config: MailerConfig) -> Self {
-        Self { config }
+    /// Fails if the delivery backend can't be created or a DKIM key can't be loaded.
+    pub fn new(config: MailerConfig) -> Result<Self, MailerError> {
+        let transport = transport::from_config(&config)?;
+        Self::with_transport(config, transport)
     }
 
-    pub async fn send(&self, email_request: &SendEmailRequest) -> Result<Message, MailerError> {
-        let sender = email_request
-            .from
-            .as_ref()
-            .and_then(|from| self.config.find_sender(from))
-            .unwrap_or(self.config.default_sender());
+    pub fn with_transport(
+        config: MailerConfig,
+        transport: Arc<dyn MailTransport>,
+    ) -> Result<Self, MailerError> {
+        let dkim = dkim::load_dkim_configs(&config.dkim)?;
+
+        Ok(Self {
+            rate_limiter: Arc::new(RateLimiter::new(&config)),
+            config,
+            transport,
+            dkim: Arc::new(dkim),
+        })
+    }
 
-        let email = self.build_email(email_request, sender)?;
-        let transport = self.build_transport(sender)?;
+    /// Sends the email. `known_recipients` are the phone book entries of the
+    /// recipients, used to look up their PGP keys. The recipients matching
+    /// `suppressions` are left out of the message.
+    ///
+    /// Waits for the rate limits of the sender if they are reached.
+    ///
+    /// Only fails if the message can't be built, or if every recipient is
+    /// suppressed. A failed delivery is returned in `SentEmail::delivery`, so
+    /// that the attempt can be recorded.
+    pub async fn send(
+        &self,
+        email_request: &SendEmailRequest,
+        known_recipients: &[Recipient],
+        suppressions: &[Suppression],
+    ) -> Result<SentEmail, MailerError> {
+        let sender = self.resolve_sender(email_request.from.as_deref());
+        let prepared = self.prepare(email_request, sender, known_recipients, suppressions)?;
 
         // Send the email
-        transport
-            .send(email.clone())
-            .await
-            .map(|_| ())
-            .map_err(MailerError::from)?;
+        self.rate_limiter.acquire(&sender.email).await;
+        let delivery = self
+            .transport
+            .send(sender, &prepared.envelope, &prepared.raw)
+            .await;
+
+        Ok(SentEmail {
+            headers: prepared.message.headers().to_string(),
+            raw: prepared.raw,
+            envelope: prepared.envelope,
+            message_id: prepared.message_id,
+            recipients: prepared.recipients,
+            suppressed: prepared.suppressed,
+            sandboxed: self.config.sandbox.is_some(),
+            sender: sender.email.clone(),
+            reply_to: email_request.reply_to.clone(),
+            transport: self.transport.name().to_string(),
+            delivery,
+        })
+    }
+
+    /// Builds the message exactly as `send` would, without sending it.
+    pub fn preview(
+        &self,
+        email_request: &SendEmailRequest,
+        known_recipients: &[Recipient],
+        suppressions: &[Suppression],
+    ) -> Result<PreparedEmail, MailerError> {
+        let sender = self.resolve_sender(email_request.from.as_deref());
+
+        self.prepare(email_request, sender, known_recipients, suppressions)
+    }
+
+    /// Leaves out the suppressed recipients, then builds and signs the message
+    /// and its envelope.
+    fn prepare(
+        &self,
+        email_request: &SendEmailRequest,
+        sender: &MailSender,
+        known_recipients: &[Recipient],
+        suppressions: &[Suppression],
+    ) -> Result<PreparedEmail, MailerError> {
+        let (to, suppressed) = Self::filter_suppressed(&email_request.to, suppressions)?;
+        let email_request = &SendEmailRequest {
+            to,
+            ..email_request.clone()
+        };
+        let recipients = Self::parse_recipients(&email_request.to)?;
+        let mut message = self.build_email(email_request, sender, known_recipients)?;
+        self.sign_dkim(&mut message, sender);
+
+        let message_id = message
+            .headers()
+            .get_raw("Message-ID")
+            .unwrap_or_default()
+            .to_string();
+        let envelope = Envelope::new(
+            Some(self.envelope_sender(sender, &message_id)?),
+            message.envelope().to().to_vec(),
+        )?;
+
+        Ok(PreparedEmail {
+            raw: message.formatted(),
+            message,
+            envelope,
+            recipients: recipients.into_iter().map(|r| r.email).collect(),
+            suppressed,
+            message_id,
+        })
+    }
+
+    /// Re-delivers a previously sent raw message to the recipients. The message
+    /// is unchanged but for the RFC 5322 `Resent-*` headers prepended to it, so
+    /// that the resend has a Message-ID of its own and the DKIM signature of the
+    /// original stays valid. The sandbox still redirects it, but can't mark the
+    /// message itself. Suppressed recipients are left out, as with `send`.
+    ///
+    /// The message can't be encrypted again, so an encrypted message is only
+    /// resent to its `original_recipients`, and a plain one to recipients who
+    /// don't require encryption. `known_recipients` are the phone book entries
+    /// of the recipients, as with `send`.
+    pub async fn resend(
+        &self,
+        raw: Vec<u8>,
+        from: &str,
+        to: &[String],
+        original_recipients: &[String],
+        known_recipients: &[Recipient],
+        suppressions: &[Suppression],
+    ) -> Result<SentEmail, MailerError> {
+        let sender = self.resolve_sender(Some(from));
+
+        let (to, suppressed) = Self::filter_suppressed(to, suppressions)?;
+        let recipients = Self::parse_recipients(&to)?
+            .into_iter()
+            .map(|r| r.email)
+            .collect::<Vec<_>>();
+        if Self::is_encrypted(&raw) {
+            let original_recipients = Self::parse_recipients(original_recipients)?
+                .into_iter()
+                .map(|r| r.email.to_string().to_lowercase())
+                .collect::<Vec<_>>();
+            let new_recipients = recipients
+                .iter()
+                .map(|recipient| recipient.to_string())
+                .filter(|recipient| !original_recipients.contains(&recipient.to_lowercase()))
+                .collect::<Vec<_>>();
+            if !new_recipients.is_empty() {
+                return Err(new_rmcp_error(&format!(
+                    "Refusing to resend an encrypted email to {}: it can only be decrypted by its original recipients",
+                    new_recipients.join(", ")
+                )));
+            }
+        } else {
+            let known_recipients = recipients
+                .iter()
+                .map(|recipient| {
+                    known_recipients
+                        .iter()
+                        .find(|r| r.email.eq_ignore_ascii_case(recipient.as_ref()))
+                })
+                .collect::<Vec<_>>();
+            Self::check_encryption_required(&known_recipients)?;
+        }
+        let envelope_to = match self
+            .config
+            .sandbox
+            .as_ref()
+            .and_then(|sandbox| sandbox.redirect_to.as_ref())
+        {
+            Some(redirect_to) => vec![
+                redirect_to
+                    .parse()
+                    .map_err(|_| new_rmcp_error("Invalid sandbox redirect_to email"))?,
+            ],
+            None => recipients.clone(),
+        };
+
+        // RFC 5322 section 3.6.6: resent fields are prepended, newest first
+        let sender_address = sender
+            .email
+            .parse::<Address>()
+            .map_err(|_| new_rmcp_error("Invalid sender email"))?;
+        let message_id = format!(
+            "<{:032x}@{}>",
+            rand::random::<u128>(),
+            sender_address.domain()
+        );
+        let resent_headers = format!(
+            "Resent-Date: {}\r\nResent-From: {}\r\nResent-Message-ID: {}\r\n",
+            chrono::Utc::now().to_rfc2822(),
+            sender_address,
+            message_id
+        );
+        let raw = [resent_headers.as_bytes(), &raw].concat();
+
+        // The header section ends at the first empty line
+        let headers = String::from_utf8_lossy(&raw)
+            .split_once("\r\n\r\n")
+            .map(|(headers, _)| format!("{headers}\r\n"))
+            .unwrap_or_default();
+
+        let envelope = Envelope::new(
+            Some(self.envelope_sender(sender, &message_id)?),
+            envelope_to,
+        )?;
+        self.rate_limiter.acquire(&sender.email).await;
+        let delivery = self.transport.send(sender, &envelope, &raw).await;
+
+        Ok(SentEmail {
+            raw,
+            headers,
+            envelope,
+            recipients,
+            suppressed,
+            sandboxed: self.config.sandbox.is_some(),
+            message_id,
+            sender: sender.email.clone(),
+            reply_to: None,
+            transport: self.transport.name().to_string(),
+            delivery,
+        })
+    }
+
+    /// Returns the remaining quota of the rate limits.
+    pub fn rate_limit_status(&self) -> Vec<RateLimitStatus> {
+        self.rate_limiter.status()
+    }
+
+    /// Returns the DNS TXT records to publish for the DKIM keys, optionally
+    /// only the one of the given domain.
+    pub fn dkim_dns_records(&self, domain: Option<&str>) -> Result<Vec<String>, MailerError> {
+        self.config
+            .dkim
+            .iter()
+            .filter(|config| domain.is_none_or(|domain| config.domain.eq_ignore_ascii_case(domain)))
+            .map(dkim::dns_record)
+            .collect()
+    }
+
+    /// Checks that the delivery backend of the sender is reachable and accepts
+    /// its credentials, without sending any mail.
+    pub async fn test_connection(&self, from: Option<&str>) -> Result<bool, MailerError> {
+        let sender = self.resolve_sender(from);
+
+        self.transport.test_connection(sender).await
+    }
+
+    /// Returns the envelope sender of a message: its VERP return path if
+    /// bounces are configured with one, the sender itself otherwise.
+    fn envelope_sender(
+        &self,
+        sender: &MailSender,
+        message_id: &str,
+    ) -> Result<Address, MailerError> {
+        let return_path = self
+            .config
+            .bounces
+            .as_ref()
+            .and_then(|bounces| bounces.return_path.as_deref());
+        if let Some(verp_address) =
+            return_path.and_then(|return_path| bounce::verp_address(return_path, message_id))
+        {
+            return Ok(verp_address);
+        }
+
+        sender
+            .email
+            .parse()
+            .map_err(|_| new_rmcp_error("Invalid sender email"))
+    }
 
-        Ok(email)
+    /// Returns the configured sender matching `from`, or the default sender.
+    fn resolve_sender(&self, from: Option<&str>) -> &MailSender {
+        from.and_then(|from| self.config.find_sender(from))
+            .unwrap_or(self.config.default_sender())
     }
 
-    fn build_email(
+    pub fn build_email(
         &self,
         email_request: &SendEmailRequest,
-        from: &MailSender,
+        sender: &MailSender,
+        known_recipients: &[Recipient],
     ) -> Result<Message, MailerError> {
-        let from = from
+        let from = sender
             .email
             .parse::<lettre::message::Mailbox>()
             .map_err(|_| new_rmcp_error("Invalid sender email"))?;
@@ -51,47 +396,648 @@ impl Mailer {
         let mut msg_builder = Message::builder()
             .from(from)
             .subject(&email_request.subject)
-            .header(ContentType::TEXT_PLAIN);
+            .message_id(None);
+
+        let recipients = Self::parse_recipients(&email_request.to)?;
+        // A message redirected by the sandbox only reaches the catch-all address,
+        // which can't decrypt a message encrypted to the original recipients
+        let redirected = self
+            .config
+            .sandbox
+            .as_ref()
+            .is_some_and(|sandbox| sandbox.redirect_to.is_some());
+        let encryption = if redirected {
+            None
+        } else {
+            Self::find_encryption_keys(&recipients, known_recipients)?
+        };
+        let mut body = email_request.body.clone();
 
-        for recipient in &email_request.to {
-            msg_builder = msg_builder.to(recipient
-                .parse()
-                .map_err(|_| new_rmcp_error("Invalid recipient email"))?);
+        match &self.config.sandbox {
+            Some(sandbox) => {
+                // Keep the original recipients visible, but deliver to the catch-all
+                // address if there is one
+                let original_to = recipients
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                msg_builder = msg_builder
+                    .raw_header(HeaderValue::new(SANDBOX_ORIGINAL_TO, original_to.clone()));
+                body = format!("[SANDBOX] Originally addressed to: {original_to}\n\n{body}");
+
+                match &sandbox.redirect_to {
+                    Some(redirect_to) => {
+                        msg_builder = msg_builder.to(redirect_to
+                            .parse()
+                            .map_err(|_| new_rmcp_error("Invalid sandbox redirect_to email"))?);
+                    }
+                    None => {
+                        for recipient in recipients {
+                            msg_builder = msg_builder.to(recipient);
+                        }
+                    }
+                }
+            }
+            None => {
+                for recipient in recipients {
+                    msg_builder = msg_builder.to(recipient);
+                }
+            }
         }
 
         if let Some(reply_to) = &email_request.reply_to {
-            msg_builder = msg_builder.reply_to(reply_to.parse().unwrap());
+            msg_builder = msg_builder.reply_to(
+                reply_to
+                    .parse()
+                    .map_err(|_| new_rmcp_error("Invalid reply_to address"))?,
+            );
         }
 
-        msg_builder
-            .body(email_request.body.clone())
-            .map_err(MailerError::from)
+        if let Some(thread) = &email_request.thread {
+            msg_builder = msg_builder
+                .in_reply_to(thread.in_reply_to.clone())
+                .references(thread.references.join(" "));
+        }
+
+        // RFC 8058 one-click unsubscribe
+        if let Some(unsubscribe_url) = &email_request.unsubscribe_url {
+            msg_builder = msg_builder
+                .raw_header(HeaderValue::new(
+                    LIST_UNSUBSCRIBE,
+                    format!("<{unsubscribe_url}>"),
+                ))
+                .raw_header(HeaderValue::new(
+                    LIST_UNSUBSCRIBE_POST,
+                    "List-Unsubscribe=One-Click".to_string(),
+                ));
+        }
+
+        let entity = SinglePart::plain(body.clone());
+        match encryption {
+            Some(Encryption::Pgp(public_keys)) => {
+                let encrypted =
+                    pgp::encrypt(&entity, &public_keys, sender.pgp_signing_key.as_ref())?;
+                msg_builder.multipart(encrypted)
+            }
+            Some(Encryption::Smime(certificates)) => {
+                let enveloped = smime::encrypt(&entity, &certificates, sender.smime.as_ref())?;
+                msg_builder.singlepart(enveloped)
+            }
+            None => match (&sender.pgp_signing_key, &sender.smime) {
+                (Some(signing_key), _) if signing_key.sign_unencrypted => {
+                    msg_builder.multipart(pgp::sign(&entity, signing_key)?)
+                }
+                (_, Some(smime_config)) if smime_config.sign_unencrypted => {
+                    msg_builder.singlepart(smime::sign(&entity, smime_config)?)
+                }
+                _ => msg_builder.header(ContentType::TEXT_PLAIN).body(body),
+            },
+        }
+        .map_err(MailerError::from)
     }
 
-    fn build_transport(
-        &self,
-        sender: &MailSender,
-    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, MailerError> {
-        let credentials = sender
-            .credentials
-            .as_ref()
-            .map(|creds| Credentials::new(creds.username.clone(), creds.password.clone()));
+    /// Returns the keys to encrypt the message with, if every recipient has a
+    /// PGP key or every recipient has an S/MIME certificate. PGP is preferred.
+    /// Fails if a recipient requires encryption but the message can't be encrypted.
+    fn find_encryption_keys<'a>(
+        recipients: &[Mailbox],
+        known_recipients: &'a [Recipient],
+    ) -> Result<Option<Encryption<'a>>, MailerError> {
+        let recipients = recipients
+            .iter()
+            .map(|recipient| {
+                known_recipients
+                    .iter()
+                    .find(|r| r.email.eq_ignore_ascii_case(recipient.email.as_ref()))
+            })
+            .collect::<Vec<_>>();
+
+        if !recipients.is_empty() {
+            let public_keys = recipients
+                .iter()
+                .map(|r| r.and_then(|r| r.pgp_public_key.as_deref()))
+                .collect::<Option<Vec<_>>>();
+            if let Some(public_keys) = public_keys {
+                return Ok(Some(Encryption::Pgp(public_keys)));
+            }
+
+            let certificates = recipients
+                .iter()
+                .map(|r| r.and_then(|r| r.smime_certificate.as_deref()))
+                .collect::<Option<Vec<_>>>();
+            if let Some(certificates) = certificates {
+                return Ok(Some(Encryption::Smime(certificates)));
+            }
+        }
+
+        Self::check_encryption_required(&recipients)?;
+
+        Ok(None)
+    }
+
+    /// Fails if one of the recipients requires encryption, for an email that
+    /// can't be encrypted.
+    fn check_encryption_required(recipients: &[Option<&Recipient>]) -> Result<(), MailerError> {
+        let encryption_required = recipients
+            .iter()
+            .flatten()
+            .filter(|r| r.encryption_required)
+            .map(|r| r.email.as_str())
+            .collect::<Vec<_>>();
+        if !encryption_required.is_empty() {
+            return Err(new_rmcp_error(&format!(
+                "Refusing to send an unencrypted email: {} require(s) encryption, but not every recipient has a PGP key or S/MIME certificate",
+                encryption_required.join(", ")
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the raw message is PGP/MIME or S/MIME encrypted.
+    fn is_encrypted(raw: &[u8]) -> bool {
+        let Some(message) = MessageParser::default().parse_headers(raw) else {
+            return false;
+        };
+
+        message.content_type().is_some_and(|content_type| {
+            match (content_type.ctype(), content_type.subtype()) {
+                ("multipart", Some("encrypted")) => true,
+                ("application", Some("pkcs7-mime" | "x-pkcs7-mime")) => {
+                    content_type.attribute("smime-type") == Some("enveloped-data")
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Signs the message with the DKIM key of the sender's domain, if one is configured.
+    fn sign_dkim(&self, email: &mut Message, sender: &MailSender) {
+        let Ok(sender_email) = sender.email.parse::<Address>() else {
+            return;
+        };
+
+        if let Some(dkim_config) = self.dkim.get(&sender_email.domain().to_lowercase()) {
+            email.sign(dkim_config);
+        }
+    }
+
+    /// Splits the recipients into the allowed and the suppressed ones. Fails if
+    /// every recipient is suppressed, since there is nobody left to send to.
+    pub(crate) fn filter_suppressed(
+        to: &[String],
+        suppressions: &[Suppression],
+    ) -> Result<(Vec<String>, Vec<String>), MailerError> {
+        let recipients = Self::parse_recipients(to)?;
+        let (allowed, suppressed): (Vec<_>, Vec<_>) = to
+            .iter()
+            .zip(recipients)
+            .partition(|(_, recipient)| !suppressions.iter().any(|s| s.matches(&recipient.email)));
+        let allowed = allowed
+            .into_iter()
+            .map(|(to, _)| to.clone())
+            .collect::<Vec<_>>();
+        let suppressed = suppressed
+            .into_iter()
+            .map(|(to, _)| to.clone())
+            .collect::<Vec<_>>();
+
+        if allowed.is_empty() && !suppressed.is_empty() {
+            return Err(new_rmcp_error(&format!(
+                "Refusing to send the email: every recipient is suppressed ({})",
+                suppressed.join(", ")
+            )));
+        }
+
+        Ok((allowed, suppressed))
+    }
 
-        if let Some(credentials) = credentials {
-            let mailer = AsyncSmtpTransport::<Tokio1Executor>::relay(&self.config.smtp_host)
-                .unwrap()
-                .port(self.config.smtp_port)
-                .credentials(credentials)
-                .build();
+    fn parse_recipients(to: &[String]) -> Result<Vec<Mailbox>, MailerError> {
+        to.iter()
+            .map(|recipient| {
+                recipient
+                    .parse::<Mailbox>()
+                    .map_err(|_| new_rmcp_error("Invalid recipient email"))
+            })
+            .collect()
+    }
+}
 
-            return Ok(mailer);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{BounceConfig, SandboxConfig},
+        model::{email_record::EmailStatus, recipient::RecipientStatus},
+        transport::StubTransport,
+    };
+
+    /// A plain email request to the recipients.
+    fn request(to: &[&str]) -> SendEmailRequest {
+        SendEmailRequest {
+            from: None,
+            to: to.iter().map(|to| to.to_string()).collect(),
+            reply_to: None,
+            subject: "Test Subject".to_string(),
+            body: "Test Body".to_string(),
+            thread: None,
+            unsubscribe_url: None,
         }
+    }
+
+    #[tokio::test]
+    async fn test_send_with_stub_transport() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+
+        let sent_message = mailer
+            .send(&request(&["bob@domain.com"]), &[], &[])
+            .await
+            .expect("Failed to send email");
+        assert_eq!(sent_message.envelope.to().len(), 1);
+        assert!(!sent_message.sandboxed);
+        assert!(sent_message.message_id.starts_with('<'));
+        assert_eq!(sent_message.sender, "test@test.com");
+        assert_eq!(sent_message.transport, "stub");
+        assert_eq!(sent_message.delivery.status, EmailStatus::Sent);
+
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Subject: Test Subject"));
+        assert!(messages[0].contains("To: bob@domain.com"));
+        assert!(messages[0].contains("Test Body"));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_unsubscribe_url() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+
+        let sent_message = mailer
+            .send(
+                &SendEmailRequest {
+                    unsubscribe_url: Some(
+                        "https://mailer.test.com/unsubscribe?token=abc&sig=def".to_string(),
+                    ),
+                    ..request(&["bob@domain.com"])
+                },
+                &[],
+                &[],
+            )
+            .await
+            .expect("Failed to send email");
+        assert!(
+            sent_message.headers.contains(
+                "List-Unsubscribe: <https://mailer.test.com/unsubscribe?token=abc&sig=def>"
+            )
+        );
+        assert!(
+            sent_message
+                .headers
+                .contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_with_verp_return_path() {
+        let stub = Arc::new(StubTransport::new());
+        let config = MailerConfig {
+            bounces: Some(BounceConfig {
+                mailbox: "test@test.com".to_string(),
+                folder: "Bounces".to_string(),
+                return_path: Some("bounces@test.com".to_string()),
+                soft_bounce_limit: 3,
+                sync_interval_minutes: 10,
+            }),
+            ..Default::default()
+        };
+        let mailer = Mailer::with_transport(config, stub.clone()).unwrap();
+
+        let sent_message = mailer
+            .send(&request(&["bob@domain.com"]), &[], &[])
+            .await
+            .expect("Failed to send email");
+        let envelope_from = sent_message.envelope.from().unwrap().to_string();
+        let token = bounce::verp_token("bounces@test.com", &envelope_from).unwrap();
+        assert!(sent_message.message_id.starts_with(&format!("<{token}@")));
+
+        let resent_message = mailer
+            .resend(
+                sent_message.raw.clone(),
+                &sent_message.sender,
+                &["carol@domain.com".to_string()],
+                &["bob@domain.com".to_string()],
+                &[],
+                &[],
+            )
+            .await
+            .expect("Failed to resend email");
+        // The resend bounces to the return path of its own Message-ID
+        let envelope_from = resent_message.envelope.from().unwrap().to_string();
+        let token = bounce::verp_token("bounces@test.com", &envelope_from).unwrap();
+        assert!(resent_message.message_id.starts_with(&format!("<{token}@")));
+        assert_ne!(resent_message.envelope.from(), sent_message.envelope.from());
+    }
+
+    #[tokio::test]
+    async fn test_resend() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+
+        let sent_message = mailer
+            .send(&request(&["bob@domain.com"]), &[], &[])
+            .await
+            .expect("Failed to send email");
+
+        let resent_message = mailer
+            .resend(
+                sent_message.raw.clone(),
+                &sent_message.sender,
+                &["carol@domain.com".to_string()],
+                &["bob@domain.com".to_string()],
+                &[],
+                &[],
+            )
+            .await
+            .expect("Failed to resend email");
+        assert!(resent_message.raw.ends_with(&sent_message.raw));
+        assert_ne!(resent_message.message_id, sent_message.message_id);
+        assert!(resent_message.headers.starts_with("Resent-Date: "));
+        assert!(resent_message.headers.contains(&format!(
+            "Resent-Message-ID: {}\r\n",
+            resent_message.message_id
+        )));
+        assert!(resent_message.headers.ends_with(&sent_message.headers));
+        assert_eq!(
+            resent_message.envelope.to(),
+            ["carol@domain.com".parse::<Address>().unwrap()]
+        );
+
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].ends_with(&messages[0]));
+    }
+
+    #[tokio::test]
+    async fn test_resend_refuses_unreadable_email() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+        let encrypted = b"Message-ID: <1@test.com>\r\n\
+            Content-Type: multipart/encrypted; protocol=\"application/pgp-encrypted\"; boundary=\"b\"\r\n\
+            \r\n\
+            --b--\r\n"
+            .to_vec();
+        let original_to = ["bob@domain.com".to_string()];
+
+        let resend = async |raw: &[u8], to: &str, known_recipients: &[Recipient]| {
+            mailer
+                .resend(
+                    raw.to_vec(),
+                    "test@test.com",
+                    &[to.to_string()],
+                    &original_to,
+                    known_recipients,
+                    &[],
+                )
+                .await
+        };
+
+        // An encrypted email can only be resent to the recipients it was encrypted to
+        assert!(resend(&encrypted, "carol@domain.com", &[]).await.is_err());
+        assert!(resend(&encrypted, "Bob@domain.com", &[]).await.is_ok());
+
+        // A plain email isn't resent to a recipient requiring encryption
+        let plain = b"Message-ID: <2@test.com>\r\n\r\nTest Body\r\n".to_vec();
+        let known_recipients = [Recipient {
+            id: 1,
+            name: "carol".to_string(),
+            email: "carol@domain.com".to_string(),
+            status: RecipientStatus::Active,
+            pgp_public_key: None,
+            smime_certificate: None,
+            encryption_required: true,
+        }];
+        assert!(
+            resend(&plain, "carol@domain.com", &known_recipients)
+                .await
+                .is_err()
+        );
+
+        assert_eq!(stub.messages().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_in_sandbox() {
+        let stub = Arc::new(StubTransport::new());
+        let config = MailerConfig {
+            sandbox: Some(SandboxConfig {
+                redirect_to: Some("catch-all@test.com".to_string()),
+                dir: None,
+            }),
+            ..Default::default()
+        };
+        let mailer = Mailer::with_transport(config, stub.clone()).unwrap();
+
+        let sent_message = mailer
+            .send(&request(&["bob@domain.com"]), &[], &[])
+            .await
+            .expect("Failed to send email");
+        assert!(sent_message.sandboxed);
+        assert_eq!(
+            sent_message.recipients,
+            vec!["bob@domain.com".parse::<Address>().unwrap()]
+        );
+        assert_eq!(
+            sent_message.envelope.to(),
+            ["catch-all@test.com".parse::<Address>().unwrap()]
+        );
+
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("To: catch-all@test.com"));
+        assert!(messages[0].contains("X-Sandbox-Original-To: bob@domain.com"));
+        assert!(messages[0].contains("Originally addressed to: bob@domain.com"));
+    }
 
-        let mailer =
-            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.config.smtp_host)
-                .port(self.config.smtp_port)
-                .build();
+    #[tokio::test]
+    async fn test_send_in_sandbox_is_not_encrypted() {
+        let stub = Arc::new(StubTransport::new());
+        let config = MailerConfig {
+            sandbox: Some(SandboxConfig {
+                redirect_to: Some("catch-all@test.com".to_string()),
+                dir: None,
+            }),
+            ..Default::default()
+        };
+        let mailer = Mailer::with_transport(config, stub.clone()).unwrap();
+        // The key is never parsed, since the catch-all couldn't decrypt the message
+        let known_recipients = [Recipient {
+            id: 1,
+            name: "bob".to_string(),
+            email: "bob@domain.com".to_string(),
+            status: RecipientStatus::Active,
+            pgp_public_key: Some("not a key".to_string()),
+            smime_certificate: None,
+            encryption_required: true,
+        }];
 
-        Ok(mailer)
+        mailer
+            .send(&request(&["bob@domain.com"]), &known_recipients, &[])
+            .await
+            .expect("Failed to send email");
+
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("To: catch-all@test.com"));
+        assert!(messages[0].contains("Test Body"));
+    }
+
+    #[tokio::test]
+    async fn test_send_refuses_unencrypted_email() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+        let known_recipients = [Recipient {
+            id: 1,
+            name: "bob".to_string(),
+            email: "bob@domain.com".to_string(),
+            status: RecipientStatus::Active,
+            pgp_public_key: None,
+            smime_certificate: None,
+            encryption_required: true,
+        }];
+
+        let result = mailer
+            .send(&request(&["Bob <bob@domain.com>"]), &known_recipients, &[])
+            .await;
+        assert!(result.is_err());
+        assert!(stub.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_refuses_invalid_reply_to() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+
+        let result = mailer
+            .send(
+                &SendEmailRequest {
+                    reply_to: Some("not an address".to_string()),
+                    ..request(&["bob@domain.com"])
+                },
+                &[],
+                &[],
+            )
+            .await;
+        assert!(
+            result
+                .unwrap_err()
+                .message
+                .ends_with("Invalid reply_to address")
+        );
+        assert!(stub.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_preview() {
+        let stub = Arc::new(StubTransport::new());
+        let config = MailerConfig {
+            sandbox: Some(SandboxConfig {
+                redirect_to: Some("catch-all@test.com".to_string()),
+                dir: None,
+            }),
+            ..Default::default()
+        };
+        let mailer = Mailer::with_transport(config, stub.clone()).unwrap();
+        let suppressions = [Suppression {
+            id: 1,
+            entry: "carol@domain.com".to_string(),
+            reason: None,
+            created_at: Default::default(),
+        }];
+
+        let prepared = mailer
+            .preview(
+                &SendEmailRequest {
+                    body: "Grüße aus dem Test".to_string(),
+                    ..request(&["bob@domain.com", "carol@domain.com"])
+                },
+                &[],
+                &suppressions,
+            )
+            .expect("Failed to preview email");
+        assert_eq!(
+            prepared.recipients,
+            vec!["bob@domain.com".parse::<Address>().unwrap()]
+        );
+        assert_eq!(prepared.suppressed, vec!["carol@domain.com"]);
+        assert_eq!(
+            prepared.envelope.to(),
+            ["catch-all@test.com".parse::<Address>().unwrap()]
+        );
+
+        // The body is decoded, not transfer-encoded
+        let body = prepared.text_body().unwrap();
+        assert!(body.contains("Originally addressed to: bob@domain.com"));
+        assert!(body.contains("Grüße aus dem Test"));
+
+        assert!(stub.messages().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_skips_suppressed_recipients() {
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(MailerConfig::default(), stub.clone()).unwrap();
+        let suppression = |id, entry: &str| Suppression {
+            id,
+            entry: entry.to_string(),
+            reason: None,
+            created_at: Default::default(),
+        };
+        let suppressions = [
+            suppression(1, "carol@domain.com"),
+            suppression(2, "competitor.com"),
+        ];
+        let sent_message = mailer
+            .send(
+                &request(&[
+                    "bob@domain.com",
+                    "Carol <Carol@Domain.com>",
+                    "dan@mail.competitor.com",
+                ]),
+                &[],
+                &suppressions,
+            )
+            .await
+            .expect("Failed to send email");
+        assert_eq!(sent_message.recipients.len(), 1);
+        assert_eq!(sent_message.recipients[0].to_string(), "bob@domain.com");
+        assert_eq!(
+            sent_message.suppressed,
+            vec!["Carol <Carol@Domain.com>", "dan@mail.competitor.com"]
+        );
+        assert_eq!(sent_message.envelope.to().len(), 1);
+        assert!(!sent_message.headers.contains("competitor.com"));
+
+        // Nobody is left to send to
+        let result = mailer
+            .send(&request(&["erin@competitor.com"]), &[], &suppressions)
+            .await;
+        assert!(result.is_err());
+
+        let resent_message = mailer
+            .resend(
+                sent_message.raw.clone(),
+                &sent_message.sender,
+                &["bob@domain.com".to_string(), "carol@domain.com".to_string()],
+                &["bob@domain.com".to_string()],
+                &[],
+                &suppressions,
+            )
+            .await
+            .expect("Failed to resend email");
+        assert_eq!(resent_message.envelope.to().len(), 1);
+        assert_eq!(resent_message.suppressed, vec!["carol@domain.com"]);
+        assert_eq!(stub.messages().await.len(), 2);
     }
 }
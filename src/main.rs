@@ -1,22 +1,36 @@
+pub mod bounce;
 pub mod config;
 pub mod database;
+pub mod dkim;
 pub mod error;
+pub mod imap;
+pub mod inbound;
 pub mod logging;
 pub mod mailer;
 pub mod model;
+pub mod pagination;
+pub mod pgp;
+pub mod policy;
+pub mod rate_limit;
 pub mod request;
 pub mod service;
+pub mod smime;
+pub mod tag_expression;
+pub mod transport;
+pub mod unsubscribe;
 
 use axum::{extract::Request, middleware::Next, response::Response};
-use config::Config;
-use log::info;
+use config::{Config, DatabaseConfig};
+use database::Database;
+use log::{error, info};
+use mailer::Mailer;
 use rmcp::transport::{
     StreamableHttpServerConfig, StreamableHttpService,
     streamable_http_server::session::local::LocalSessionManager,
 };
-use std::error::Error;
+use std::{error::Error, time::Duration};
 
-use crate::logging::init_logging;
+use crate::{imap::ImapClient, logging::init_logging};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -26,17 +40,41 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Initialize logging
     init_logging(&config.logger_config);
 
+    spawn_retention_job(&config.db_config);
+    spawn_inbound_sync_jobs(&config);
+    spawn_bounce_sync_job(&config);
+
     // Start the server
     let bind_address = config.server_host.clone();
+    let inbound_router = config
+        .inbound_config
+        .as_ref()
+        .map(|inbound_config| inbound::webhook_router(inbound_config, &config.db_config));
+    let unsubscribe_router = config
+        .unsubscribe_config
+        .as_ref()
+        .map(|unsubscribe_config| unsubscribe::router(unsubscribe_config, &config.db_config));
+    // Shared by all sessions so that pooled SMTP connections are reused
+    let mailer = Mailer::new(config.mailer_config.clone())?;
     let service = StreamableHttpService::new(
-        move || Ok(service::MailerService::new(config.clone())),
+        move || {
+            Ok(service::MailerService::with_mailer(
+                config.clone(),
+                mailer.clone(),
+            ))
+        },
         LocalSessionManager::default().into(),
         StreamableHttpServerConfig::default(),
     );
 
-    let router = axum::Router::new()
-        .nest_service("/mcp", service)
-        .layer(axum::middleware::from_fn(log_request));
+    let mut router = axum::Router::new().nest_service("/mcp", service);
+    if let Some(inbound_router) = inbound_router {
+        router = router.merge(inbound_router);
+    }
+    if let Some(unsubscribe_router) = unsubscribe_router {
+        router = router.merge(unsubscribe_router);
+    }
+    let router = router.layer(axum::middleware::from_fn(log_request));
     let ct = tokio_util::sync::CancellationToken::new();
 
     let tcp_listener = tokio::net::TcpListener::bind(bind_address).await?;
@@ -49,6 +87,100 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Applies the retention rules and purges the expired raw messages
+/// periodically, on a database connection of its own.
+fn spawn_retention_job(db_config: &DatabaseConfig) {
+    if db_config.retention.rules.is_empty() && db_config.raw_email_retention_days.is_none() {
+        return;
+    }
+
+    let mut db = Database::new(db_config.clone());
+    let period = Duration::from_secs(db_config.retention.interval_hours.max(1) * 3600);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match db.purge_history(false) {
+                Ok(purged) => {
+                    for (rule, count) in purged {
+                        info!("Retention rule {rule:?} purged {count} records");
+                    }
+                }
+                Err(e) => error!("Failed to apply the retention rules: {}", e),
+            }
+            match db.purge_raw_emails() {
+                Ok(count) => info!("Purged {count} expired raw messages"),
+                Err(e) => error!("Failed to purge the expired raw messages: {}", e),
+            }
+        }
+    });
+}
+
+/// Syncs the replies received by each sender with a sync interval, on a
+/// thread and database connection of its own since IMAP calls block.
+fn spawn_inbound_sync_jobs(config: &Config) {
+    for sender in &config.mailer_config.senders {
+        let Some(sync_interval_minutes) = sender
+            .imap
+            .as_ref()
+            .and_then(|imap| imap.sync_interval_minutes)
+        else {
+            continue;
+        };
+        let Some(client) = ImapClient::for_sender(sender) else {
+            error!(
+                "No IMAP credentials for {}, replies aren't synced",
+                sender.email
+            );
+            continue;
+        };
+
+        let mut db = Database::new(config.db_config.clone());
+        let period = Duration::from_secs(sync_interval_minutes.max(1) * 60);
+        std::thread::spawn(move || {
+            loop {
+                if let Err(e) = inbound::sync_replies(&mut db, &client) {
+                    error!(
+                        "Failed to sync the replies received by {}: {}",
+                        client.email, e
+                    );
+                }
+                std::thread::sleep(period);
+            }
+        });
+    }
+}
+
+/// Reads the bounce folder periodically, on a thread and database connection
+/// of its own since IMAP calls block.
+fn spawn_bounce_sync_job(config: &Config) {
+    let Some(bounces) = config.mailer_config.bounces.clone() else {
+        return;
+    };
+    let Some(client) = config
+        .mailer_config
+        .find_sender(&bounces.mailbox)
+        .and_then(ImapClient::for_sender)
+    else {
+        error!(
+            "No IMAP credentials for {}, bounces aren't read",
+            bounces.mailbox
+        );
+        return;
+    };
+
+    let mut db = Database::new(config.db_config.clone());
+    let period = Duration::from_secs(bounces.sync_interval_minutes.max(1) * 60);
+    std::thread::spawn(move || {
+        loop {
+            if let Err(e) = bounce::sync_bounces(&mut db, &client, &bounces) {
+                error!("Failed to read the bounces of {}: {}", client.email, e);
+            }
+            std::thread::sleep(period);
+        }
+    });
+}
+
 async fn log_request(request: Request, next: Next) -> Response {
     use http_body_util::BodyExt;
 
@@ -0,0 +1,87 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    deserialize::{FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::{Identifiable, Insertable, Queryable},
+    serialize::{Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+
+use crate::database::schema::bounces;
+
+/// A recipient a sent email couldn't be delivered to, as reported by a
+/// delivery status notification.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = bounces)]
+#[diesel(primary_key(id))]
+pub struct Bounce {
+    pub id: i32,
+    /// The sent email that bounced, if the notification could be mapped back to it.
+    pub email_history_id: Option<i32>,
+    /// The recipient who bounced, if the address is in the phone book.
+    pub recipient_id: Option<i32>,
+    /// The address that bounced.
+    pub email: String,
+    pub kind: BounceKind,
+    /// The RFC 3463 status code, e.g. `5.1.1`.
+    pub status: String,
+    /// The reply of the remote server, if reported.
+    pub diagnostic: Option<String>,
+    /// The `Message-ID` of the notification.
+    pub notification_id: Option<String>,
+    pub received_at: NaiveDateTime,
+}
+
+/// A bounce to be inserted, `received_at` is set by the database.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = bounces)]
+pub struct NewBounce {
+    pub email_history_id: Option<i32>,
+    pub recipient_id: Option<i32>,
+    pub email: String,
+    pub kind: BounceKind,
+    pub status: String,
+    pub diagnostic: Option<String>,
+    pub notification_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression)]
+#[diesel(sql_type = diesel::sql_types::Text)]
+pub enum BounceKind {
+    /// A permanent failure, e.g. an unknown mailbox.
+    Hard,
+    /// A transient failure the server gave up retrying, e.g. a full mailbox.
+    Soft,
+}
+
+impl ToSql<Text, Sqlite> for BounceKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> diesel::serialize::Result {
+        let kind_str = match self {
+            BounceKind::Hard => "hard",
+            BounceKind::Soft => "soft",
+        };
+        out.set_value(kind_str);
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for BounceKind {
+    fn from_sql(bytes: SqliteValue) -> diesel::deserialize::Result<Self> {
+        let t = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(t.as_str().try_into()?)
+    }
+}
+
+impl TryFrom<&str> for BounceKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "hard" => Ok(BounceKind::Hard),
+            "soft" => Ok(BounceKind::Soft),
+            _ => Err(format!("Invalid bounce kind: {}", value)),
+        }
+    }
+}
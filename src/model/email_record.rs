@@ -1,7 +1,12 @@
 use chrono::NaiveDateTime;
 use diesel::{
     Selectable,
-    prelude::{Identifiable, Insertable, Queryable},
+    deserialize::{FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::{Identifiable, Insertable, Queryable, QueryableByName},
+    serialize::{Output, ToSql},
+    sql_types::{Integer, Text, Timestamp},
+    sqlite::{Sqlite, SqliteValue},
 };
 
 use crate::database::schema::email_history;
@@ -14,4 +19,117 @@ pub struct EmailRecord {
     pub subject: String,
     pub body: String,
     pub sent_at: NaiveDateTime,
+    /// Whether the email was redirected by the sandbox instead of reaching its recipients.
+    pub sandboxed: bool,
+    /// The `Message-ID` header of the sent message, including the angle brackets.
+    pub message_id: String,
+    pub sender: String,
+    /// All headers of the sent message, as sent.
+    pub headers: String,
+    /// The record this email replies to.
+    pub in_reply_to_id: Option<i32>,
+    /// The first record of the thread. `None` if this email starts a thread.
+    pub thread_id: Option<i32>,
+    pub reply_to: Option<String>,
+    /// The delivery backend the email was handed to, e.g. `smtp`.
+    pub transport: String,
+    /// The SMTP reply code of the server, if the transport talks SMTP.
+    pub smtp_code: Option<i32>,
+    /// The reply of the server, or the error if the delivery failed.
+    pub smtp_message: Option<String>,
+    pub status: EmailStatus,
+    /// The record whose raw message was re-delivered by this one.
+    pub resent_from_id: Option<i32>,
+    /// The template the email was rendered from.
+    pub template_id: Option<i32>,
+    /// When the body was deleted by a retention rule. The body is empty after that.
+    pub body_purged_at: Option<NaiveDateTime>,
+}
+
+impl EmailRecord {
+    /// Returns the id of the first record of the thread this email belongs to.
+    pub fn thread_root_id(&self) -> i32 {
+        self.thread_id.unwrap_or(self.id)
+    }
+}
+
+/// An email record to be inserted, `sent_at` is set by the database.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = email_history)]
+pub struct NewEmailRecord {
+    pub subject: String,
+    pub body: String,
+    pub sandboxed: bool,
+    pub message_id: String,
+    pub sender: String,
+    pub headers: String,
+    pub in_reply_to_id: Option<i32>,
+    pub thread_id: Option<i32>,
+    pub reply_to: Option<String>,
+    pub transport: String,
+    pub smtp_code: Option<i32>,
+    pub smtp_message: Option<String>,
+    pub status: EmailStatus,
+    pub resent_from_id: Option<i32>,
+    pub template_id: Option<i32>,
+}
+
+/// An email record matching a full-text search, best match first.
+#[derive(Debug, Clone, QueryableByName)]
+pub struct EmailSearchResult {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub subject: String,
+    #[diesel(sql_type = Text)]
+    pub sender: String,
+    #[diesel(sql_type = Timestamp)]
+    pub sent_at: NaiveDateTime,
+    /// The best matching fragment, with the matched terms in `**`.
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+/// The outcome of the delivery of an email.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression)]
+#[diesel(sql_type = diesel::sql_types::Text)]
+pub enum EmailStatus {
+    /// Accepted by the SMTP server.
+    Sent,
+    /// Rejected, or the transport could not be reached.
+    Failed,
+    /// Handed to a local queue, e.g. a sendmail binary or a directory.
+    Queued,
+}
+
+impl ToSql<Text, Sqlite> for EmailStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> diesel::serialize::Result {
+        let status_str = match self {
+            EmailStatus::Sent => "sent",
+            EmailStatus::Failed => "failed",
+            EmailStatus::Queued => "queued",
+        };
+        out.set_value(status_str);
+        Ok(diesel::serialize::IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for EmailStatus {
+    fn from_sql(bytes: SqliteValue) -> diesel::deserialize::Result<Self> {
+        let t = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(t.as_str().try_into()?)
+    }
+}
+
+impl TryFrom<&str> for EmailStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "sent" => Ok(EmailStatus::Sent),
+            "failed" => Ok(EmailStatus::Failed),
+            "queued" => Ok(EmailStatus::Queued),
+            _ => Err(format!("Invalid email status: {}", value)),
+        }
+    }
 }
@@ -0,0 +1,46 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    prelude::{Identifiable, Insertable, Queryable},
+};
+
+use crate::database::schema::email_replies;
+
+/// A received message replying to a sent email.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = email_replies)]
+#[diesel(primary_key(id))]
+pub struct EmailReply {
+    pub id: i32,
+    /// The sent email replied to.
+    pub email_history_id: i32,
+    /// The recipient who replied, if the sender is in the phone book.
+    pub recipient_id: Option<i32>,
+    /// The `Message-ID` header of the reply, including the angle brackets.
+    pub message_id: String,
+    pub sender: String,
+    pub subject: String,
+    pub received_at: NaiveDateTime,
+}
+
+/// A reply to be inserted, `received_at` is set by the database.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = email_replies)]
+pub struct NewEmailReply {
+    pub email_history_id: i32,
+    pub recipient_id: Option<i32>,
+    pub message_id: String,
+    pub sender: String,
+    pub subject: String,
+}
+
+/// How far the messages of a mailbox folder have been synced.
+#[derive(Debug, Clone, Copy, Queryable, PartialEq, Eq)]
+pub struct InboundSyncState {
+    /// The UIDs are only valid as long as the folder keeps this UIDVALIDITY.
+    #[diesel(deserialize_as = i64)]
+    pub uid_validity: u32,
+    /// The last UID synced.
+    #[diesel(deserialize_as = i64)]
+    pub last_uid: u32,
+}
@@ -0,0 +1,88 @@
+use diesel::{
+    prelude::QueryableByName,
+    sql_types::{BigInt, Text},
+};
+
+/// What the email history is aggregated by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailStatsDimension {
+    Day,
+    /// Weeks start on Monday, e.g. `2024-W05`.
+    Week,
+    Month,
+    /// An email counts once for each of its recipients.
+    Recipient,
+    /// An email counts once for each group with a current member among its recipients.
+    Group,
+    Sender,
+    /// Only the emails sent with a template are counted.
+    Template,
+}
+
+impl EmailStatsDimension {
+    pub const ALL: [EmailStatsDimension; 7] = [
+        EmailStatsDimension::Day,
+        EmailStatsDimension::Week,
+        EmailStatsDimension::Month,
+        EmailStatsDimension::Recipient,
+        EmailStatsDimension::Group,
+        EmailStatsDimension::Sender,
+        EmailStatsDimension::Template,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailStatsDimension::Day => "day",
+            EmailStatsDimension::Week => "week",
+            EmailStatsDimension::Month => "month",
+            EmailStatsDimension::Recipient => "recipient",
+            EmailStatsDimension::Group => "group",
+            EmailStatsDimension::Sender => "sender",
+            EmailStatsDimension::Template => "template",
+        }
+    }
+
+    /// Whether the rows are periods of time, listed in chronological order.
+    pub fn is_period(&self) -> bool {
+        matches!(
+            self,
+            EmailStatsDimension::Day | EmailStatsDimension::Week | EmailStatsDimension::Month
+        )
+    }
+}
+
+impl TryFrom<&str> for EmailStatsDimension {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        EmailStatsDimension::ALL
+            .into_iter()
+            .find(|dimension| dimension.as_str() == value)
+            .ok_or_else(|| format!("Invalid email stats dimension: {}", value))
+    }
+}
+
+/// The number of emails of one day, recipient, sender, etc., by delivery status.
+#[derive(Debug, Clone, PartialEq, Eq, QueryableByName)]
+pub struct EmailStatsRow {
+    #[diesel(sql_type = Text)]
+    pub key: String,
+    #[diesel(sql_type = BigInt)]
+    pub total: i64,
+    #[diesel(sql_type = BigInt)]
+    pub sent: i64,
+    #[diesel(sql_type = BigInt)]
+    pub queued: i64,
+    #[diesel(sql_type = BigInt)]
+    pub failed: i64,
+}
+
+impl EmailStatsRow {
+    /// Returns the share of failed deliveries, between 0 and 1.
+    pub fn failure_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.failed as f64 / self.total as f64
+    }
+}
@@ -0,0 +1,45 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    prelude::{Identifiable, Insertable, Queryable},
+};
+
+use crate::database::schema::inbound_messages;
+
+/// A message pushed to the inbound webhook. The raw message is stored
+/// compressed next to it and loaded on demand.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = inbound_messages)]
+#[diesel(primary_key(id))]
+pub struct InboundMessage {
+    pub id: i32,
+    /// The `Message-ID` header, including the angle brackets.
+    pub message_id: Option<String>,
+    /// The address of the sender, without the display name.
+    pub sender: String,
+    /// The recipient who sent the message, if the sender is in the phone book.
+    pub recipient_id: Option<i32>,
+    /// The `To` header, formatted.
+    pub to_addresses: String,
+    pub subject: String,
+    /// The text body, or the HTML body if the message has no text part.
+    pub body: String,
+    /// The sent email the message replies to.
+    pub email_history_id: Option<i32>,
+    pub received_at: NaiveDateTime,
+}
+
+/// An inbound message to be inserted, `received_at` is set by the database.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = inbound_messages)]
+pub struct NewInboundMessage {
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub recipient_id: Option<i32>,
+    pub to_addresses: String,
+    pub subject: String,
+    pub body: String,
+    pub email_history_id: Option<i32>,
+    /// The raw message, gzip compressed.
+    pub raw_message: Vec<u8>,
+}
@@ -1,8 +1,17 @@
+pub mod bounce;
 pub mod email_record;
+pub mod email_reply;
+pub mod email_stats;
 pub mod event;
 pub mod event_attendee;
 pub mod group;
+pub mod inbound_message;
+pub mod raw_email;
 pub mod recipient;
+pub mod recipient_attribute;
 pub mod recipient_email_record;
 pub mod recipient_group;
+pub mod recipient_tag;
+pub mod suppression;
 pub mod template;
+pub mod unsubscribe;
@@ -0,0 +1,40 @@
+use std::io::{Read, Write};
+
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    prelude::{Identifiable, Insertable, Queryable},
+};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+use crate::{database::schema::raw_emails, error::MailerError};
+
+/// The formatted RFC 5322 message of an email record, gzip compressed.
+#[derive(Debug, Clone, Queryable, Insertable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = raw_emails)]
+#[diesel(primary_key(email_history_id))]
+pub struct RawEmail {
+    pub email_history_id: i32,
+    pub message: Vec<u8>,
+    pub created_at: NaiveDateTime,
+}
+
+impl RawEmail {
+    pub fn compress(message: &[u8]) -> Result<Vec<u8>, MailerError> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(message)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Returns the message exactly as it was sent.
+    pub fn decompress(&self) -> Result<Vec<u8>, MailerError> {
+        Self::decompress_bytes(&self.message)
+    }
+
+    /// Decompresses a message compressed by `compress`.
+    pub fn decompress_bytes(compressed: &[u8]) -> Result<Vec<u8>, MailerError> {
+        let mut message = Vec::new();
+        GzDecoder::new(compressed).read_to_end(&mut message)?;
+        Ok(message)
+    }
+}
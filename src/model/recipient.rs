@@ -18,13 +18,22 @@ pub struct Recipient {
     pub name: String,
     pub email: String,
     pub status: RecipientStatus,
+    /// ASCII armored OpenPGP public key used to encrypt mail to this recipient.
+    pub pgp_public_key: Option<String>,
+    /// PEM encoded S/MIME certificate used to encrypt mail to this recipient.
+    pub smime_certificate: Option<String>,
+    /// Refuse to send unencrypted mail to this recipient.
+    pub encryption_required: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, FromSqlRow, AsExpression)]
 #[diesel(sql_type = diesel::sql_types::Text)]
 pub enum RecipientStatus {
     Active,
+    /// Removed by hand.
     Inactive,
+    /// Deactivated after a hard bounce or repeated soft ones.
+    Bounced,
 }
 
 impl ToSql<Text, Sqlite> for RecipientStatus {
@@ -32,6 +41,7 @@ impl ToSql<Text, Sqlite> for RecipientStatus {
         let status_str = match self {
             RecipientStatus::Active => "Active",
             RecipientStatus::Inactive => "Inactive",
+            RecipientStatus::Bounced => "Bounced",
         };
         out.set_value(status_str);
         Ok(diesel::serialize::IsNull::No)
@@ -52,6 +62,7 @@ impl TryFrom<&str> for RecipientStatus {
         match value {
             "Active" => Ok(RecipientStatus::Active),
             "Inactive" => Ok(RecipientStatus::Inactive),
+            "Bounced" => Ok(RecipientStatus::Bounced),
             _ => Err(format!("Invalid recipient status: {}", value)),
         }
     }
@@ -0,0 +1,20 @@
+use diesel::{
+    Selectable,
+    prelude::{Associations, Identifiable, Insertable, Queryable},
+};
+
+use crate::{database::schema::recipient_attributes, model::recipient::Recipient};
+
+/// A free-form attribute of a recipient, e.g. its company or first name,
+/// available to the templates under its name.
+#[derive(
+    Debug, Clone, Queryable, Insertable, Selectable, Identifiable, Associations, PartialEq, Eq,
+)]
+#[diesel(belongs_to(Recipient))]
+#[diesel(table_name = recipient_attributes)]
+#[diesel(primary_key(recipient_id, name))]
+pub struct RecipientAttribute {
+    pub recipient_id: i32,
+    pub name: String,
+    pub value: String,
+}
@@ -0,0 +1,18 @@
+use diesel::{
+    Selectable,
+    prelude::{Associations, Identifiable, Insertable, Queryable},
+};
+
+use crate::{database::schema::recipient_tags, model::recipient::Recipient};
+
+/// A tag of a recipient. Tags are lowercased.
+#[derive(
+    Debug, Clone, Queryable, Insertable, Selectable, Identifiable, Associations, PartialEq, Eq,
+)]
+#[diesel(belongs_to(Recipient))]
+#[diesel(table_name = recipient_tags)]
+#[diesel(primary_key(recipient_id, tag))]
+pub struct RecipientTag {
+    pub recipient_id: i32,
+    pub tag: String,
+}
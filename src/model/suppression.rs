@@ -0,0 +1,47 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    prelude::{Identifiable, Insertable, Queryable},
+};
+use lettre::Address;
+
+use crate::database::schema::suppressions;
+
+/// An address, or a whole domain, that must never be emailed.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = suppressions)]
+#[diesel(primary_key(id))]
+pub struct Suppression {
+    pub id: i32,
+    /// The suppressed address or domain, lowercased.
+    pub entry: String,
+    /// Why the entry is suppressed, e.g. a legal hold or a complaint.
+    pub reason: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A suppression to be inserted, `created_at` is set by the database.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = suppressions)]
+pub struct NewSuppression {
+    pub entry: String,
+    pub reason: Option<String>,
+}
+
+impl Suppression {
+    /// Whether the entry is a domain rather than a single address.
+    pub fn is_domain(&self) -> bool {
+        !self.entry.contains('@')
+    }
+
+    /// Whether the address is suppressed by this entry. A domain also
+    /// suppresses its subdomains.
+    pub fn matches(&self, address: &Address) -> bool {
+        if !self.is_domain() {
+            return address.to_string().eq_ignore_ascii_case(&self.entry);
+        }
+
+        let domain = address.domain().to_lowercase();
+        domain == self.entry || domain.ends_with(&format!(".{}", self.entry))
+    }
+}
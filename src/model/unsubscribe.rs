@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use diesel::{
+    Selectable,
+    prelude::{Identifiable, Queryable},
+};
+
+use crate::database::schema::unsubscribes;
+
+/// An address that opted out of the emails of a group, or of all group emails.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable, PartialEq, Eq)]
+#[diesel(table_name = unsubscribes)]
+#[diesel(primary_key(id))]
+pub struct Unsubscribe {
+    pub id: i32,
+    /// The address that opted out, lowercased.
+    pub email: String,
+    /// The group opted out of. `None` for all groups.
+    pub group_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
@@ -0,0 +1,107 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64};
+use rmcp::schemars::{self, JsonSchema};
+use serde::Deserialize;
+
+use crate::error::{MailerError, new_rmcp_error};
+
+/// Number of items in a page when the caller doesn't set a limit.
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+
+/// Largest page a caller can ask for.
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Sort order of a paginated list, by creation order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Oldest first.
+    #[default]
+    Asc,
+    /// Newest first.
+    Desc,
+}
+
+/// A page of rows to load, keyed by id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Page {
+    /// Only load the rows after this id, in the sort order.
+    pub after_id: Option<i32>,
+    /// Maximum number of rows to load. All rows are loaded if not set.
+    pub limit: Option<i64>,
+    pub order: SortOrder,
+}
+
+impl Page {
+    /// Returns the page to load to fill `limit` items, plus one extra row to
+    /// tell whether there is a next page.
+    pub fn probe(after_id: Option<i32>, limit: i64, order: SortOrder) -> Self {
+        Self {
+            after_id,
+            limit: Some(limit + 1),
+            order,
+        }
+    }
+}
+
+/// Encodes the position after the item `id` of the list `kind` as an opaque
+/// `next_cursor` token.
+pub fn encode_cursor(kind: &str, id: i32) -> String {
+    BASE64.encode(format!("{kind}:{id}"))
+}
+
+/// Decodes a cursor token into the list kind and the id to continue after.
+pub fn decode_cursor(cursor: &str) -> Result<(String, i32), MailerError> {
+    BASE64
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|cursor| {
+            let (kind, id) = cursor.split_once(':')?;
+            Some((kind.to_string(), id.parse().ok()?))
+        })
+        .ok_or_else(|| new_rmcp_error("Invalid cursor"))
+}
+
+/// Truncates the rows loaded with `Page::probe` to `limit` items. Returns the
+/// cursor of the next page if there are more rows.
+pub fn split_page<T>(
+    mut items: Vec<T>,
+    limit: i64,
+    kind: &str,
+    id: impl Fn(&T) -> i32,
+) -> (Vec<T>, Option<String>) {
+    let limit = usize::try_from(limit).unwrap_or_default();
+    if items.len() <= limit {
+        return (items, None);
+    }
+
+    items.truncate(limit);
+    let next_cursor = items.last().map(|item| encode_cursor(kind, id(item)));
+    (items, next_cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor() {
+        let cursor = encode_cursor("recipient", 42);
+        assert_eq!(
+            decode_cursor(&cursor).unwrap(),
+            ("recipient".to_string(), 42)
+        );
+        assert!(decode_cursor("not a cursor").is_err());
+    }
+
+    #[test]
+    fn test_split_page() {
+        let (items, next_cursor) = split_page(vec![1, 2, 3], 2, "event", |i| *i);
+        assert_eq!(items, vec![1, 2]);
+        assert_eq!(decode_cursor(&next_cursor.unwrap()).unwrap().1, 2);
+
+        let (items, next_cursor) = split_page(vec![1, 2], 2, "event", |i| *i);
+        assert_eq!(items, vec![1, 2]);
+        assert!(next_cursor.is_none());
+    }
+}
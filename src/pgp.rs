@@ -0,0 +1,223 @@
+use lettre::message::{
+    MultiPart, SinglePart,
+    header::{ContentTransferEncoding, ContentType},
+};
+use pgp::{
+    Deserializable, Message as PgpMessage, SignedPublicKey, SignedSecretKey,
+    crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm},
+    types::KeyTrait,
+};
+
+use crate::{
+    config::PgpSigningKey,
+    error::{MailerError, new_rmcp_error},
+};
+
+/// Parses an ASCII armored public key and returns its fingerprint in hex.
+pub fn public_key_fingerprint(armored_key: &str) -> Result<String, MailerError> {
+    let (public_key, _) = SignedPublicKey::from_string(armored_key)
+        .map_err(|e| new_rmcp_error(&format!("Invalid PGP public key: {}", e)))?;
+
+    Ok(public_key
+        .fingerprint()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect())
+}
+
+/// Builds an RFC 3156 `multipart/encrypted` body from the entity, encrypted to
+/// all given public keys. The entity is signed first if a signing key is given.
+pub fn encrypt(
+    entity: &SinglePart,
+    armored_public_keys: &[&str],
+    signing_key: Option<&PgpSigningKey>,
+) -> Result<MultiPart, MailerError> {
+    let public_keys = armored_public_keys
+        .iter()
+        .map(|armored_key| {
+            SignedPublicKey::from_string(armored_key)
+                .map(|(key, _)| key)
+                .map_err(|e| new_rmcp_error(&format!("Invalid PGP public key: {}", e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Most keys encrypt with a subkey, not with the primary key
+    let encryption_keys = public_keys
+        .iter()
+        .map(|key| {
+            key.public_subkeys
+                .iter()
+                .find(|subkey| subkey.is_encryption_key())
+                .ok_or_else(|| {
+                    new_rmcp_error(&format!(
+                        "PGP public key {:?} has no encryption subkey",
+                        key.key_id()
+                    ))
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut message = PgpMessage::new_literal_bytes("", &entity.formatted());
+    if let Some(signing_key) = signing_key {
+        let (secret_key, passphrase) = load_signing_key(signing_key)?;
+        message = message
+            .sign(&secret_key, || passphrase, HashAlgorithm::SHA2_256)
+            .map_err(|e| new_rmcp_error(&format!("Failed to sign PGP message: {}", e)))?;
+    }
+
+    let armored_message = message
+        .encrypt_to_keys(
+            &mut rand::thread_rng(),
+            SymmetricKeyAlgorithm::AES256,
+            &encryption_keys,
+        )
+        .and_then(|encrypted| encrypted.to_armored_string(None))
+        .map_err(|e| new_rmcp_error(&format!("Failed to encrypt PGP message: {}", e)))?;
+
+    Ok(
+        MultiPart::encrypted("application/pgp-encrypted".to_string())
+            .singlepart(
+                SinglePart::builder()
+                    .header(content_type("application/pgp-encrypted")?)
+                    .header(ContentTransferEncoding::SevenBit)
+                    .body(String::from("Version: 1")),
+            )
+            .singlepart(
+                SinglePart::builder()
+                    .header(content_type(
+                        "application/octet-stream; name=\"encrypted.asc\"",
+                    )?)
+                    .header(ContentTransferEncoding::SevenBit)
+                    .body(armored_message),
+            ),
+    )
+}
+
+/// Builds an RFC 3156 `multipart/signed` body with a detached signature of the entity.
+pub fn sign(entity: &SinglePart, signing_key: &PgpSigningKey) -> Result<MultiPart, MailerError> {
+    let (secret_key, passphrase) = load_signing_key(signing_key)?;
+
+    // The CRLF before the closing boundary belongs to the boundary, not to the entity
+    let formatted = entity.formatted();
+    let signed_data = formatted.strip_suffix(b"\r\n").unwrap_or(&formatted);
+
+    let armored_signature = PgpMessage::new_literal_bytes("", signed_data)
+        .sign(&secret_key, || passphrase, HashAlgorithm::SHA2_256)
+        .and_then(|signed| signed.into_signature().to_armored_string(None))
+        .map_err(|e| new_rmcp_error(&format!("Failed to sign PGP message: {}", e)))?;
+
+    Ok(MultiPart::signed(
+        "application/pgp-signature".to_string(),
+        "pgp-sha256".to_string(),
+    )
+    .singlepart(entity.clone())
+    .singlepart(
+        SinglePart::builder()
+            .header(content_type(
+                "application/pgp-signature; name=\"signature.asc\"",
+            )?)
+            .header(ContentTransferEncoding::SevenBit)
+            .body(armored_signature),
+    ))
+}
+
+fn load_signing_key(signing_key: &PgpSigningKey) -> Result<(SignedSecretKey, String), MailerError> {
+    let armored_key = std::fs::read_to_string(&signing_key.private_key_path)?;
+    let (secret_key, _) = SignedSecretKey::from_string(&armored_key)
+        .map_err(|e| new_rmcp_error(&format!("Invalid PGP signing key: {}", e)))?;
+
+    Ok((
+        secret_key,
+        signing_key.passphrase.clone().unwrap_or_default(),
+    ))
+}
+
+fn content_type(value: &str) -> Result<ContentType, MailerError> {
+    ContentType::parse(value).map_err(|_| new_rmcp_error(&format!("Invalid content type: {value}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use pgp::{
+        composed::{KeyType, SecretKeyParamsBuilder, SubkeyParamsBuilder},
+        types::SecretKeyTrait,
+    };
+
+    use super::*;
+
+    /// Generates a signing key with an encryption subkey, as ASCII armored
+    /// secret and public keys.
+    fn generate_key(user_id: &str) -> (String, String) {
+        let secret_key = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::EdDSA)
+            .can_sign(true)
+            .can_create_certificates(true)
+            .primary_user_id(user_id.to_string())
+            .subkey(
+                SubkeyParamsBuilder::default()
+                    .key_type(KeyType::ECDH)
+                    .can_encrypt(true)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap()
+            .generate()
+            .unwrap()
+            .sign(String::new)
+            .unwrap();
+        let public_key = secret_key
+            .public_key()
+            .sign(&secret_key, String::new)
+            .unwrap();
+
+        (
+            secret_key.to_armored_string(None).unwrap(),
+            public_key.to_armored_string(None).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_encrypt_and_sign() {
+        let (sender_secret_key, sender_public_key) = generate_key("Sender <sender@test.com>");
+        let (recipient_secret_key, recipient_public_key) = generate_key("Bob <bob@domain.com>");
+        let path = std::env::temp_dir().join(format!("pgp-test-{}.asc", std::process::id()));
+        std::fs::write(&path, &sender_secret_key).unwrap();
+        let signing_key = PgpSigningKey {
+            private_key_path: path.to_string_lossy().to_string(),
+            passphrase: None,
+            sign_unencrypted: false,
+        };
+
+        assert_eq!(
+            public_key_fingerprint(&recipient_public_key).unwrap().len(),
+            40
+        );
+
+        let entity = SinglePart::plain("Secret Body".to_string());
+        let encrypted = encrypt(&entity, &[&recipient_public_key], Some(&signing_key)).unwrap();
+        let formatted = String::from_utf8(encrypted.formatted()).unwrap();
+        assert!(formatted.contains("multipart/encrypted"));
+        assert!(!formatted.contains("Secret Body"));
+
+        // Only the recipient can decrypt it, and the signature verifies
+        // against the sender key
+        let start = formatted.find("-----BEGIN PGP MESSAGE-----").unwrap();
+        let end = formatted.find("-----END PGP MESSAGE-----").unwrap();
+        let (message, _) =
+            PgpMessage::from_string(&formatted[start..end + "-----END PGP MESSAGE-----".len()])
+                .unwrap();
+        let (recipient_secret_key, _) =
+            SignedSecretKey::from_string(&recipient_secret_key).unwrap();
+        let (mut decrypter, _) = message
+            .decrypt(String::new, &[&recipient_secret_key])
+            .unwrap();
+        let decrypted = decrypter.next().unwrap().unwrap().decompress().unwrap();
+
+        let (sender_public_key, _) = SignedPublicKey::from_string(&sender_public_key).unwrap();
+        decrypted.verify(&sender_public_key).unwrap();
+        assert_eq!(decrypted.get_content().unwrap(), Some(entity.formatted()));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
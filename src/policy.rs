@@ -0,0 +1,127 @@
+use lettre::message::Mailbox;
+
+use crate::{
+    config::SendPolicy,
+    error::{MailerError, new_rmcp_error},
+};
+
+/// Checks a message to `to` against the policy: its number of recipients and
+/// their domains. Fails with the offending addresses.
+pub fn check(policy: &SendPolicy, to: &[String]) -> Result<(), MailerError> {
+    if let Some(max_recipients) = policy.max_recipients
+        && to.len() > max_recipients
+    {
+        return Err(new_rmcp_error(&format!(
+            "Send policy violation: {} recipients, at most {max_recipients} allowed per message",
+            to.len()
+        )));
+    }
+
+    check_domains(policy, to)
+}
+
+/// Checks the domains of the recipients against the policy, ignoring their
+/// number. Fails with the offending addresses.
+fn check_domains(policy: &SendPolicy, to: &[String]) -> Result<(), MailerError> {
+    let violations = to
+        .iter()
+        .filter_map(|recipient| {
+            let reason = match recipient.parse::<Mailbox>() {
+                Ok(mailbox) => domain_violation(policy, mailbox.email.domain())?,
+                Err(_) => "invalid address",
+            };
+            Some(format!("{recipient} ({reason})"))
+        })
+        .collect::<Vec<_>>();
+
+    if !violations.is_empty() {
+        return Err(new_rmcp_error(&format!(
+            "Send policy violation, refusing to email: {}",
+            violations.join(", ")
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns why the domain can't be emailed, if it can't.
+fn domain_violation(policy: &SendPolicy, domain: &str) -> Option<&'static str> {
+    if policy
+        .denied_domains
+        .iter()
+        .any(|denied| covers(denied, domain))
+    {
+        return Some("domain denied");
+    }
+
+    if !policy.allowed_domains.is_empty()
+        && !policy
+            .allowed_domains
+            .iter()
+            .any(|allowed| covers(allowed, domain))
+    {
+        return Some("domain not allowed");
+    }
+
+    None
+}
+
+/// Whether the policy domain covers the domain or one of its subdomains.
+fn covers(policy_domain: &str, domain: &str) -> bool {
+    let policy_domain = policy_domain.trim_start_matches('@').to_lowercase();
+    let domain = domain.to_lowercase();
+    domain == policy_domain || domain.ends_with(&format!(".{policy_domain}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let policy = SendPolicy {
+            allowed_domains: vec!["domain.com".to_string()],
+            denied_domains: vec!["@partner.domain.com".to_string()],
+            max_recipients: Some(2),
+        };
+        let to = |to: &[&str]| to.iter().map(|to| to.to_string()).collect::<Vec<_>>();
+
+        assert!(
+            check(
+                &policy,
+                &to(&["bob@domain.com", "Carol <carol@Sales.Domain.com>"])
+            )
+            .is_ok()
+        );
+        assert!(
+            check(
+                &policy,
+                &to(&["a@domain.com", "b@domain.com", "c@domain.com"])
+            )
+            .is_err()
+        );
+        assert!(
+            check_domains(
+                &policy,
+                &to(&["a@domain.com", "b@domain.com", "c@domain.com"])
+            )
+            .is_ok()
+        );
+
+        let error = check(&policy, &to(&["dan@partner.domain.com", "erin@other.com"])).unwrap_err();
+        assert!(
+            error
+                .message
+                .contains("dan@partner.domain.com (domain denied)")
+        );
+        assert!(
+            error
+                .message
+                .contains("erin@other.com (domain not allowed)")
+        );
+        assert!(check(&policy, &to(&["frank@notdomain.com"])).is_err());
+
+        // Anyone can be emailed without an allow-list
+        assert!(check(&SendPolicy::default(), &to(&["erin@other.com"])).is_ok());
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::{MailerConfig, RateLimitConfig};
+
+/// The remaining quota of a rate limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitStatus {
+    /// `global`, or the email address of the sender.
+    pub scope: String,
+    pub messages_per_minute: u32,
+    pub burst: u32,
+    /// Messages that can be sent right now without waiting.
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub full_in_secs: u64,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    config: RateLimitConfig,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(config: RateLimitConfig, now: Instant) -> Self {
+        Self {
+            config,
+            tokens: config.burst() as f64,
+            refilled_at: now,
+        }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.config.messages_per_minute as f64 / 60.0
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.refill_per_sec())
+            .min(self.config.burst() as f64);
+        self.refilled_at = now;
+    }
+
+    /// Returns how long until a token is available, zero if one is.
+    fn wait_time(&self) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec())
+    }
+
+    fn status(&self, scope: &str) -> RateLimitStatus {
+        let missing = self.config.burst() as f64 - self.tokens;
+        RateLimitStatus {
+            scope: scope.to_string(),
+            messages_per_minute: self.config.messages_per_minute,
+            burst: self.config.burst(),
+            remaining: self.tokens.floor() as u32,
+            full_in_secs: (missing / self.refill_per_sec()).ceil() as u64,
+        }
+    }
+}
+
+/// The outbound rate limits: a global one, for the provider, and one per
+/// sender. A message takes a token from both.
+#[derive(Debug)]
+pub struct RateLimiter {
+    global: Option<Mutex<TokenBucket>>,
+    /// Keyed by the lowercased email address of the sender.
+    senders: HashMap<String, Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &MailerConfig) -> Self {
+        Self::with_start(config, Instant::now())
+    }
+
+    fn with_start(config: &MailerConfig, now: Instant) -> Self {
+        Self {
+            global: config
+                .rate_limit
+                .map(|rate_limit| Mutex::new(TokenBucket::new(rate_limit, now))),
+            senders: config
+                .senders
+                .iter()
+                .filter_map(|sender| {
+                    let rate_limit = sender.rate_limit?;
+                    Some((
+                        sender.email.to_lowercase(),
+                        Mutex::new(TokenBucket::new(rate_limit, now)),
+                    ))
+                })
+                .collect(),
+        }
+    }
+
+    /// Waits until the sender may send a message, and takes its token.
+    pub async fn acquire(&self, sender: &str) {
+        loop {
+            let wait = self.try_acquire(sender, Instant::now());
+            if wait.is_zero() {
+                return;
+            }
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes a token from every bucket of the sender if they all have one.
+    /// Otherwise takes nothing and returns how long to wait.
+    fn try_acquire(&self, sender: &str, now: Instant) -> Duration {
+        let mut buckets = self
+            .senders
+            .get(&sender.to_lowercase())
+            .into_iter()
+            .chain(&self.global)
+            .map(|bucket| bucket.lock().expect("Rate limit lock poisoned"))
+            .collect::<Vec<_>>();
+
+        for bucket in buckets.iter_mut() {
+            bucket.refill(now);
+        }
+        let wait = buckets
+            .iter()
+            .map(|bucket| bucket.wait_time())
+            .max()
+            .unwrap_or_default();
+        if wait.is_zero() {
+            for bucket in buckets.iter_mut() {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        wait
+    }
+
+    /// Returns the remaining quota of every rate limit, the global one first.
+    pub fn status(&self) -> Vec<RateLimitStatus> {
+        self.status_at(Instant::now())
+    }
+
+    fn status_at(&self, now: Instant) -> Vec<RateLimitStatus> {
+        let mut senders = self.senders.iter().collect::<Vec<_>>();
+        senders.sort_by_key(|(sender, _)| *sender);
+
+        self.global
+            .iter()
+            .map(|bucket| ("global", bucket))
+            .chain(
+                senders
+                    .into_iter()
+                    .map(|(sender, bucket)| (sender.as_str(), bucket)),
+            )
+            .map(|(scope, bucket)| {
+                let mut bucket = bucket.lock().expect("Rate limit lock poisoned");
+                bucket.refill(now);
+                bucket.status(scope)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MailSender;
+
+    #[test]
+    fn test_rate_limiter() {
+        let config = MailerConfig {
+            rate_limit: Some(RateLimitConfig {
+                messages_per_minute: 60,
+                burst: Some(3),
+            }),
+            senders: vec![
+                MailSender {
+                    email: "test@test.com".to_string(),
+                    rate_limit: Some(RateLimitConfig {
+                        messages_per_minute: 30,
+                        burst: Some(1),
+                    }),
+                    ..Default::default()
+                },
+                MailSender {
+                    email: "test2@test.com".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let limiter = RateLimiter::with_start(&config, start);
+
+        // The sender limit is reached first
+        assert!(limiter.try_acquire("Test@test.com", start).is_zero());
+        assert_eq!(
+            limiter.try_acquire("test@test.com", start),
+            Duration::from_secs(2)
+        );
+
+        // The other sender is only limited globally
+        assert!(limiter.try_acquire("test2@test.com", start).is_zero());
+        assert!(limiter.try_acquire("test2@test.com", start).is_zero());
+        assert_eq!(
+            limiter.try_acquire("test2@test.com", start),
+            Duration::from_secs(1)
+        );
+
+        // A waiting sender takes nothing from the global bucket
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            limiter.try_acquire("test@test.com", later),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            limiter.status_at(later),
+            vec![
+                RateLimitStatus {
+                    scope: "global".to_string(),
+                    messages_per_minute: 60,
+                    burst: 3,
+                    remaining: 1,
+                    full_in_secs: 2,
+                },
+                RateLimitStatus {
+                    scope: "test@test.com".to_string(),
+                    messages_per_minute: 30,
+                    burst: 1,
+                    remaining: 0,
+                    full_in_secs: 1,
+                },
+            ]
+        );
+        assert!(limiter.try_acquire("test2@test.com", later).is_zero());
+    }
+}
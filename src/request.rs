@@ -4,7 +4,13 @@ use rmcp::schemars::{self, Schema, schema_for};
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, JsonSchema)]
+use crate::{
+    error::{MailerError, new_rmcp_error},
+    model::{email_record::EmailStatus, email_stats::EmailStatsDimension},
+    pagination::{DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE, SortOrder, decode_cursor},
+};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[schemars(description = "Request to send an email to one or more recipients.")]
 pub struct SendEmailRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -20,6 +26,98 @@ pub struct SendEmailRequest {
     pub subject: String,
     #[schemars(description = "Body of the email.")]
     pub body: String,
+    /// Threading headers, set when replying to a previous email.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub thread: Option<ThreadHeaders>,
+    /// One-click unsubscribe link, set when sending to a group member.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub unsubscribe_url: Option<String>,
+}
+
+/// The `In-Reply-To` and `References` headers of a reply.
+#[derive(Debug, Clone)]
+pub struct ThreadHeaders {
+    pub in_reply_to: String,
+    pub references: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to reply to a previously sent email, keeping the reply in the same thread."
+)]
+pub struct ReplyToEmailRequest {
+    #[schemars(description = "The id of the email record to reply to.")]
+    pub email_id: i32,
+    #[schemars(description = "Body of the reply.")]
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address. If not provided, the sender of the original email will be used."
+    )]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional list of recipient email addresses. If not provided, the recipients of the original email will be used."
+    )]
+    pub to: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Whether to quote the original email below the reply. Defaults to false."
+    )]
+    pub quote_original: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the sent emails that got no reply.")]
+pub struct GetUnansweredEmailsRequest {
+    #[schemars(description = "Only list the emails sent at least this many days ago.")]
+    pub days: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional sender email address to filter the emails by.")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional maximum number of emails, oldest first. Defaults to 50.")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the bounces of the sent emails.")]
+pub struct GetBouncesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional email address to filter the bounces by.")]
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub page: PageRequest,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to apply the configured retention rules to the email history.")]
+pub struct PurgeHistoryRequest {
+    #[schemars(
+        description = "If true, only count the records each rule would purge, without deleting anything."
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to get the raw message of a sent email.")]
+pub struct GetRawEmailRequest {
+    #[schemars(description = "The id of the email record.")]
+    pub email_id: i32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to re-deliver a sent email, unchanged.")]
+pub struct ResendEmailRequest {
+    #[schemars(description = "The id of the email record to resend.")]
+    pub email_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional list of recipient email addresses. If not provided, the recipients of the original email will be used."
+    )]
+    pub to: Option<Vec<String>>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -40,6 +138,49 @@ pub struct SendGroupEmailRequest {
     pub body: String,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to send an email to the recipients whose tags match a tag expression."
+)]
+pub struct SendTaggedEmailRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address. If not provided, the default sender will be used."
+    )]
+    pub from: Option<String>,
+    #[schemars(
+        description = "The tag expression selecting the recipients, e.g. `customer AND (emea OR apac) AND NOT churned`. NOT binds tighter than AND, which binds tighter than OR."
+    )]
+    pub tag_expression: String,
+    #[schemars(description = "Optional reply-to email address.")]
+    pub reply_to: Option<String>,
+    #[schemars(description = "Subject of the email.")]
+    pub subject: String,
+    #[schemars(description = "Body of the email.")]
+    pub body: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to add tags to, or remove tags from, recipients in bulk.")]
+pub struct TagRecipientsRequest {
+    #[schemars(description = "The email addresses of the recipients in the phone book.")]
+    pub emails: Vec<String>,
+    #[schemars(
+        description = "The tags. Letters, digits, '_', '-', '.' and ':' are allowed, and tags are case-insensitive."
+    )]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the addresses that unsubscribed from the group emails.")]
+pub struct ListUnsubscribesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional group name. If provided, only the addresses that unsubscribed from this group are listed."
+    )]
+    pub group_name: Option<String>,
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(description = "Request to send an email using a predefined template with dynamic data.")]
 pub struct SendEmailWithTemplateRequest {
@@ -62,6 +203,202 @@ pub struct SendEmailWithTemplateRequest {
     pub template_data: HashMap<String, String>,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to preview an email without sending it. Accepts any kind of send request."
+)]
+pub enum PreviewEmailRequest {
+    #[schemars(description = "Preview a simple email to one or more recipients.")]
+    Email(SendEmailRequest),
+    #[schemars(description = "Preview an email to all members of a group.")]
+    Group(SendGroupEmailRequest),
+    #[schemars(description = "Preview an email to the recipients matching a tag expression.")]
+    Tagged(SendTaggedEmailRequest),
+    #[schemars(description = "Preview an email rendered from a template.")]
+    Template(SendEmailWithTemplateRequest),
+    #[schemars(description = "Preview an invitation for a calendar event.")]
+    EventInvitation(SendEventInvitationRequest),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to check the connectivity and authentication of a sender's SMTP server without sending any email."
+)]
+pub struct TestSmtpConnectionRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address. If not provided, the default sender will be used."
+    )]
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to get the DNS TXT records to publish for the configured DKIM keys."
+)]
+pub struct GetDkimDnsRecordRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender domain. If not provided, the records of all configured domains are returned."
+    )]
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the folders of a mailbox.")]
+pub struct ListMailFoldersRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address whose IMAP account to read. If not provided, the first account is used."
+    )]
+    pub account: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to search the messages of a mailbox folder.")]
+pub struct SearchMessagesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address whose IMAP account to read. If not provided, the first account is used."
+    )]
+    pub account: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional folder to search. Defaults to INBOX.")]
+    pub folder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional text the From header must contain.")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional text the subject must contain.")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional date of the oldest messages to list. Should be in ISO 8601 format (e.g., \"2023-10-01\")."
+    )]
+    pub since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional date to list the messages received before. Should be in ISO 8601 format (e.g., \"2023-10-31\")."
+    )]
+    pub before: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional flag to only list the unread messages. Defaults to false.")]
+    pub unread_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional maximum number of messages, newest first. Defaults to 20.")]
+    pub limit: Option<u32>,
+}
+
+impl SearchMessagesRequest {
+    /// Validates the request by checking that the dates are in the correct format.
+    pub fn validate_schema(&self) -> Option<Schema> {
+        if [&self.since, &self.before]
+            .into_iter()
+            .flatten()
+            .any(|date| parse_date(date).is_none())
+        {
+            return Some(schema_for!(SearchMessagesRequest));
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to fetch a message of a mailbox folder.")]
+pub struct FetchMessageRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address whose IMAP account to read. If not provided, the first account is used."
+    )]
+    pub account: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional folder of the message. Defaults to INBOX.")]
+    pub folder: Option<String>,
+    #[schemars(description = "The UID of the message, as listed by the search.")]
+    pub uid: u32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to mark messages of a mailbox folder as read or flagged.")]
+pub struct MarkMessagesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sender email address whose IMAP account to read. If not provided, the first account is used."
+    )]
+    pub account: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional folder of the messages. Defaults to INBOX.")]
+    pub folder: Option<String>,
+    #[schemars(description = "The UIDs of the messages, as listed by the search.")]
+    pub uids: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional flag to mark the messages as read (true) or unread (false)."
+    )]
+    pub read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional flag to flag (true) or unflag (false) the messages.")]
+    pub flagged: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the messages received by the inbound webhook.")]
+pub struct ListInboundMessagesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional sender email address to filter the messages by.")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional recipient email address to filter the messages by, matching only the messages sent by this known recipient."
+    )]
+    pub recipient: Option<String>,
+    #[serde(flatten)]
+    pub page: PageRequest,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to read a message received by the inbound webhook.")]
+pub struct ReadInboundMessageRequest {
+    #[schemars(description = "The id of the inbound message.")]
+    pub message_id: i32,
+    #[serde(default)]
+    #[schemars(description = "If true, return the raw MIME message instead of the parsed one.")]
+    pub raw: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to import the OpenPGP public key of a recipient, used to encrypt the emails sent to them."
+)]
+pub struct ImportPgpKeyRequest {
+    #[schemars(description = "The email address of the recipient owning the key.")]
+    pub email: String,
+    #[schemars(description = "The ASCII armored OpenPGP public key.")]
+    pub public_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Whether to refuse sending unencrypted emails to the recipient. Defaults to false."
+    )]
+    pub encryption_required: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to import the S/MIME certificate of a recipient, used to encrypt the emails sent to them."
+)]
+pub struct ImportSmimeCertificateRequest {
+    #[schemars(description = "The email address of the recipient owning the certificate.")]
+    pub email: String,
+    #[schemars(description = "The PEM encoded X.509 certificate.")]
+    pub certificate: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Whether to refuse sending unencrypted emails to the recipient. Defaults to false."
+    )]
+    pub encryption_required: Option<bool>,
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(
     description = "Request to manage groups, including adding, removing, and updating groups."
@@ -101,6 +438,63 @@ pub struct RemoveGroupRequest {
     pub name: String,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to manage the suppression list of the addresses and domains that must never be emailed."
+)]
+pub enum ManageSuppressionRequest {
+    #[schemars(description = "Request to suppress an address or a domain.")]
+    Add(AddSuppressionRequest),
+    #[schemars(description = "Request to lift the suppression of an address or a domain.")]
+    Remove(RemoveSuppressionRequest),
+    #[schemars(description = "Request to list the suppressed addresses and domains.")]
+    List(ListSuppressionsRequest),
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to suppress an address or a domain.")]
+pub struct AddSuppressionRequest {
+    #[schemars(
+        description = "The email address, or the domain, to suppress. A domain also suppresses its subdomains."
+    )]
+    pub entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional reason of the suppression, e.g. a legal hold or a complaint."
+    )]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to lift the suppression of an address or a domain.")]
+pub struct RemoveSuppressionRequest {
+    #[schemars(description = "The suppressed email address or domain.")]
+    pub entry: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to list the suppressed addresses and domains.")]
+pub struct ListSuppressionsRequest {
+    #[serde(flatten)]
+    pub page: PageRequest,
+}
+
+impl AddSuppressionRequest {
+    /// Returns the lowercased entry if it is a valid address or domain.
+    pub fn normalized_entry(&self) -> Option<String> {
+        let entry = self.entry.trim().trim_start_matches('@').to_lowercase();
+        if entry.contains('@') {
+            return entry.parse::<lettre::Address>().is_ok().then_some(entry);
+        }
+
+        let is_domain = entry.contains('.')
+            && entry.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        is_domain.then_some(entry)
+    }
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(
     description = "Request to manage recipients, including adding, removing, and updating recipients."
@@ -123,6 +517,11 @@ pub struct AddRecipientRequest {
     pub name: String,
     #[schemars(description = "The email address of the recipient to be added.")]
     pub email: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional free-form attributes of the recipient, e.g. company or first_name. Templates sent to this recipient alone can use them as placeholders."
+    )]
+    pub attributes: HashMap<String, String>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -138,15 +537,35 @@ pub struct UpdateRecipientRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(description = "The new email address for the recipient, if it is being updated.")]
     pub new_email: Option<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Attributes of the recipient to add or update. An empty value removes the attribute."
+    )]
+    pub attributes: HashMap<String, String>,
 }
 
 impl UpdateRecipientRequest {
     pub fn validate_schema(&self) -> Option<Schema> {
-        (self.new_name.is_none() && self.new_email.is_none())
+        (self.new_name.is_none() && self.new_email.is_none() && self.attributes.is_empty())
             .then(|| schema_for!(UpdateRecipientRequest))
     }
 }
 
+/// Returns the names of the attributes that can't be used as template
+/// placeholders.
+pub fn invalid_attribute_names(attributes: &HashMap<String, String>) -> Vec<&str> {
+    attributes
+        .keys()
+        .filter(|name| {
+            name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+        })
+        .map(String::as_str)
+        .collect()
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(description = "Request to remove an existing recipient by their email address.")]
 pub struct RemoveRecipientRequest {
@@ -165,6 +584,53 @@ pub struct AddRecipientToGroupRequest {
     pub email: String,
 }
 
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(description = "Pagination of a list. Omit it to get the first page.")]
+pub struct PageRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional cursor to continue from, as returned in the `next_cursor` of the previous page."
+    )]
+    pub cursor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional maximum number of items in the page. Defaults to 50, at most 500."
+    )]
+    pub limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional sort order by creation: \"asc\" (oldest first, the default) or \"desc\" (newest first)."
+    )]
+    pub order: Option<SortOrder>,
+}
+
+impl PageRequest {
+    /// Returns the page size, capped at `MAX_PAGE_SIZE`.
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .map_or(DEFAULT_PAGE_SIZE, i64::from)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order.unwrap_or_default()
+    }
+
+    /// Decodes the cursor into the list kind and the id to continue after.
+    pub fn cursor(&self) -> Result<Option<(String, i32)>, MailerError> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+
+    /// Returns the id to continue after, checking that the cursor belongs to the list `kind`.
+    pub fn after_id(&self, kind: &str) -> Result<Option<i32>, MailerError> {
+        match self.cursor()? {
+            Some((cursor_kind, id)) if cursor_kind == kind => Ok(Some(id)),
+            Some(_) => Err(new_rmcp_error("The cursor belongs to another list")),
+            None => Ok(None),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(description = "Request to get the an email template by its unique name.")]
 pub struct GetEmailTemplatesRequest {
@@ -234,6 +700,14 @@ pub struct GetEmailHistoryRequest {
     #[schemars(description = "Optional email address to filter the email history by recipient.")]
     pub to: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional sender email address to filter the email history by.")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional delivery status to filter the email history by: \"sent\", \"failed\" or \"queued\"."
+    )]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(
         description = "Optional start date to filter the email history. Should be in ISO 8601 format (e.g., \"2023-10-01T00:00:00\")."
     )]
@@ -243,13 +717,27 @@ pub struct GetEmailHistoryRequest {
         description = "Optional end date to filter the email history. Should be in ISO 8601 format (e.g., \"2023-10-31T23:59:59\")."
     )]
     pub end_date: Option<String>,
+    #[serde(flatten)]
+    pub page: PageRequest,
 }
 
 impl GetEmailHistoryRequest {
     /// Validates the request by checking that at least one field is provided and that the provided fields are in the correct format.
     pub fn validate_schema(&self) -> Option<Schema> {
         // If all fields are None, the request is considered invalid
-        if self.to.is_none() && self.start_date.is_none() && self.end_date.is_none() {
+        if self.to.is_none()
+            && self.from.is_none()
+            && self.status.is_none()
+            && self.start_date.is_none()
+            && self.end_date.is_none()
+        {
+            return Some(schema_for!(GetEmailHistoryRequest));
+        }
+
+        // Validate the 'status' field if it is provided
+        if let Some(status) = &self.status
+            && EmailStatus::try_from(status.as_str()).is_err()
+        {
             return Some(schema_for!(GetEmailHistoryRequest));
         }
 
@@ -269,6 +757,90 @@ impl GetEmailHistoryRequest {
     }
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(description = "Request to aggregate the email history into statistics.")]
+pub struct GetEmailStatsRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional list of what to aggregate by: \"day\", \"week\", \"month\", \"recipient\", \"group\", \"sender\" or \"template\". Defaults to all of them."
+    )]
+    pub group_by: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional start date of the emails to count. Should be in ISO 8601 format (e.g., \"2023-10-01T00:00:00\")."
+    )]
+    pub start_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional end date of the emails to count. Should be in ISO 8601 format (e.g., \"2023-10-31T23:59:59\")."
+    )]
+    pub end_date: Option<String>,
+}
+
+impl GetEmailStatsRequest {
+    /// Validates the aggregation dimensions and the date range.
+    pub fn validate_schema(&self) -> Option<Schema> {
+        if let Some(group_by) = &self.group_by
+            && group_by
+                .iter()
+                .any(|dimension| EmailStatsDimension::try_from(dimension.as_str()).is_err())
+        {
+            return Some(schema_for!(GetEmailStatsRequest));
+        }
+
+        if !is_valid_start_end_time(self.start_date.as_ref(), self.end_date.as_ref()) {
+            return Some(schema_for!(GetEmailStatsRequest));
+        }
+
+        None
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Request to search the email history by text, optionally combined with filters."
+)]
+pub struct SearchEmailsRequest {
+    #[schemars(
+        description = "Full-text query over the subject, body, sender and recipients. Supports AND, OR, NOT, \"exact phrases\", prefix* matching and column filters such as subject:invoice."
+    )]
+    pub query: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Optional sender email address to filter the results by.")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional group name, to only search the emails sent to its members."
+    )]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional start date to filter the results. Should be in ISO 8601 format (e.g., \"2023-10-01T00:00:00\")."
+    )]
+    pub start_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional end date to filter the results. Should be in ISO 8601 format (e.g., \"2023-10-31T23:59:59\")."
+    )]
+    pub end_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(description = "Maximum number of results. Defaults to 20.")]
+    pub limit: Option<u32>,
+}
+
+impl SearchEmailsRequest {
+    /// Validates the request by checking that the query isn't empty and that the dates are in the correct format.
+    pub fn validate_schema(&self) -> Option<Schema> {
+        if self.query.trim().is_empty()
+            || !is_valid_start_end_time(self.start_date.as_ref(), self.end_date.as_ref())
+        {
+            return Some(schema_for!(SearchEmailsRequest));
+        }
+
+        None
+    }
+}
+
 /// Validates that the provided start and end dates are in the correct format and that the start date is not after the end date.
 pub(crate) fn is_valid_start_end_time(
     start_date: Option<&String>,
@@ -335,6 +907,11 @@ pub(crate) fn parse_start_end_time(
     }
 }
 
+/// Parses an ISO 8601 date, e.g. `2023-10-01`.
+pub(crate) fn parse_date(date: &str) -> Option<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 #[schemars(description = "Request to create a new calendar event with the specified details.")]
 pub struct CreateEventRequest {
@@ -368,6 +945,8 @@ pub struct ListEventsRequest {
         description = "Optional end date to filter events. Should be in ISO 8601 format (e.g., \"2023-10-31T23:59:59\")."
     )]
     pub end_date: Option<String>,
+    #[serde(flatten)]
+    pub page: PageRequest,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -405,4 +984,9 @@ pub struct SendEventInvitationTo {
         description = "Individuals to send the invitation to. The string is the individual's user name."
     )]
     pub individuals: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(
+        description = "Optional tag expression selecting more recipients, e.g. `customer AND NOT churned`."
+    )]
+    pub tag_expression: Option<String>,
 }
@@ -1,6 +1,6 @@
-use std::{sync::Arc, vec};
+use std::{collections::HashMap, sync::Arc, vec};
 
-use lettre::Message;
+use lettre::{Address, message::Mailbox};
 use rmcp::{
     ServerHandler,
     handler::server::{tool::ToolRouter, wrapper::Parameters},
@@ -10,35 +10,100 @@ use rmcp::{
 use tokio::sync::{Mutex, MutexGuard};
 
 use crate::{
-    config::Config,
+    config::{Config, SendPolicy, UnsubscribeConfig},
     database::Database,
-    error::new_rmcp_error,
-    mailer::Mailer,
-    model::{recipient::Recipient, template::Template},
+    error::{MailerError, new_rmcp_error},
+    imap::{DEFAULT_FOLDER, ImapClient, MessageQuery},
+    mailer::{Mailer, SentEmail},
+    model::{
+        email_record::{EmailRecord, EmailStatus, NewEmailRecord},
+        email_stats::EmailStatsDimension,
+        event::Event,
+        group::Group,
+        recipient::Recipient,
+        suppression::NewSuppression,
+        template::Template,
+    },
+    pagination::{DEFAULT_PAGE_SIZE, Page, encode_cursor, split_page},
+    pgp, policy,
     request::{
-        AddRecipientToGroupRequest, CreateEventRequest, GetEmailHistoryRequest,
-        GetEmailTemplatesRequest, ListEventsRequest, ManageGroupsRequest, ManageRecipientsRequest,
-        ManageTemplatesRequest, SendEmailRequest, SendEmailWithTemplateRequest,
-        SendEventInvitationRequest, SendGroupEmailRequest, is_valid_start_end_time,
-        parse_start_end_time,
+        AddRecipientToGroupRequest, CreateEventRequest, FetchMessageRequest, GetBouncesRequest,
+        GetDkimDnsRecordRequest, GetEmailHistoryRequest, GetEmailStatsRequest,
+        GetEmailTemplatesRequest, GetRawEmailRequest, GetUnansweredEmailsRequest,
+        ImportPgpKeyRequest, ImportSmimeCertificateRequest, ListEventsRequest,
+        ListInboundMessagesRequest, ListMailFoldersRequest, ListSuppressionsRequest,
+        ListUnsubscribesRequest, ManageGroupsRequest, ManageRecipientsRequest,
+        ManageSuppressionRequest, ManageTemplatesRequest, MarkMessagesRequest, PageRequest,
+        PreviewEmailRequest, PurgeHistoryRequest, ReadInboundMessageRequest, ReplyToEmailRequest,
+        ResendEmailRequest, SearchEmailsRequest, SearchMessagesRequest, SendEmailRequest,
+        SendEmailWithTemplateRequest, SendEventInvitationRequest, SendGroupEmailRequest,
+        SendTaggedEmailRequest, TagRecipientsRequest, TestSmtpConnectionRequest, ThreadHeaders,
+        invalid_attribute_names, is_valid_start_end_time, parse_date, parse_start_end_time,
     },
+    smime,
+    tag_expression::{TagExpression, normalize_tag},
+    unsubscribe,
 };
 
+/// Where a recorded email comes from.
+#[derive(Debug, Clone, Copy)]
+enum EmailOrigin<'a> {
+    /// Written from scratch.
+    New,
+    /// Rendered from a template.
+    Template(&'a Template),
+    /// A reply to a recorded email.
+    Reply(&'a EmailRecord),
+    /// The raw message of a recorded email, delivered again.
+    Resend(&'a EmailRecord),
+}
+
+/// The recipients and tags of a bulk tag request.
+#[derive(Debug)]
+struct ResolvedTagRequest {
+    recipient_ids: Vec<i32>,
+    /// Normalized and deduplicated.
+    tags: Vec<String>,
+    /// The addresses that aren't known recipients.
+    unknown: Vec<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct MailerService {
     // Required by rmcp
     tool_router: ToolRouter<Self>,
     mailer: Mailer,
     db: Arc<Mutex<Database>>,
+    /// IMAP clients of the senders with an IMAP account.
+    imap_clients: Arc<Vec<ImapClient>>,
+    unsubscribe_config: Option<UnsubscribeConfig>,
+    send_policy: Option<SendPolicy>,
 }
 
 #[tool_router]
 impl MailerService {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config) -> Result<Self, MailerError> {
+        let mailer = Mailer::new(config.mailer_config.clone())?;
+        Ok(Self::with_mailer(config, mailer))
+    }
+
+    /// Creates the service with an existing mailer, so that its pooled SMTP
+    /// connections can be shared between sessions.
+    pub fn with_mailer(config: Config, mailer: Mailer) -> Self {
+        let imap_clients = config
+            .mailer_config
+            .senders
+            .iter()
+            .filter_map(ImapClient::for_sender)
+            .collect();
+
         Self {
             tool_router: Self::tool_router(),
-            mailer: Mailer::new(config.mailer_config),
+            mailer,
             db: Arc::new(Mutex::new(Database::new(config.db_config))),
+            imap_clients: Arc::new(imap_clients),
+            unsubscribe_config: config.unsubscribe_config,
+            send_policy: config.mailer_config.policy,
         }
     }
 
@@ -47,125 +112,758 @@ impl MailerService {
         &self,
         Parameters(email_request): Parameters<SendEmailRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
-        let sent_message = self.mailer.send(&email_request).await?;
+        let (sent_email, _) = self
+            .send_and_record(email_request, EmailOrigin::New)
+            .await?;
+
+        Self::delivery_result(&sent_email, "Email sent successfully!")
+    }
+
+    #[tool(
+        description = "Send an email to a group, skipping the members who unsubscribed. With unsubscribe links configured, each member gets a message of their own with a one-click unsubscribe link"
+    )]
+    async fn send_email_to_group(
+        &self,
+        Parameters(email_request): Parameters<SendGroupEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (group, request, skipped) =
+            Self::resolve_group_request(&mut self.db.lock().await, email_request)?;
+        let success_message =
+            format!("Email sent to group successfully! Skipped {skipped} unsubscribed members.");
+        if request.to.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "No members to send the email to. Skipped {skipped} unsubscribed members."
+            ))]));
+        }
+
+        // Checked for the whole group, even when each member gets a message of their own
+        self.check_send_policy(&request.to)?;
+
+        let Some(unsubscribe_config) = &self.unsubscribe_config else {
+            let (sent_email, _) = self.send_and_record(request, EmailOrigin::New).await?;
+
+            return Self::delivery_result(&sent_email, &success_message);
+        };
+
+        self.send_with_unsubscribe_links(
+            request,
+            unsubscribe_config,
+            Some(group.id),
+            "members",
+            &success_message,
+        )
+        .await
+    }
+
+    #[tool(
+        description = "Send an email to the recipients whose tags match a tag expression, e.g. `customer AND (emea OR apac) AND NOT churned`, resolved at send time. Recipients who unsubscribed from all group emails are skipped. With unsubscribe links configured, each recipient gets a message of their own with a one-click link unsubscribing from all group emails"
+    )]
+    async fn send_email_to_tag_expression(
+        &self,
+        Parameters(email_request): Parameters<SendTaggedEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (request, skipped) =
+            Self::resolve_tagged_request(&mut self.db.lock().await, email_request)?;
+        if request.to.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "No recipients match the tag expression. Skipped {skipped} unsubscribed recipients."
+            ))]));
+        }
 
+        let success_message = format!(
+            "Email sent to {} tagged recipients successfully! Skipped {skipped} unsubscribed recipients.",
+            request.to.len()
+        );
+        let Some(unsubscribe_config) = &self.unsubscribe_config else {
+            let (sent_email, _) = self.send_and_record(request, EmailOrigin::New).await?;
+
+            return Self::delivery_result(&sent_email, &success_message);
+        };
+
+        // Checked for all of them, even when each gets a message of their own
+        self.check_send_policy(&request.to)?;
+
+        // Not sent to a group, so the link unsubscribes from all group emails
+        self.send_with_unsubscribe_links(
+            request,
+            unsubscribe_config,
+            None,
+            "tagged recipients",
+            &success_message,
+        )
+        .await
+    }
+
+    #[tool(description = "Add tags to recipients in bulk")]
+    async fn tag_recipients(
+        &self,
+        Parameters(tag_request): Parameters<TagRecipientsRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
         let mut db = self.db.lock().await;
-        // Save the recipient record in the database
-        let recipient_ids = Self::save_recipient_record(&mut db, &sent_message)
-            .expect("Failed to save recipient record");
+        let ResolvedTagRequest {
+            recipient_ids,
+            tags,
+            unknown,
+        } = Self::resolve_tag_request(&mut db, tag_request)?;
+
+        let added = db.tag_recipients(&recipient_ids, &tags)?;
+
+        let mut result = vec![Content::text(format!(
+            "Added {added} tags to {} recipients successfully!",
+            recipient_ids.len()
+        ))];
+        if !unknown.is_empty() {
+            result.push(Content::text(format!(
+                "Recipients not found: {}",
+                unknown.join(", ")
+            )));
+        }
 
-        // Save email record with recipient IDs
-        Self::save_email_record_with_recipient_ids(
-            &mut db,
-            email_request.subject,
-            email_request.body,
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(description = "Remove tags from recipients in bulk")]
+    async fn untag_recipients(
+        &self,
+        Parameters(tag_request): Parameters<TagRecipientsRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let mut db = self.db.lock().await;
+        let ResolvedTagRequest {
             recipient_ids,
-        )
-        .expect("Failed to save email record");
+            tags,
+            unknown,
+        } = Self::resolve_tag_request(&mut db, tag_request)?;
+
+        let removed = db.untag_recipients(&recipient_ids, &tags)?;
+
+        let mut result = vec![Content::text(format!(
+            "Removed {removed} tags from {} recipients successfully!",
+            recipient_ids.len()
+        ))];
+        if !unknown.is_empty() {
+            result.push(Content::text(format!(
+                "Recipients not found: {}",
+                unknown.join(", ")
+            )));
+        }
 
-        Ok(CallToolResult::success(vec![Content::text(
-            "Email sent successfully!",
-        )]))
+        Ok(CallToolResult::success(result))
     }
 
-    #[tool(description = "Send an email to a group")]
-    async fn send_email_to_group(
+    #[tool(description = "List the recipient tags, with their number of recipients")]
+    async fn get_tags(&self) -> Result<CallToolResult, rmcp::ErrorData> {
+        let tags = self.db.lock().await.list_tags()?;
+
+        if tags.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text("No tags")]));
+        }
+
+        Ok(CallToolResult::success(
+            tags.into_iter()
+                .map(|(tag, count)| Content::text(format!("Tag: {tag} ({count} recipients)")))
+                .collect(),
+        ))
+    }
+
+    #[tool(
+        description = "List the addresses that unsubscribed from the group emails, optionally only for a group. Addresses without a group unsubscribed from all groups"
+    )]
+    async fn list_unsubscribes(
         &self,
-        Parameters(email_request): Parameters<SendGroupEmailRequest>,
+        Parameters(ListUnsubscribesRequest { group_name }): Parameters<ListUnsubscribesRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
         let mut db = self.db.lock().await;
-        let group = db
-            .find_group_by_name(email_request.group_name.clone())
-            .map_err(|_| new_rmcp_error("Group not found"))?;
+        let group_id = match group_name {
+            Some(group_name) => Some(
+                db.find_group_by_name(group_name)
+                    .map_err(|_| new_rmcp_error("Group not found"))?
+                    .id,
+            ),
+            None => None,
+        };
 
-        let recipients = db.find_recipients_by_group_id(group.id)?;
-        let to = recipients.into_iter().map(|r| r.email).collect::<Vec<_>>();
+        let unsubscribes = db.list_unsubscribes(group_id)?;
+        if unsubscribes.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No unsubscribed addresses",
+            )]));
+        }
+
+        let result = unsubscribes
+            .into_iter()
+            .map(|u| Content::text(format!("Unsubscribe: {u:?}")))
+            .collect::<Vec<_>>();
+
+        Ok(CallToolResult::success(result))
+    }
 
-        let request = SendEmailRequest {
-            from: email_request.from,
-            to,
-            reply_to: email_request.reply_to,
-            subject: email_request.subject,
-            body: email_request.body,
+    #[tool(description = "Send an email with template")]
+    async fn send_email_with_template(
+        &self,
+        Parameters(email_request): Parameters<SendEmailWithTemplateRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (template, request) =
+            Self::resolve_template_request(&mut self.db.lock().await, email_request)?;
+
+        let (sent_email, _) = self
+            .send_and_record(request, EmailOrigin::Template(&template))
+            .await?;
+
+        Self::delivery_result(&sent_email, "Email sent with template successfully!")
+    }
+
+    #[tool(
+        description = "Reply to a previously sent email by its email record id. The reply is sent in the same thread, to the original recipients unless others are given"
+    )]
+    async fn reply_to_email(
+        &self,
+        Parameters(reply_request): Parameters<ReplyToEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (original, email_request) = {
+            let mut db = self.db.lock().await;
+            let original = db
+                .find_email_record_by_id(reply_request.email_id)
+                .map_err(|_| new_rmcp_error("Email record not found"))?;
+
+            let to = match reply_request.to {
+                Some(to) => to,
+                None => db
+                    .find_recipients_by_email_record_id(original.id)?
+                    .into_iter()
+                    .map(|r| r.email)
+                    .collect(),
+            };
+
+            let subject = if original.subject.to_lowercase().starts_with("re:") {
+                original.subject.clone()
+            } else {
+                format!("Re: {}", original.subject)
+            };
+
+            let mut body = reply_request.body;
+            if reply_request.quote_original.unwrap_or(false) {
+                let quoted = original
+                    .body
+                    .lines()
+                    .map(|line| format!("> {line}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                body = format!(
+                    "{body}\n\nOn {}, {} wrote:\n{quoted}",
+                    original.sent_at, original.sender
+                );
+            }
+
+            let email_request = SendEmailRequest {
+                from: reply_request
+                    .from
+                    .or_else(|| Some(original.sender.clone()).filter(|s| !s.is_empty())),
+                to,
+                reply_to: None,
+                subject,
+                body,
+                thread: Some(ThreadHeaders {
+                    in_reply_to: original.message_id.clone(),
+                    references: Self::thread_references(&mut db, &original)?,
+                }),
+                unsubscribe_url: None,
+            };
+            (original, email_request)
+        };
+
+        let (sent_email, _) = self
+            .send_and_record(email_request, EmailOrigin::Reply(&original))
+            .await?;
+
+        Self::delivery_result(&sent_email, "Reply sent successfully!")
+    }
+
+    #[tool(description = "Get the raw RFC 5322 message of a sent email, exactly as it was sent")]
+    async fn get_raw_email(
+        &self,
+        Parameters(GetRawEmailRequest { email_id }): Parameters<GetRawEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let raw_email = {
+            let mut db = self.db.lock().await;
+            db.find_raw_email(email_id)
+                .map_err(|_| new_rmcp_error("Raw email not found, it may have expired"))?
         };
+        let raw_message = raw_email.decompress()?;
 
-        let sent_message = self.mailer.send(&request).await?;
+        Ok(CallToolResult::success(vec![Content::text(
+            String::from_utf8_lossy(&raw_message).to_string(),
+        )]))
+    }
 
+    #[tool(
+        description = "Re-deliver a sent email unchanged, to its original recipients unless others are given. The resend is recorded as a new email linked to the original, with a Message-ID of its own. An encrypted email can only be resent to its original recipients"
+    )]
+    async fn resend_email(
+        &self,
+        Parameters(ResendEmailRequest { email_id, to }): Parameters<ResendEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (original, raw_email, original_to, to, known_recipients, suppressions) = {
+            let mut db = self.db.lock().await;
+            let original = db
+                .find_email_record_by_id(email_id)
+                .map_err(|_| new_rmcp_error("Email record not found"))?;
+            let raw_email = db
+                .find_raw_email(email_id)
+                .map_err(|_| new_rmcp_error("Raw email not found, it may have expired"))?;
+
+            let original_to = db
+                .find_recipients_by_email_record_id(original.id)?
+                .into_iter()
+                .map(|r| r.email)
+                .collect::<Vec<_>>();
+            let to = to.unwrap_or_else(|| original_to.clone());
+            let known_recipients = Self::find_known_recipients(&mut db, &to);
+            let suppressions = db.list_suppressions(&Page::default())?;
+            (
+                original,
+                raw_email,
+                original_to,
+                to,
+                known_recipients,
+                suppressions,
+            )
+        };
+        self.check_send_policy(&to)?;
+
+        // The database isn't locked while waiting for the rate limits and the delivery
+        let sent_email = self
+            .mailer
+            .resend(
+                raw_email.decompress()?,
+                &original.sender,
+                &to,
+                &original_to,
+                &known_recipients,
+                &suppressions,
+            )
+            .await?;
+
+        let mut db = self.db.lock().await;
         // Save the recipient record in the database
-        let recipient_ids = Self::save_recipient_record(&mut db, &sent_message)
-            .expect("Failed to save recipient record");
+        let recipient_ids = Self::save_recipient_record(&mut db, &sent_email.recipients)?;
 
         // Save email record with recipient IDs
         Self::save_email_record_with_recipient_ids(
             &mut db,
-            request.subject,
-            request.body,
+            original.subject.clone(),
+            original.body.clone(),
             recipient_ids,
-        )
-        .expect("Failed to save email record");
+            &sent_email,
+            EmailOrigin::Resend(&original),
+        )?;
+
+        Self::delivery_result(&sent_email, "Email resent successfully!")
+    }
+
+    #[tool(
+        description = "Preview an email without sending it. Renders the exact message, including groups, tags and templates, applies the send policy, suppressions and sandbox as a send would, and returns its signed headers, recipients and decoded body"
+    )]
+    async fn preview_email(
+        &self,
+        Parameters(preview_request): Parameters<PreviewEmailRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let (request, known_recipients, suppressions) = {
+            let mut db = self.db.lock().await;
+            let request = match preview_request {
+                PreviewEmailRequest::Email(request) => request,
+                PreviewEmailRequest::Group(request) => {
+                    Self::resolve_group_request(&mut db, request)?.1
+                }
+                PreviewEmailRequest::Tagged(request) => {
+                    Self::resolve_tagged_request(&mut db, request)?.0
+                }
+                PreviewEmailRequest::Template(request) => {
+                    Self::resolve_template_request(&mut db, request)?.1
+                }
+                PreviewEmailRequest::EventInvitation(request) => {
+                    Self::resolve_invitation_request(&mut db, request)?.1
+                }
+            };
+            self.check_send_policy(&request.to)?;
+            let known_recipients = Self::find_known_recipients(&mut db, &request.to);
+            let suppressions = db.list_suppressions(&Page::default())?;
+
+            (request, known_recipients, suppressions)
+        };
+
+        let prepared = self
+            .mailer
+            .preview(&request, &known_recipients, &suppressions)?;
+
+        let recipients = prepared
+            .recipients
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        let envelope_recipients = prepared
+            .envelope
+            .to()
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+        // An encrypted body is only readable by the recipients
+        let body = match prepared.text_body() {
+            Some(body) => format!("Body:\n{body}"),
+            None => format!(
+                "Body (encrypted, shown before encryption):\n{}",
+                request.body
+            ),
+        };
+
+        let mut result = vec![
+            Content::text(format!("Recipients: {}", recipients.join(", "))),
+            Content::text(format!("Delivered to: {}", envelope_recipients.join(", "))),
+            Content::text(format!("Headers:\n{}", prepared.message.headers())),
+            Content::text(body),
+        ];
+        result.extend(Self::suppressed_content(&prepared.suppressed));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Test the SMTP connection and authentication of a sender without sending an email"
+    )]
+    async fn test_smtp_connection(
+        &self,
+        Parameters(TestSmtpConnectionRequest { from }): Parameters<TestSmtpConnectionRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let is_connected = self.mailer.test_connection(from.as_deref()).await?;
+
+        if !is_connected {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(
+                "SMTP server did not respond to NOOP",
+            )));
+        }
 
         Ok(CallToolResult::success(vec![Content::text(
-            "Email sent to group successfully!",
+            "SMTP connection succeeded!",
         )]))
     }
 
-    #[tool(description = "Send an email with template")]
-    async fn send_email_with_template(
+    #[tool(description = "Get the DNS TXT records to publish for the DKIM signing keys")]
+    async fn get_dkim_dns_record(
         &self,
-        Parameters(email_request): Parameters<SendEmailWithTemplateRequest>,
+        Parameters(GetDkimDnsRecordRequest { domain }): Parameters<GetDkimDnsRecordRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
-        let mut db = self.db.lock().await;
-        let res_template = db
-            .find_template_by_name(email_request.template_name.clone())
-            .map_err(|_| new_rmcp_error("Template not found"))?;
+        let records = self.mailer.dkim_dns_records(domain.as_deref())?;
 
-        let body = res_template
-            .format(email_request.template_data.clone())
-            .map_err(|e| new_rmcp_error(&e))?;
+        if records.is_empty() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(
+                "No DKIM key configured for the domain",
+            )));
+        }
+
+        Ok(CallToolResult::success(
+            records.into_iter().map(Content::text).collect(),
+        ))
+    }
+
+    #[tool(
+        description = "Get the remaining quota of the outbound rate limits, the global one and the one of each sender. Sends wait when a quota is used up"
+    )]
+    async fn get_rate_limit_status(&self) -> Result<CallToolResult, rmcp::ErrorData> {
+        let status = self.mailer.rate_limit_status();
+
+        if status.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No rate limit configured",
+            )]));
+        }
 
-        let request = SendEmailRequest {
-            from: email_request.from,
-            to: email_request.to,
-            reply_to: email_request.reply_to,
-            subject: email_request.subject,
-            body,
+        Ok(CallToolResult::success(
+            status
+                .into_iter()
+                .map(|s| Content::text(format!("Rate limit: {s:?}")))
+                .collect(),
+        ))
+    }
+
+    #[tool(description = "List the folders of the mailbox of a sender, over IMAP")]
+    async fn list_mail_folders(
+        &self,
+        Parameters(ListMailFoldersRequest { account }): Parameters<ListMailFoldersRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let client = self.imap_client(account.as_deref())?;
+        let folders = Self::run_imap(client, |client| client.list_folders()).await?;
+
+        let result = folders
+            .into_iter()
+            .map(|folder| {
+                Content::text(format!(
+                    "Folder: {} (delimiter: {}, attributes: {})",
+                    folder.name,
+                    folder.delimiter.as_deref().unwrap_or("none"),
+                    folder.attributes.join(" ")
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Search the messages of a mailbox folder by sender, subject, date and unread state, over IMAP. Lists the newest messages first without marking them read"
+    )]
+    async fn search_messages(
+        &self,
+        Parameters(search_request): Parameters<SearchMessagesRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if let Some(schema) = search_request.validate_schema() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Invalid request: since and before must be valid dates. Schema: {}",
+                serde_json::to_string_pretty(&schema).unwrap()
+            ))));
+        }
+
+        let client = self.imap_client(search_request.account.as_deref())?;
+        let folder = search_request
+            .folder
+            .unwrap_or_else(|| DEFAULT_FOLDER.to_string());
+        let query = MessageQuery {
+            from: search_request.from,
+            subject: search_request.subject,
+            since: search_request.since.as_deref().and_then(parse_date),
+            before: search_request.before.as_deref().and_then(parse_date),
+            unread_only: search_request.unread_only.unwrap_or(false),
         };
+        let limit = search_request.limit.unwrap_or(20) as usize;
 
-        let sent_message = self.mailer.send(&request).await?;
+        let messages =
+            Self::run_imap(client, move |client| client.search(&folder, &query, limit)).await?;
 
-        // Save the recipient record in the database
-        let recipient_ids = Self::save_recipient_record(&mut db, &sent_message)
-            .expect("Failed to save recipient record");
+        if messages.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No messages found",
+            )]));
+        }
 
-        // Save email record with recipient IDs
-        Self::save_email_record_with_recipient_ids(
-            &mut db,
-            request.subject,
-            request.body,
-            recipient_ids,
-        )
-        .expect("Failed to save email record");
+        let result = messages
+            .into_iter()
+            .map(|message| {
+                Content::text(format!(
+                    "UID {}: {} | From: {} | Date: {} | Flags: {}",
+                    message.uid,
+                    message.subject,
+                    message.from,
+                    message.date.as_deref().unwrap_or("unknown"),
+                    message.flags.join(" ")
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Fetch a message of a mailbox folder over IMAP, parsed into its headers, text and HTML bodies and attachment list. Doesn't mark it read"
+    )]
+    async fn fetch_message(
+        &self,
+        Parameters(FetchMessageRequest {
+            account,
+            folder,
+            uid,
+        }): Parameters<FetchMessageRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let client = self.imap_client(account.as_deref())?;
+        let folder = folder.unwrap_or_else(|| DEFAULT_FOLDER.to_string());
+        let message = Self::run_imap(client, move |client| client.fetch(&folder, uid)).await?;
+
+        let mut result = vec![
+            Content::text(format!(
+                "UID {}: {}\nFrom: {}\nTo: {}\nDate: {}\nFlags: {}",
+                message.uid,
+                message.subject,
+                message.from,
+                message.to,
+                message.date.as_deref().unwrap_or("unknown"),
+                message.flags.join(" ")
+            )),
+            Content::text(format!("Headers:\n{}", message.headers)),
+        ];
+        if let Some(text_body) = message.text_body {
+            result.push(Content::text(format!("Text body:\n{text_body}")));
+        }
+        if let Some(html_body) = message.html_body {
+            result.push(Content::text(format!("HTML body:\n{html_body}")));
+        }
+        result.extend(message.attachments.into_iter().map(|attachment| {
+            Content::text(format!(
+                "Attachment: {} ({}, {} bytes)",
+                attachment.name, attachment.content_type, attachment.size
+            ))
+        }));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Mark messages of a mailbox folder as read or unread, flagged or unflagged, over IMAP"
+    )]
+    async fn mark_messages(
+        &self,
+        Parameters(MarkMessagesRequest {
+            account,
+            folder,
+            uids,
+            read,
+            flagged,
+        }): Parameters<MarkMessagesRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if uids.is_empty() || (read.is_none() && flagged.is_none()) {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(
+                "Invalid request: uids must not be empty, and read or flagged must be set",
+            )));
+        }
+
+        let client = self.imap_client(account.as_deref())?;
+        let folder = folder.unwrap_or_else(|| DEFAULT_FOLDER.to_string());
+        Self::run_imap(client, move |client| {
+            client.set_flags(&folder, &uids, read, flagged)
+        })
+        .await?;
 
         Ok(CallToolResult::success(vec![Content::text(
-            "Email sent with template successfully!",
+            "Messages marked successfully!",
         )]))
     }
 
     #[tool(
-        description = "Describe the phone book. It includes the information about the recipients and groups"
+        description = "Import the OpenPGP public key of a recipient. Emails are encrypted when every recipient has a key"
+    )]
+    async fn import_pgp_key(
+        &self,
+        Parameters(import_request): Parameters<ImportPgpKeyRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let fingerprint = pgp::public_key_fingerprint(&import_request.public_key)?;
+
+        let mut db = self.db.lock().await;
+        let recipient = db
+            .find_recipient_by_email(import_request.email.clone())
+            .map_err(|_| new_rmcp_error("Recipient not found"))?;
+        db.set_recipient_pgp_key(
+            recipient.id,
+            Some(import_request.public_key),
+            import_request
+                .encryption_required
+                .unwrap_or(recipient.encryption_required),
+        )?;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "PGP key {fingerprint} imported successfully!"
+        ))]))
+    }
+
+    #[tool(
+        description = "List the recipients with an OpenPGP public key and their key fingerprints"
+    )]
+    async fn list_pgp_keys(&self) -> Result<CallToolResult, rmcp::ErrorData> {
+        let recipients = {
+            let mut db = self.db.lock().await;
+            db.list_recipients(&Page::default())?
+        };
+
+        let result = recipients
+            .into_iter()
+            .filter_map(|r| {
+                let public_key = r.pgp_public_key.as_deref()?;
+                let fingerprint = pgp::public_key_fingerprint(public_key)
+                    .unwrap_or_else(|_| "invalid key".to_string());
+                Some(Content::text(format!(
+                    "Recipient: {}, fingerprint: {}, encryption required: {}",
+                    r.email, fingerprint, r.encryption_required
+                )))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Import the S/MIME certificate of a recipient. Emails are encrypted when every recipient has a certificate"
     )]
-    async fn describe_phone_book(&self) -> Result<CallToolResult, rmcp::ErrorData> {
-        let (recipients, groups) = {
+    async fn import_smime_certificate(
+        &self,
+        Parameters(import_request): Parameters<ImportSmimeCertificateRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let certificate_info = smime::certificate_info(&import_request.certificate)?;
+
+        let mut db = self.db.lock().await;
+        let recipient = db
+            .find_recipient_by_email(import_request.email.clone())
+            .map_err(|_| new_rmcp_error("Recipient not found"))?;
+        db.set_recipient_smime_certificate(
+            recipient.id,
+            Some(import_request.certificate),
+            import_request
+                .encryption_required
+                .unwrap_or(recipient.encryption_required),
+        )?;
+
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "S/MIME certificate of {} imported successfully! It expires at {}",
+            certificate_info.subject, certificate_info.expires_at
+        ))]))
+    }
+
+    #[tool(
+        description = "Describe the phone book. It includes the information about the recipients and groups, the recipients first. Returns a next_cursor when there are more entries"
+    )]
+    async fn describe_phone_book(
+        &self,
+        Parameters(page_request): Parameters<PageRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let limit = page_request.limit();
+        let order = page_request.order();
+
+        let (recipients, groups, next_cursor) = {
             let mut db = self.db.lock().await;
-            let recipients = db.list_recipients()?;
-            let groups = db.list_groups()?;
 
-            (recipients, groups)
+            // The recipients are listed first, so a group cursor skips all of them
+            let (recipients, after_group_id) = match page_request.cursor()? {
+                Some((kind, id)) if kind == "group" => (vec![], Some(id)),
+                Some((kind, _)) if kind != "recipient" => {
+                    return Err(rmcp::ErrorData::from(new_rmcp_error(
+                        "The cursor belongs to another list",
+                    )));
+                }
+                cursor => {
+                    let after_id = cursor.map(|(_, id)| id);
+                    (
+                        db.list_recipients(&Page::probe(after_id, limit, order))?,
+                        None,
+                    )
+                }
+            };
+
+            let (recipients, next_cursor) = split_page(recipients, limit, "recipient", |r| r.id);
+            let remaining = limit - recipients.len() as i64;
+            if next_cursor.is_some() {
+                (recipients, vec![], next_cursor)
+            } else if remaining == 0 {
+                // The page is full of recipients, continue with the groups if there are any
+                let has_groups = !db.list_groups(&Page::probe(None, 0, order))?.is_empty();
+                let next_cursor = recipients
+                    .last()
+                    .filter(|_| has_groups)
+                    .map(|r| encode_cursor("recipient", r.id));
+                (recipients, vec![], next_cursor)
+            } else {
+                let groups = db.list_groups(&Page::probe(after_group_id, remaining, order))?;
+                let (groups, next_cursor) = split_page(groups, remaining, "group", |g| g.id);
+                (recipients, groups, next_cursor)
+            }
         };
 
         let mut result = recipients
             .into_iter()
-            .map(|r| Content::text(format!("Recipient: {r:?}")))
+            .map(|r| Content::text(Self::describe_recipient(&r)))
             .collect::<Vec<_>>();
         result.extend(
             groups
@@ -173,6 +871,7 @@ impl MailerService {
                 .map(|g| Content::text(format!("Group: {g:?}")))
                 .collect::<Vec<_>>(),
         );
+        result.extend(Self::next_cursor_content(next_cursor));
 
         Ok(CallToolResult::success(result))
     }
@@ -214,7 +913,9 @@ impl MailerService {
         Ok(CallToolResult::success(result_message))
     }
 
-    #[tool(description = "Recipient management: add, remove, update")]
+    #[tool(
+        description = "Recipient management: add, remove, update. Recipients can have free-form attributes, used by the templates sent to them alone. Adding a removed or bounced recipient again reactivates it"
+    )]
     async fn manage_recipient(
         &self,
         Parameters(manage_recipient_request): Parameters<ManageRecipientsRequest>,
@@ -223,9 +924,19 @@ impl MailerService {
 
         let result_message = match manage_recipient_request {
             ManageRecipientsRequest::Add(add_request) => {
-                db.new_recipient(add_request.name, add_request.email)?;
-
-                vec![Content::text("Recipient added successfully!")]
+                Self::validate_attributes(&add_request.attributes)?;
+                let (recipient, message) = match db
+                    .reactivate_recipient(add_request.name.clone(), add_request.email.clone())?
+                {
+                    Some(recipient) => (recipient, "Recipient reactivated successfully!"),
+                    None => (
+                        db.new_recipient(add_request.name, add_request.email)?,
+                        "Recipient added successfully!",
+                    ),
+                };
+                db.set_recipient_attributes(recipient.id, &add_request.attributes)?;
+
+                vec![Content::text(message)]
             }
             ManageRecipientsRequest::Remove(remove_request) => {
                 let recipient_id = db
@@ -239,7 +950,7 @@ impl MailerService {
             ManageRecipientsRequest::Update(update_request) => {
                 if let Some(schema) = update_request.validate_schema() {
                     return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
-                        "Invalid request: At least one of new_name or new_email must be provided. Schema: {}",
+                        "Invalid request: At least one of new_name, new_email or attributes must be provided. Schema: {}",
                         serde_json::to_string_pretty(&schema).unwrap()
                     ))));
                 }
@@ -250,10 +961,12 @@ impl MailerService {
                         id: r.id,
                         name: update_request.new_name.unwrap_or(r.name),
                         email: update_request.new_email.unwrap_or(r.email),
-                        status: r.status,
+                        ..r
                     })
                     .map_err(|_| new_rmcp_error("Recipient not found"))?;
+                Self::validate_attributes(&update_request.attributes)?;
                 db.update_recipient(recipient.id, recipient.name, recipient.email)?;
+                db.set_recipient_attributes(recipient.id, &update_request.attributes)?;
 
                 vec![Content::text("Recipient updated successfully!")]
             }
@@ -262,6 +975,69 @@ impl MailerService {
         Ok(CallToolResult::success(result_message))
     }
 
+    #[tool(
+        description = "Manage the suppression list of the addresses and domains that must never be emailed: add, remove, list. Every send leaves out the suppressed recipients and reports them"
+    )]
+    async fn manage_suppression(
+        &self,
+        Parameters(manage_suppression_request): Parameters<ManageSuppressionRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let mut db = self.db.lock().await;
+
+        let result_message = match manage_suppression_request {
+            ManageSuppressionRequest::Add(add_request) => {
+                let entry = add_request.normalized_entry().ok_or_else(|| {
+                    new_rmcp_error("Invalid entry: expected an email address or a domain")
+                })?;
+                let added = db.add_suppression(NewSuppression {
+                    entry,
+                    reason: add_request.reason,
+                })?;
+
+                vec![Content::text(if added {
+                    "Suppression added successfully!"
+                } else {
+                    "Already suppressed"
+                })]
+            }
+            ManageSuppressionRequest::Remove(remove_request) => {
+                if !db.remove_suppression(remove_request.entry.trim())? {
+                    return Err(rmcp::ErrorData::from(new_rmcp_error(
+                        "Suppression not found",
+                    )));
+                }
+
+                vec![Content::text("Suppression removed successfully!")]
+            }
+            ManageSuppressionRequest::List(ListSuppressionsRequest { page: page_request }) => {
+                let limit = page_request.limit();
+                let page = Page::probe(
+                    page_request.after_id("suppression")?,
+                    limit,
+                    page_request.order(),
+                );
+                let suppressions = db.list_suppressions(&page)?;
+                let (suppressions, next_cursor) =
+                    split_page(suppressions, limit, "suppression", |s| s.id);
+
+                if suppressions.is_empty() {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        "No suppressed addresses or domains",
+                    )]));
+                }
+
+                let mut result = suppressions
+                    .into_iter()
+                    .map(|s| Content::text(format!("Suppression: {s:?}")))
+                    .collect::<Vec<_>>();
+                result.extend(Self::next_cursor_content(next_cursor));
+                result
+            }
+        };
+
+        Ok(CallToolResult::success(result_message))
+    }
+
     #[tool(description = "Add a recipient to the mail group")]
     async fn add_recipient_to_group(
         &self,
@@ -282,17 +1058,30 @@ impl MailerService {
         )]))
     }
 
-    #[tool(description = "Describe email templates.")]
-    async fn describe_email_template(&self) -> Result<CallToolResult, rmcp::ErrorData> {
+    #[tool(
+        description = "Describe email templates. Returns a next_cursor when there are more templates"
+    )]
+    async fn describe_email_template(
+        &self,
+        Parameters(page_request): Parameters<PageRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let limit = page_request.limit();
+        let page = Page::probe(
+            page_request.after_id("template")?,
+            limit,
+            page_request.order(),
+        );
         let templates = {
             let mut db = self.db.lock().await;
-            db.list_templates()?
+            db.list_templates(&page)?
         };
+        let (templates, next_cursor) = split_page(templates, limit, "template", |t| t.id);
 
-        let result = templates
+        let mut result = templates
             .into_iter()
             .map(|t| Content::text(format!("Template: {t:?}")))
             .collect::<Vec<_>>();
+        result.extend(Self::next_cursor_content(next_cursor));
 
         Ok(CallToolResult::success(result))
     }
@@ -317,85 +1106,433 @@ impl MailerService {
         ))]))
     }
 
-    #[tool(description = "Manage email template: add, remove, update")]
-    async fn manage_email_template(
+    #[tool(description = "Manage email template: add, remove, update")]
+    async fn manage_email_template(
+        &self,
+        Parameters(manage_template_request): Parameters<ManageTemplatesRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let mut db = self.db.lock().await;
+
+        let result_message = match manage_template_request {
+            ManageTemplatesRequest::Add(add_request) => {
+                db.new_template(add_request.name, add_request.format_string)?;
+
+                vec![Content::text("Email template added successfully!")]
+            }
+            ManageTemplatesRequest::Remove(remove_request) => {
+                let template_id = db
+                    .find_template_by_name(remove_request.name.clone())
+                    .map(|t| t.id)
+                    .map_err(|_| new_rmcp_error("Template not found"))?;
+
+                db.remove_template(template_id)?;
+
+                vec![Content::text("Email template removed successfully!")]
+            }
+            ManageTemplatesRequest::Update(update_request) => {
+                if let Some(schema) = update_request.validate_schema() {
+                    return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                        "Invalid request: At least one of new_name or new_format_string must be provided. Schema: {}",
+                        serde_json::to_string_pretty(&schema).unwrap()
+                    ))));
+                }
+
+                let template = db
+                    .find_template_by_name(update_request.name.clone())
+                    .map(|t| Template {
+                        id: t.id,
+                        name: update_request.new_name.unwrap_or(t.name),
+                        format_string: update_request.new_format_string.unwrap_or(t.format_string),
+                    })
+                    .map_err(|_| new_rmcp_error("Template not found"))?;
+
+                db.update_template(template.id, template.name, template.format_string)?;
+
+                vec![Content::text("Email template updated successfully!")]
+            }
+        };
+
+        Ok(CallToolResult::success(result_message))
+    }
+
+    #[tool(
+        description = "Get email records filter by recipients or group by time range. The records of a page are grouped into threads by their root record id; a thread spanning several pages is listed in part on each, under the same id"
+    )]
+    async fn get_email_records(
+        &self,
+        Parameters(get_email_history_request): Parameters<GetEmailHistoryRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if let Some(schema) = get_email_history_request.validate_schema() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Invalid request: At least one filter must be provided (to, from, status, start_date, end_date). Schema: {}",
+                serde_json::to_string_pretty(&schema).unwrap()
+            ))));
+        }
+
+        let mut db = self.db.lock().await;
+
+        let start_end_time = parse_start_end_time(
+            get_email_history_request.start_date.as_ref(),
+            get_email_history_request.end_date.as_ref(),
+        );
+
+        let recipient_id = get_email_history_request.to.and_then(|recipient_email| {
+            db.find_recipient_by_email(recipient_email)
+                .ok()
+                .map(|recipient| recipient.id)
+        });
+
+        let status = get_email_history_request
+            .status
+            .as_deref()
+            .map(EmailStatus::try_from)
+            .transpose()
+            .map_err(|e| new_rmcp_error(&e))?;
+
+        let page_request = &get_email_history_request.page;
+        let limit = page_request.limit();
+        let page = Page::probe(
+            page_request.after_id("email_record")?,
+            limit,
+            page_request.order(),
+        );
+
+        let records = db
+            .list_email_records_by_criteria(
+                start_end_time,
+                recipient_id,
+                get_email_history_request.from,
+                status,
+                &page,
+            )
+            .map_err(|e| new_rmcp_error(&format!("Failed to list email records: {}", e)))?;
+        let (records, next_cursor) = split_page(records, limit, "email_record", |r| r.id);
+
+        // Group the records of the page into threads, in the order their first record is listed
+        let mut threads: Vec<(i32, Vec<EmailRecord>)> = Vec::new();
+        for record in records {
+            let root_id = record.thread_root_id();
+            match threads.iter_mut().find(|(id, _)| *id == root_id) {
+                Some((_, thread)) => thread.push(record),
+                None => threads.push((root_id, vec![record])),
+            }
+        }
+
+        let mut result = threads
+            .into_iter()
+            .map(|(root_id, records)| {
+                let records = records
+                    .iter()
+                    .map(|r| format!("Email Record: {r:?}"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Content::text(format!("Thread {root_id}:\n{records}"))
+            })
+            .collect::<Vec<_>>();
+        result.extend(Self::next_cursor_content(next_cursor));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "List the sent emails that got no reply at least the given number of days after they were sent, oldest first. Replies are synced from the IMAP inbox of the senders"
+    )]
+    async fn get_unanswered_emails(
+        &self,
+        Parameters(GetUnansweredEmailsRequest { days, from, limit }): Parameters<
+            GetUnansweredEmailsRequest,
+        >,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let sent_before = chrono::Utc::now().naive_utc() - chrono::Duration::days(i64::from(days));
+        let limit = limit.map_or(DEFAULT_PAGE_SIZE, i64::from);
+
+        let mut db = self.db.lock().await;
+        let records = db
+            .list_unanswered_email_records(sent_before, from, limit)
+            .map_err(|e| new_rmcp_error(&format!("Failed to list email records: {}", e)))?;
+
+        if records.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No unanswered emails",
+            )]));
+        }
+
+        let mut result = Vec::with_capacity(records.len());
+        for record in records {
+            let recipients = db
+                .find_recipients_by_email_record_id(record.id)?
+                .into_iter()
+                .map(|r| r.email)
+                .collect::<Vec<_>>();
+            result.push(Content::text(format!(
+                "Email Record {}: {} | From: {} | To: {} | Sent at: {}",
+                record.id,
+                record.subject,
+                record.sender,
+                recipients.join(", "),
+                record.sent_at
+            )));
+        }
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "List the bounces of the sent emails, read from the delivery status notifications of the bounce mailbox. Recipients are deactivated after a hard bounce or repeated soft bounces"
+    )]
+    async fn get_bounces(
+        &self,
+        Parameters(GetBouncesRequest {
+            email,
+            page: page_request,
+        }): Parameters<GetBouncesRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let limit = page_request.limit();
+        let page = Page::probe(
+            page_request.after_id("bounce")?,
+            limit,
+            page_request.order(),
+        );
+        let bounces = self.db.lock().await.list_bounces(email, &page)?;
+        let (bounces, next_cursor) = split_page(bounces, limit, "bounce", |b| b.id);
+
+        if bounces.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text("No bounces")]));
+        }
+
+        let mut result = bounces
+            .into_iter()
+            .map(|b| Content::text(format!("Bounce: {b:?}")))
+            .collect::<Vec<_>>();
+        result.extend(Self::next_cursor_content(next_cursor));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "Get email statistics: the number of sent, queued and failed emails and the failure rate per day, week, month, recipient, group, sender and template. Returns one JSON table per dimension"
+    )]
+    async fn get_email_stats(
+        &self,
+        Parameters(stats_request): Parameters<GetEmailStatsRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if let Some(schema) = stats_request.validate_schema() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Invalid request: The dimensions and the dates must be valid. Schema: {}",
+                serde_json::to_string_pretty(&schema).unwrap()
+            ))));
+        }
+
+        let start_end_time = parse_start_end_time(
+            stats_request.start_date.as_ref(),
+            stats_request.end_date.as_ref(),
+        );
+        let dimensions = match &stats_request.group_by {
+            Some(group_by) => group_by
+                .iter()
+                .map(|dimension| EmailStatsDimension::try_from(dimension.as_str()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| new_rmcp_error(&e))?,
+            None => EmailStatsDimension::ALL.to_vec(),
+        };
+
+        let mut db = self.db.lock().await;
+        let mut result = Vec::with_capacity(dimensions.len());
+        for dimension in dimensions {
+            let rows = db
+                .email_stats(dimension, start_end_time)
+                .map_err(|e| new_rmcp_error(&format!("Failed to aggregate email records: {}", e)))?
+                .into_iter()
+                .map(|row| {
+                    serde_json::json!({
+                        dimension.as_str(): row.key,
+                        "total": row.total,
+                        "sent": row.sent,
+                        "queued": row.queued,
+                        "failed": row.failed,
+                        "failure_rate": row.failure_rate(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            result.push(Content::json(serde_json::json!({
+                "dimension": dimension.as_str(),
+                "columns": [dimension.as_str(), "total", "sent", "queued", "failed", "failure_rate"],
+                "rows": rows,
+            }))?);
+        }
+
+        Ok(CallToolResult::success(result))
+    }
+
+    #[tool(
+        description = "List the messages received by the inbound webhook, optionally filtered by sender or known recipient"
+    )]
+    async fn list_inbound_messages(
         &self,
-        Parameters(manage_template_request): Parameters<ManageTemplatesRequest>,
+        Parameters(ListInboundMessagesRequest {
+            from,
+            recipient,
+            page: page_request,
+        }): Parameters<ListInboundMessagesRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
         let mut db = self.db.lock().await;
 
-        let result_message = match manage_template_request {
-            ManageTemplatesRequest::Add(add_request) => {
-                db.new_template(add_request.name, add_request.format_string)?;
+        let recipient_id = match recipient {
+            Some(recipient) => Some(
+                db.find_recipient_by_email(recipient)
+                    .map_err(|_| new_rmcp_error("Recipient not found"))?
+                    .id,
+            ),
+            None => None,
+        };
 
-                vec![Content::text("Email template added successfully!")]
-            }
-            ManageTemplatesRequest::Remove(remove_request) => {
-                let template_id = db
-                    .find_template_by_name(remove_request.name.clone())
-                    .map(|t| t.id)
-                    .map_err(|_| new_rmcp_error("Template not found"))?;
+        let limit = page_request.limit();
+        let page = Page::probe(
+            page_request.after_id("inbound_message")?,
+            limit,
+            page_request.order(),
+        );
+        let messages = db.list_inbound_messages(from, recipient_id, &page)?;
+        let (messages, next_cursor) = split_page(messages, limit, "inbound_message", |m| m.id);
 
-                db.remove_template(template_id)?;
+        if messages.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No inbound messages",
+            )]));
+        }
 
-                vec![Content::text("Email template removed successfully!")]
-            }
-            ManageTemplatesRequest::Update(update_request) => {
-                if let Some(schema) = update_request.validate_schema() {
-                    return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
-                        "Invalid request: At least one of new_name or new_format_string must be provided. Schema: {}",
-                        serde_json::to_string_pretty(&schema).unwrap()
-                    ))));
-                }
+        let mut result = messages
+            .into_iter()
+            .map(|m| {
+                Content::text(format!(
+                    "Inbound message {}: {} | From: {} | Recipient id: {} | Replies to: {} | Received at: {}",
+                    m.id,
+                    m.subject,
+                    m.sender,
+                    m.recipient_id
+                        .map_or("unknown".to_string(), |id| id.to_string()),
+                    m.email_history_id
+                        .map_or("none".to_string(), |id| format!("email record {id}")),
+                    m.received_at
+                ))
+            })
+            .collect::<Vec<_>>();
+        result.extend(Self::next_cursor_content(next_cursor));
 
-                let template = db
-                    .find_template_by_name(update_request.name.clone())
-                    .map(|t| Template {
-                        id: t.id,
-                        name: update_request.new_name.unwrap_or(t.name),
-                        format_string: update_request.new_format_string.unwrap_or(t.format_string),
-                    })
-                    .map_err(|_| new_rmcp_error("Template not found"))?;
+        Ok(CallToolResult::success(result))
+    }
 
-                db.update_template(template.id, template.name, template.format_string)?;
+    #[tool(
+        description = "Read a message received by the inbound webhook, parsed or as the raw MIME message"
+    )]
+    async fn read_inbound_message(
+        &self,
+        Parameters(ReadInboundMessageRequest { message_id, raw }): Parameters<
+            ReadInboundMessageRequest,
+        >,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let mut db = self.db.lock().await;
+        let message = db
+            .find_inbound_message(message_id)
+            .map_err(|_| new_rmcp_error("Inbound message not found"))?;
+
+        if raw {
+            let raw_message = db.find_inbound_raw_message(message.id)?;
+            return Ok(CallToolResult::success(vec![Content::text(
+                String::from_utf8_lossy(&raw_message).to_string(),
+            )]));
+        }
 
-                vec![Content::text("Email template updated successfully!")]
-            }
-        };
+        Ok(CallToolResult::success(vec![
+            Content::text(format!(
+                "Inbound message {}: {}\nMessage-ID: {}\nFrom: {}\nTo: {}\nReceived at: {}",
+                message.id,
+                message.subject,
+                message.message_id.as_deref().unwrap_or("none"),
+                message.sender,
+                message.to_addresses,
+                message.received_at
+            )),
+            Content::text(format!("Body:\n{}", message.body)),
+        ]))
+    }
 
-        Ok(CallToolResult::success(result_message))
+    #[tool(
+        description = "Admin tool: apply the configured retention rules to the email history, deleting old bodies or records. Use dry_run to only count what would be purged"
+    )]
+    async fn purge_history(
+        &self,
+        Parameters(PurgeHistoryRequest { dry_run }): Parameters<PurgeHistoryRequest>,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let purged = self
+            .db
+            .lock()
+            .await
+            .purge_history(dry_run)
+            .map_err(|e| new_rmcp_error(&format!("Failed to purge email history: {}", e)))?;
+
+        if purged.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No retention rules are configured",
+            )]));
+        }
+
+        let verb = if dry_run { "would purge" } else { "purged" };
+        let result = purged
+            .into_iter()
+            .map(|(rule, count)| Content::text(format!("Rule {rule:?} {verb} {count} records")))
+            .collect::<Vec<_>>();
+
+        Ok(CallToolResult::success(result))
     }
 
-    #[tool(description = "Get email records filter by recipients or group by time range")]
-    async fn get_email_records(
+    #[tool(
+        description = "Search the email history by text, combined with optional sender, group and date filters. Returns the best matches first, with the matched terms highlighted"
+    )]
+    async fn search_emails(
         &self,
-        Parameters(get_email_history_request): Parameters<GetEmailHistoryRequest>,
+        Parameters(search_request): Parameters<SearchEmailsRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
-        if let Some(schema) = get_email_history_request.validate_schema() {
+        if let Some(schema) = search_request.validate_schema() {
             return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
-                "Invalid request: At least one filter must be provided (to, start_date, end_date). Schema: {}",
+                "Invalid request: The query must not be empty and the dates must be valid. Schema: {}",
                 serde_json::to_string_pretty(&schema).unwrap()
             ))));
         }
 
         let mut db = self.db.lock().await;
 
+        let group_id = match &search_request.group {
+            Some(group_name) => Some(
+                db.find_group_by_name(group_name.clone())
+                    .map_err(|_| new_rmcp_error("Group not found"))?
+                    .id,
+            ),
+            None => None,
+        };
+
         let start_end_time = parse_start_end_time(
-            get_email_history_request.start_date.as_ref(),
-            get_email_history_request.end_date.as_ref(),
+            search_request.start_date.as_ref(),
+            search_request.end_date.as_ref(),
         );
 
-        let recipient_id = get_email_history_request.to.and_then(|recipient_email| {
-            db.find_recipient_by_email(recipient_email)
-                .ok()
-                .map(|recipient| recipient.id)
-        });
-
         let result = db
-            .list_email_records_by_criteria(start_end_time, recipient_id)
-            .map_err(|e| new_rmcp_error(&format!("Failed to list email records: {}", e)))?
+            .search_email_records(
+                &search_request.query,
+                search_request.from,
+                start_end_time,
+                group_id,
+                i64::from(search_request.limit.unwrap_or(20)),
+            )
+            .map_err(|e| new_rmcp_error(&format!("Failed to search emails: {}", e)))?
             .into_iter()
-            .map(|r| Content::text(format!("Email Record: {r:?}")))
+            .map(|r| {
+                Content::text(format!(
+                    "Email Record {}: subject: {}, from: {}, sent at: {}\n{}",
+                    r.id, r.subject, r.sender, r.sent_at, r.snippet
+                ))
+            })
             .collect::<Vec<_>>();
 
         Ok(CallToolResult::success(result))
@@ -416,9 +1553,11 @@ impl MailerService {
             event_request.is_all_day,
         )?;
 
-        if let Ok(overlapping_events) =
-            db.list_events(event_request.start_time, event_request.end_time)
-        {
+        if let Ok(overlapping_events) = db.list_events(
+            event_request.start_time,
+            event_request.end_time,
+            &Page::default(),
+        ) {
             let overlapping_events = overlapping_events
                 .iter()
                 .filter(|e| e.id != new_event.id)
@@ -440,6 +1579,7 @@ impl MailerService {
         Parameters(ListEventsRequest {
             start_date,
             end_date,
+            page: page_request,
         }): Parameters<ListEventsRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
         let mut db = self.db.lock().await;
@@ -452,15 +1592,20 @@ impl MailerService {
 
         let start_end_time = parse_start_end_time(start_date.as_ref(), end_date.as_ref());
 
+        let limit = page_request.limit();
+        let page = Page::probe(page_request.after_id("event")?, limit, page_request.order());
+
         let events = match start_end_time {
-            Some((start, end)) => db.list_events(start, Some(end))?,
-            None => db.list_events(chrono::NaiveDateTime::MIN, None)?,
+            Some((start, end)) => db.list_events(start, Some(end), &page)?,
+            None => db.list_events(chrono::NaiveDateTime::MIN, None, &page)?,
         };
+        let (events, next_cursor) = split_page(events, limit, "event", |e| e.id);
 
-        let result = events
+        let mut result = events
             .into_iter()
             .map(|e| Content::text(format!("Event: {e:?}")))
             .collect::<Vec<_>>();
+        result.extend(Self::next_cursor_content(next_cursor));
 
         Ok(CallToolResult::success(result))
     }
@@ -470,13 +1615,167 @@ impl MailerService {
         &self,
         Parameters(invitation_request): Parameters<SendEventInvitationRequest>,
     ) -> Result<CallToolResult, rmcp::ErrorData> {
-        let mut db = self.db.lock().await;
+        let (event, email_request) =
+            Self::resolve_invitation_request(&mut self.db.lock().await, invitation_request)?;
+
+        let (sent_email, recipient_ids) = self
+            .send_and_record(email_request, EmailOrigin::New)
+            .await?;
+
+        // Set event attendees in the database, unless nobody was invited
+        if sent_email.delivery.status != EmailStatus::Failed {
+            Self::save_event_attendee(&mut self.db.lock().await, event.id, recipient_ids)?;
+        }
+
+        Self::delivery_result(&sent_email, "Event invitations sent successfully!")
+    }
+
+    /// Resolve the members of the group into a plain email request.
+    fn resolve_group_request(
+        db: &mut MutexGuard<'_, Database>,
+        email_request: SendGroupEmailRequest,
+    ) -> Result<(Group, SendEmailRequest, usize), rmcp::ErrorData> {
+        let group = db
+            .find_group_by_name(email_request.group_name.clone())
+            .map_err(|_| new_rmcp_error("Group not found"))?;
+
+        let unsubscribed = db.find_unsubscribed_emails(Some(group.id))?;
+        let (to, skipped): (Vec<_>, Vec<_>) = db
+            .find_recipients_by_group_id(group.id)?
+            .into_iter()
+            .map(|r| r.email)
+            .partition(|email| !unsubscribed.contains(&email.to_lowercase()));
+
+        Ok((
+            group,
+            SendEmailRequest {
+                from: email_request.from,
+                to,
+                reply_to: email_request.reply_to,
+                subject: email_request.subject,
+                body: email_request.body,
+                thread: None,
+                unsubscribe_url: None,
+            },
+            skipped.len(),
+        ))
+    }
+
+    /// Resolve the recipients matching the tag expression into a plain email
+    /// request. Returns the number of matching recipients skipped because they
+    /// unsubscribed from all group emails.
+    fn resolve_tagged_request(
+        db: &mut MutexGuard<'_, Database>,
+        email_request: SendTaggedEmailRequest,
+    ) -> Result<(SendEmailRequest, usize), rmcp::ErrorData> {
+        let expression = TagExpression::parse(&email_request.tag_expression)?;
+
+        let unsubscribed = db.find_unsubscribed_emails(None)?;
+        let (to, skipped): (Vec<_>, Vec<_>) = db
+            .find_recipients_by_tag_expression(&expression)?
+            .into_iter()
+            .map(|r| r.email)
+            .partition(|email| !unsubscribed.contains(&email.to_lowercase()));
+
+        Ok((
+            SendEmailRequest {
+                from: email_request.from,
+                to,
+                reply_to: email_request.reply_to,
+                subject: email_request.subject,
+                body: email_request.body,
+                thread: None,
+                unsubscribe_url: None,
+            },
+            skipped.len(),
+        ))
+    }
+
+    /// Finds the recipients of the tag request and normalizes its tags.
+    fn resolve_tag_request(
+        db: &mut MutexGuard<'_, Database>,
+        tag_request: TagRecipientsRequest,
+    ) -> Result<ResolvedTagRequest, rmcp::ErrorData> {
+        let mut tags = Vec::new();
+        for tag in &tag_request.tags {
+            let tag = normalize_tag(tag)
+                .ok_or_else(|| new_rmcp_error(&format!("Invalid tag: {tag:?}")))?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        if tags.is_empty() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(
+                "At least one tag must be provided",
+            )));
+        }
+
+        let mut recipient_ids = Vec::new();
+        let mut unknown = Vec::new();
+        for email in tag_request.emails {
+            match db.find_recipient_by_email(email.clone()) {
+                Ok(recipient) => recipient_ids.push(recipient.id),
+                Err(_) => unknown.push(email),
+            }
+        }
+
+        Ok(ResolvedTagRequest {
+            recipient_ids,
+            tags,
+            unknown,
+        })
+    }
+
+    /// Render the template into a plain email request. Returns the template
+    /// together with the request.
+    fn resolve_template_request(
+        db: &mut MutexGuard<'_, Database>,
+        email_request: SendEmailWithTemplateRequest,
+    ) -> Result<(Template, SendEmailRequest), rmcp::ErrorData> {
+        let res_template = db
+            .find_template_by_name(email_request.template_name.clone())
+            .map_err(|_| new_rmcp_error("Template not found"))?;
 
+        // A single known recipient fills the placeholders with its attributes,
+        // unless the request gives them
+        let mut template_data = match Self::find_known_recipients(db, &email_request.to).as_slice()
+        {
+            [recipient] if email_request.to.len() == 1 => {
+                db.find_recipient_attributes(recipient.id)?
+            }
+            _ => HashMap::new(),
+        };
+        template_data.extend(email_request.template_data.clone());
+
+        let body = res_template
+            .format(template_data)
+            .map_err(|e| new_rmcp_error(&e))?;
+
+        Ok((
+            res_template,
+            SendEmailRequest {
+                from: email_request.from,
+                to: email_request.to,
+                reply_to: email_request.reply_to,
+                subject: email_request.subject,
+                body,
+                thread: None,
+                unsubscribe_url: None,
+            },
+        ))
+    }
+
+    /// Resolve the invited groups and individuals into a plain email request.
+    /// Returns the event together with the request.
+    fn resolve_invitation_request(
+        db: &mut MutexGuard<'_, Database>,
+        invitation_request: SendEventInvitationRequest,
+    ) -> Result<(Event, SendEmailRequest), rmcp::ErrorData> {
         let event = db
             .find_event_by_id(invitation_request.event_id)
             .map_err(|_| new_rmcp_error("Event not found"))?;
 
-        let recipients = invitation_request
+        let mut recipients = invitation_request
             .to
             .groups
             .iter()
@@ -488,50 +1787,220 @@ impl MailerService {
             })
             .flatten()
             .chain(invitation_request.to.individuals)
-            .collect();
+            .collect::<Vec<_>>();
+        if let Some(tag_expression) = &invitation_request.to.tag_expression {
+            let expression = TagExpression::parse(tag_expression)?;
+            for recipient in db.find_recipients_by_tag_expression(&expression)? {
+                if !recipients.contains(&recipient.email) {
+                    recipients.push(recipient.email);
+                }
+            }
+        }
 
-        // send invitations via email
         let email_request = SendEmailRequest {
             from: invitation_request.from,
             to: recipients,
             reply_to: None,
             subject: invitation_request.subject,
             body: invitation_request.body,
+            thread: None,
+            unsubscribe_url: None,
+        };
+
+        Ok((event, email_request))
+    }
+
+    /// Returns the IMAP client of the sender `account`, or of the first sender with an IMAP account.
+    fn imap_client(&self, account: Option<&str>) -> Result<ImapClient, rmcp::ErrorData> {
+        self.imap_clients
+            .iter()
+            .find(|client| account.is_none_or(|account| client.email == account))
+            .cloned()
+            .ok_or_else(|| rmcp::ErrorData::from(new_rmcp_error("No IMAP account configured")))
+    }
+
+    /// Runs a blocking IMAP operation on a thread of its own.
+    async fn run_imap<R: Send + 'static>(
+        client: ImapClient,
+        operation: impl FnOnce(&ImapClient) -> Result<R, MailerError> + Send + 'static,
+    ) -> Result<R, rmcp::ErrorData> {
+        tokio::task::spawn_blocking(move || operation(&client))
+            .await
+            .map_err(|e| new_rmcp_error(&format!("IMAP operation failed: {}", e)))?
+            .map_err(rmcp::ErrorData::from)
+    }
+
+    /// Returns the `next_cursor` of a paginated list, if there are more items.
+    fn next_cursor_content(next_cursor: Option<String>) -> Option<Content> {
+        next_cursor.map(|cursor| Content::text(format!("next_cursor: {cursor}")))
+    }
+
+    /// Describe the recipient, showing its keys by fingerprint and expiry date
+    /// instead of the whole key material.
+    fn describe_recipient(recipient: &Recipient) -> String {
+        let mut description = format!(
+            "Recipient: id: {}, name: {}, email: {}, status: {:?}",
+            recipient.id, recipient.name, recipient.email, recipient.status
+        );
+
+        if let Some(public_key) = &recipient.pgp_public_key {
+            let fingerprint = pgp::public_key_fingerprint(public_key)
+                .unwrap_or_else(|_| "invalid key".to_string());
+            description.push_str(&format!(", PGP key: {fingerprint}"));
+        }
+        if let Some(certificate) = &recipient.smime_certificate {
+            match smime::certificate_info(certificate) {
+                Ok(info) => description.push_str(&format!(
+                    ", S/MIME certificate: {} (expires at {})",
+                    info.fingerprint, info.expires_at
+                )),
+                Err(_) => description.push_str(", S/MIME certificate: invalid certificate"),
+            }
+        }
+        if recipient.encryption_required {
+            description.push_str(", encryption required");
+        }
+
+        description
+    }
+
+    /// Sends the email to each recipient in a message of their own, with a
+    /// one-click link unsubscribing from the group, or from all groups if
+    /// `group_id` is `None`. One recipient failing doesn't keep the email
+    /// from the others.
+    async fn send_with_unsubscribe_links(
+        &self,
+        request: SendEmailRequest,
+        unsubscribe_config: &UnsubscribeConfig,
+        group_id: Option<i32>,
+        recipients_name: &str,
+        success_message: &str,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        let suppressions = self.db.lock().await.list_suppressions(&Page::default())?;
+        let (to, suppressed) = Mailer::filter_suppressed(&request.to, &suppressions)?;
+        let mut failed = Vec::new();
+        for to in &to {
+            let recipient_request = unsubscribe::unsubscribe_url(unsubscribe_config, to, group_id)
+                .map(|unsubscribe_url| SendEmailRequest {
+                    from: request.from.clone(),
+                    to: vec![to.clone()],
+                    reply_to: request.reply_to.clone(),
+                    subject: request.subject.clone(),
+                    body: request.body.clone(),
+                    thread: None,
+                    unsubscribe_url: Some(unsubscribe_url),
+                })?;
+
+            match self
+                .send_and_record(recipient_request, EmailOrigin::New)
+                .await
+            {
+                Ok((sent_email, _)) if sent_email.delivery.status == EmailStatus::Failed => {
+                    failed.push(format!(
+                        "{to} ({})",
+                        sent_email
+                            .delivery
+                            .message
+                            .as_deref()
+                            .unwrap_or("unknown error")
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => failed.push(format!("{to} ({})", e.message)),
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Sent the email to {} of {} {recipients_name}. Failed to send it to {}",
+                to.len() - failed.len(),
+                to.len(),
+                failed.join(", ")
+            ))));
+        }
+
+        let mut result = vec![Content::text(success_message)];
+        result.extend(Self::suppressed_content(&suppressed));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    /// Sends the email and records it, returning the ids of its recipients.
+    /// The database is only locked to look up the recipients and to record the
+    /// email, not while waiting for the rate limits and the delivery.
+    async fn send_and_record(
+        &self,
+        request: SendEmailRequest,
+        origin: EmailOrigin<'_>,
+    ) -> Result<(SentEmail, Vec<i32>), rmcp::ErrorData> {
+        self.check_send_policy(&request.to)?;
+        let (known_recipients, suppressions) = {
+            let mut db = self.db.lock().await;
+            (
+                Self::find_known_recipients(&mut db, &request.to),
+                db.list_suppressions(&Page::default())?,
+            )
         };
 
-        let sent_message = self.mailer.send(&email_request).await?;
+        let sent_email = self
+            .mailer
+            .send(&request, &known_recipients, &suppressions)
+            .await?;
 
+        let mut db = self.db.lock().await;
         // Save the recipient record in the database
-        let recipient_ids = Self::save_recipient_record(&mut db, &sent_message)
-            .expect("Failed to save recipient record");
+        let recipient_ids = Self::save_recipient_record(&mut db, &sent_email.recipients)?;
 
         // Save email record with recipient IDs
         Self::save_email_record_with_recipient_ids(
             &mut db,
-            email_request.subject,
-            email_request.body,
+            request.subject,
+            request.body,
             recipient_ids.clone(),
-        )
-        .expect("Failed to save email record");
+            &sent_email,
+            origin,
+        )?;
 
-        // Set event attendees in the database
-        Self::save_event_attendee(&mut db, event.id, recipient_ids)
-            .expect("Failed to save event attendee");
+        Ok((sent_email, recipient_ids))
+    }
 
-        Ok(CallToolResult::success(vec![Content::text(
-            "Event invitations sent successfully!",
-        )]))
+    /// Find the phone book entries of the recipients. Unknown recipients are skipped.
+    fn find_known_recipients(db: &mut MutexGuard<'_, Database>, to: &[String]) -> Vec<Recipient> {
+        to.iter()
+            .filter_map(|recipient| recipient.parse::<Mailbox>().ok())
+            .filter_map(|mailbox| db.find_recipient_by_email(mailbox.email.to_string()).ok())
+            .collect()
+    }
+
+    /// Collect the Message-IDs of the email and its ancestors, oldest first,
+    /// for the `References` header of a reply to it.
+    fn thread_references(
+        db: &mut MutexGuard<'_, Database>,
+        email_record: &EmailRecord,
+    ) -> Result<Vec<String>, rmcp::ErrorData> {
+        let mut references = vec![email_record.message_id.clone()];
+        let mut parent_id = email_record.in_reply_to_id;
+        while let Some(id) = parent_id {
+            let parent = db.find_email_record_by_id(id)?;
+            references.push(parent.message_id);
+            parent_id = parent.in_reply_to_id;
+        }
+
+        // Records saved before Message-IDs were stored have none
+        references.retain(|message_id| !message_id.is_empty());
+        references.reverse();
+        Ok(references)
     }
 
-    /// Save recipient records in the database for each email in the sent message.
+    /// Save recipient records in the database for each recipient of the sent email.
     /// Returns a vector of recipient IDs.
     fn save_recipient_record(
         db: &mut MutexGuard<'_, Database>,
-        sent_message: &Message,
+        recipients: &[Address],
     ) -> Result<Vec<i32>, rmcp::ErrorData> {
         let mut recipient_ids = Vec::new();
 
-        for email in sent_message.envelope().to() {
+        for email in recipients {
             let email_str = email.to_string();
             let new_recipient_id = match db.find_recipient_by_email(email_str.clone()) {
                 Ok(recipient) => recipient.id, // If recipient exists, use their ID
@@ -554,14 +2023,98 @@ impl MailerService {
         email_subject: String,
         email_body: String,
         recipient_ids: Vec<i32>,
+        sent_email: &SentEmail,
+        origin: EmailOrigin<'_>,
     ) -> Result<(), rmcp::ErrorData> {
-        let email_record = db.add_email_record(email_subject, email_body)?;
+        let (in_reply_to, resent_from, template) = match origin {
+            EmailOrigin::New => (None, None, None),
+            EmailOrigin::Template(template) => (None, None, Some(template)),
+            EmailOrigin::Reply(original) => (Some(original), None, None),
+            EmailOrigin::Resend(original) => (None, Some(original), None),
+        };
+        let email_record = db.add_email_record(NewEmailRecord {
+            subject: email_subject,
+            body: email_body,
+            sandboxed: sent_email.sandboxed,
+            message_id: sent_email.message_id.clone(),
+            sender: sent_email.sender.clone(),
+            headers: sent_email.headers.clone(),
+            in_reply_to_id: in_reply_to.map(|r| r.id),
+            thread_id: in_reply_to.map(|r| r.thread_root_id()),
+            reply_to: sent_email.reply_to.clone(),
+            transport: sent_email.transport.clone(),
+            smtp_code: sent_email.delivery.code.map(i32::from),
+            smtp_message: sent_email.delivery.message.clone(),
+            status: sent_email.delivery.status,
+            resent_from_id: resent_from.map(|r| r.id),
+            template_id: template.map(|t| t.id),
+        })?;
+        db.add_raw_email(email_record.id, &sent_email.raw)?;
         for recipient_id in recipient_ids {
             db.add_recipient_email_record(email_record.id, recipient_id)?;
         }
         Ok(())
     }
 
+    /// Report the delivery outcome of the recorded email. A failed delivery is
+    /// returned as an error.
+    fn delivery_result(
+        sent_email: &SentEmail,
+        success_message: &str,
+    ) -> Result<CallToolResult, rmcp::ErrorData> {
+        if sent_email.delivery.status == EmailStatus::Failed {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Failed to send email: {}",
+                sent_email
+                    .delivery
+                    .message
+                    .as_deref()
+                    .unwrap_or("unknown error")
+            ))));
+        }
+
+        let mut result = vec![Content::text(success_message)];
+        result.extend(Self::suppressed_content(&sent_email.suppressed));
+
+        Ok(CallToolResult::success(result))
+    }
+
+    fn validate_attributes(attributes: &HashMap<String, String>) -> Result<(), rmcp::ErrorData> {
+        let invalid = invalid_attribute_names(attributes);
+        if !invalid.is_empty() {
+            return Err(rmcp::ErrorData::from(new_rmcp_error(&format!(
+                "Invalid attribute names, only letters, digits, '_', '-' and '.' are allowed: {}",
+                invalid.join(", ")
+            ))));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the recipients of a message against the send policy, if any,
+    /// before anything is sent.
+    fn check_send_policy(&self, to: &[String]) -> Result<(), rmcp::ErrorData> {
+        if let Some(policy) = &self.send_policy {
+            policy::check(policy, to)?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the recipients left out because they are suppressed, if any.
+    fn suppressed_content<S: AsRef<str>>(suppressed: &[S]) -> Option<Content> {
+        (!suppressed.is_empty()).then(|| {
+            Content::text(format!(
+                "Suppressed recipients, not emailed: {}",
+                suppressed
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+    }
+
     fn save_event_attendee(
         db: &mut MutexGuard<'_, Database>,
         event_id: i32,
@@ -593,8 +2146,194 @@ You can use the provided tools to interact with the service."#
     }
 }
 
-impl Default for MailerService {
-    fn default() -> Self {
-        Self::new(Config::default())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bounce::{DeliveryStatusNotification, FailedRecipient, process_dsn},
+        config::{BounceConfig, DatabaseConfig},
+        model::bounce::BounceKind,
+        transport::StubTransport,
+    };
+
+    /// Returns a service delivering to a stub transport, on a fresh database.
+    fn test_service(
+        db_path: &str,
+        unsubscribe_config: Option<UnsubscribeConfig>,
+    ) -> (MailerService, Arc<StubTransport>) {
+        _ = std::fs::remove_file(db_path);
+        let config = Config {
+            db_config: DatabaseConfig {
+                db_path: db_path.to_string(),
+                raw_email_retention_days: None,
+                retention: Default::default(),
+            },
+            unsubscribe_config,
+            ..Default::default()
+        };
+        let stub = Arc::new(StubTransport::new());
+        let mailer = Mailer::with_transport(config.mailer_config.clone(), stub.clone()).unwrap();
+
+        (MailerService::with_mailer(config, mailer), stub)
+    }
+
+    /// Adds the recipients to a new group.
+    async fn new_group(service: &MailerService, name: &str, emails: &[&str]) -> Vec<Recipient> {
+        let mut db = service.db.lock().await;
+        let group = db.new_group(name.to_string()).unwrap();
+        let mut recipients = Vec::new();
+        for email in emails {
+            let recipient = db
+                .new_recipient(email.to_string(), email.to_string())
+                .unwrap();
+            db.add_recipient_to_group(group.id, recipient.id).unwrap();
+            recipients.push(recipient);
+        }
+
+        recipients
+    }
+
+    fn group_request(group_name: &str) -> SendGroupEmailRequest {
+        SendGroupEmailRequest {
+            from: None,
+            group_name: group_name.to_string(),
+            reply_to: None,
+            subject: "Subject".to_string(),
+            body: "Body".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_send_skips_bounced_members() {
+        const DB_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_service_bounce.db");
+        let (service, _) = test_service(DB_PATH, None);
+        new_group(&service, "news", &["alice@domain.com", "bob@domain.com"]).await;
+
+        let mut db = service.db.lock().await;
+        let config = BounceConfig {
+            mailbox: "test@test.com".to_string(),
+            folder: "Bounces".to_string(),
+            return_path: None,
+            soft_bounce_limit: 3,
+            sync_interval_minutes: 10,
+        };
+        let dsn = DeliveryStatusNotification {
+            message_id: Some("<dsn-1@mx.domain.com>".to_string()),
+            original_message_id: None,
+            delivered_to: vec![],
+            failed_recipients: vec![FailedRecipient {
+                email: "bob@domain.com".to_string(),
+                kind: BounceKind::Hard,
+                status: "5.1.1".to_string(),
+                diagnostic: None,
+            }],
+        };
+        assert_eq!(process_dsn(&mut db, &config, &dsn).unwrap(), 1);
+
+        let (_, request, skipped) =
+            MailerService::resolve_group_request(&mut db, group_request("news")).unwrap();
+        assert_eq!(request.to, vec!["alice@domain.com"]);
+        assert_eq!(skipped, 0);
+
+        drop(db);
+        drop(service);
+        std::fs::remove_file(DB_PATH).expect("Failed to remove test_service_bounce.db");
+    }
+
+    #[tokio::test]
+    async fn test_group_send_reports_failed_members() {
+        const DB_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_service_group.db");
+        let (service, stub) = test_service(
+            DB_PATH,
+            Some(UnsubscribeConfig {
+                base_url: "https://mailer.test.com".to_string(),
+                secret: "0123456789abcdef".to_string(),
+            }),
+        );
+        let members = new_group(
+            &service,
+            "news",
+            &["bob@domain.com", "alice@domain.com", "carol@domain.com"],
+        )
+        .await;
+        {
+            let mut db = service.db.lock().await;
+            // Bob can't be mailed unencrypted, and Carol is suppressed
+            db.set_recipient_pgp_key(members[0].id, None, true).unwrap();
+            db.add_suppression(NewSuppression {
+                entry: "carol@domain.com".to_string(),
+                reason: None,
+            })
+            .unwrap();
+        }
+
+        let error = service
+            .send_email_to_group(Parameters(group_request("news")))
+            .await
+            .unwrap_err();
+        assert!(
+            error.message.contains(
+                "Sent the email to 1 of 2 members. Failed to send it to bob@domain.com ("
+            ),
+            "{}",
+            error.message
+        );
+
+        // The members after the failed one are still mailed
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("To: alice@domain.com"));
+
+        drop(service);
+        std::fs::remove_file(DB_PATH).expect("Failed to remove test_service_group.db");
+    }
+
+    #[tokio::test]
+    async fn test_tagged_send_adds_unsubscribe_links() {
+        const DB_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_service_tagged.db");
+        let (service, stub) = test_service(
+            DB_PATH,
+            Some(UnsubscribeConfig {
+                base_url: "https://mailer.test.com".to_string(),
+                secret: "0123456789abcdef".to_string(),
+            }),
+        );
+        let recipients = new_group(&service, "news", &["alice@domain.com", "bob@domain.com"]).await;
+        {
+            let ids = recipients.iter().map(|r| r.id).collect::<Vec<_>>();
+            let mut db = service.db.lock().await;
+            db.tag_recipients(&ids, &["customer".to_string()]).unwrap();
+        }
+
+        service
+            .send_email_to_tag_expression(Parameters(SendTaggedEmailRequest {
+                from: None,
+                tag_expression: "customer".to_string(),
+                reply_to: None,
+                subject: "Subject".to_string(),
+                body: "Body".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        // Each recipient gets a message of their own, with a link of their own
+        let messages = stub.messages().await;
+        assert_eq!(messages.len(), 2);
+        for (message, email) in messages.iter().zip(["alice@domain.com", "bob@domain.com"]) {
+            assert!(message.contains(&format!("To: {email}")));
+            assert!(
+                message.contains(
+                    &unsubscribe::unsubscribe_url(
+                        service.unsubscribe_config.as_ref().unwrap(),
+                        email,
+                        None
+                    )
+                    .unwrap()
+                )
+            );
+        }
+
+        drop(service);
+        std::fs::remove_file(DB_PATH).expect("Failed to remove test_service_tagged.db");
     }
 }
@@ -0,0 +1,241 @@
+use lettre::message::{
+    SinglePart,
+    header::{ContentDisposition, ContentTransferEncoding, ContentType},
+};
+use openssl::{
+    hash::MessageDigest,
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::PKey,
+    stack::Stack,
+    symm::Cipher,
+    x509::X509,
+};
+
+use crate::{
+    config::SmimeConfig,
+    error::{MailerError, new_rmcp_error},
+};
+
+/// Summary of an X.509 certificate, shown in the phone book.
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub fingerprint: String,
+    pub expires_at: String,
+}
+
+/// Parses a PEM encoded certificate and returns its subject, SHA-256 fingerprint and expiry date.
+pub fn certificate_info(pem: &str) -> Result<CertificateInfo, MailerError> {
+    let certificate = parse_certificate(pem)?;
+
+    let subject = certificate
+        .subject_name()
+        .entries()
+        .filter_map(|entry| {
+            let key = entry.object().nid().short_name().ok()?;
+            let value = entry.data().to_string().ok()?;
+            Some(format!("{key}={value}"))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let fingerprint = certificate
+        .digest(MessageDigest::sha256())
+        .map_err(|e| new_rmcp_error(&format!("Invalid S/MIME certificate: {}", e)))?
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect();
+
+    Ok(CertificateInfo {
+        subject,
+        fingerprint,
+        expires_at: certificate.not_after().to_string(),
+    })
+}
+
+/// Builds an `application/pkcs7-mime` enveloped-data part from the entity,
+/// encrypted to all given certificates. The entity is signed first if a
+/// signing certificate is given.
+pub fn encrypt(
+    entity: &SinglePart,
+    certificates: &[&str],
+    signing_config: Option<&SmimeConfig>,
+) -> Result<SinglePart, MailerError> {
+    let mut recipients = Stack::new()?;
+    for certificate in certificates {
+        recipients.push(parse_certificate(certificate)?)?;
+    }
+
+    let content = match signing_config {
+        Some(signing_config) => sign(entity, signing_config)?.formatted(),
+        None => entity.formatted(),
+    };
+
+    let enveloped = Pkcs7::encrypt(
+        &recipients,
+        &content,
+        Cipher::aes_256_cbc(),
+        Pkcs7Flags::BINARY,
+    )
+    .and_then(|pkcs7| pkcs7.to_der())
+    .map_err(|e| new_rmcp_error(&format!("Failed to encrypt S/MIME message: {}", e)))?;
+
+    pkcs7_mime_part("enveloped-data", enveloped)
+}
+
+/// Builds an opaque `application/pkcs7-mime` signed-data part from the entity.
+pub fn sign(entity: &SinglePart, signing_config: &SmimeConfig) -> Result<SinglePart, MailerError> {
+    let certificate =
+        parse_certificate(&std::fs::read_to_string(&signing_config.certificate_path)?)?;
+    let private_key = PKey::private_key_from_pem(&std::fs::read(&signing_config.private_key_path)?)
+        .map_err(|e| new_rmcp_error(&format!("Invalid S/MIME private key: {}", e)))?;
+
+    let extra_certificates = Stack::<X509>::new()?;
+    let signed = Pkcs7::sign(
+        &certificate,
+        &private_key,
+        &extra_certificates,
+        &entity.formatted(),
+        Pkcs7Flags::BINARY,
+    )
+    .and_then(|pkcs7| pkcs7.to_der())
+    .map_err(|e| new_rmcp_error(&format!("Failed to sign S/MIME message: {}", e)))?;
+
+    pkcs7_mime_part("signed-data", signed)
+}
+
+fn pkcs7_mime_part(smime_type: &str, der: Vec<u8>) -> Result<SinglePart, MailerError> {
+    let content_type = ContentType::parse(&format!(
+        "application/pkcs7-mime; smime-type={smime_type}; name=\"smime.p7m\""
+    ))
+    .map_err(|_| new_rmcp_error("Invalid S/MIME content type"))?;
+
+    Ok(SinglePart::builder()
+        .header(content_type)
+        .header(ContentDisposition::attachment("smime.p7m"))
+        .header(ContentTransferEncoding::Base64)
+        .body(der))
+}
+
+fn parse_certificate(pem: &str) -> Result<X509, MailerError> {
+    X509::from_pem(pem.trim().as_bytes())
+        .map_err(|e| new_rmcp_error(&format!("Invalid S/MIME certificate: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        rsa::Rsa,
+        x509::{X509Builder, X509NameBuilder, store::X509StoreBuilder},
+    };
+
+    use super::*;
+
+    /// Generates a self-signed certificate and its private key, as PEM.
+    fn generate_certificate(common_name: &str) -> (String, String) {
+        let private_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&private_key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&private_key, MessageDigest::sha256()).unwrap();
+
+        (
+            String::from_utf8(builder.build().to_pem().unwrap()).unwrap(),
+            String::from_utf8(private_key.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+        )
+    }
+
+    /// Returns the decoded body of a base64 encoded part.
+    fn part_der(part: &SinglePart) -> Vec<u8> {
+        let formatted = String::from_utf8(part.formatted()).unwrap();
+        let (_, body) = formatted.split_once("\r\n\r\n").unwrap();
+        STANDARD
+            .decode(body.split_whitespace().collect::<String>())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_encrypt() {
+        let (sender_certificate, sender_key) = generate_certificate("sender@test.com");
+        let (recipient_certificate, recipient_key) = generate_certificate("bob@domain.com");
+        let dir = std::env::temp_dir().join(format!("smime-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let signing_config = SmimeConfig {
+            certificate_path: dir.join("smime.pem").to_string_lossy().to_string(),
+            private_key_path: dir.join("smime.key").to_string_lossy().to_string(),
+            sign_unencrypted: true,
+        };
+        std::fs::write(&signing_config.certificate_path, &sender_certificate).unwrap();
+        std::fs::write(&signing_config.private_key_path, &sender_key).unwrap();
+
+        let info = certificate_info(&recipient_certificate).unwrap();
+        assert_eq!(info.subject, "CN=bob@domain.com");
+        assert_eq!(info.fingerprint.len(), 64);
+
+        let entity = SinglePart::plain("Secret Body".to_string());
+
+        // The signature verifies against the sender certificate
+        let signed = Pkcs7::from_der(&part_der(&sign(&entity, &signing_config).unwrap())).unwrap();
+        let mut certificates = Stack::new().unwrap();
+        certificates
+            .push(X509::from_pem(sender_certificate.as_bytes()).unwrap())
+            .unwrap();
+        let mut store = X509StoreBuilder::new().unwrap();
+        store
+            .add_cert(X509::from_pem(sender_certificate.as_bytes()).unwrap())
+            .unwrap();
+        let mut content = Vec::new();
+        signed
+            .verify(
+                &certificates,
+                &store.build(),
+                None,
+                Some(&mut content),
+                Pkcs7Flags::BINARY,
+            )
+            .unwrap();
+        assert!(String::from_utf8_lossy(&content).contains("Secret Body"));
+
+        // Only the recipient can decrypt the signed and encrypted message
+        let encrypted = encrypt(&entity, &[&recipient_certificate], Some(&signing_config)).unwrap();
+        let enveloped = Pkcs7::from_der(&part_der(&encrypted)).unwrap();
+        let decrypted = enveloped
+            .decrypt(
+                &PKey::private_key_from_pem(recipient_key.as_bytes()).unwrap(),
+                &X509::from_pem(recipient_certificate.as_bytes()).unwrap(),
+                Pkcs7Flags::BINARY,
+            )
+            .unwrap();
+        let decrypted = String::from_utf8_lossy(&decrypted);
+        assert!(decrypted.contains("smime-type=signed-data"));
+        assert!(
+            enveloped
+                .decrypt(
+                    &PKey::private_key_from_pem(sender_key.as_bytes()).unwrap(),
+                    &X509::from_pem(sender_certificate.as_bytes()).unwrap(),
+                    Pkcs7Flags::BINARY,
+                )
+                .is_err()
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
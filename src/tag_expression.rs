@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+
+use crate::error::{MailerError, new_rmcp_error};
+
+/// How deep parentheses and `NOT`s may nest, so that a crafted expression
+/// can't overflow the stack of the recursive parser.
+const MAX_NESTING_DEPTH: usize = 64;
+
+/// A boolean expression over recipient tags, e.g.
+/// `customer AND (emea OR apac) AND NOT churned`. `NOT` binds tighter than
+/// `AND`, which binds tighter than `OR`. Keywords and tags are case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagExpression {
+    Tag(String),
+    Not(Box<TagExpression>),
+    And(Box<TagExpression>, Box<TagExpression>),
+    Or(Box<TagExpression>, Box<TagExpression>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl TagExpression {
+    pub fn parse(input: &str) -> Result<Self, MailerError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+        };
+
+        let expression = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(new_rmcp_error(&format!(
+                "Invalid tag expression: unexpected {token:?} at token {}",
+                parser.position + 1
+            )));
+        }
+
+        Ok(expression)
+    }
+
+    /// Whether a recipient with these lowercased tags matches the expression.
+    pub fn matches(&self, tags: &HashSet<String>) -> bool {
+        match self {
+            TagExpression::Tag(tag) => tags.contains(tag),
+            TagExpression::Not(expression) => !expression.matches(tags),
+            TagExpression::And(left, right) => left.matches(tags) && right.matches(tags),
+            TagExpression::Or(left, right) => left.matches(tags) || right.matches(tags),
+        }
+    }
+}
+
+/// Returns the tag lowercased, if it is a valid tag name: letters, digits,
+/// `_`, `-`, `.` and `:`, and not a keyword.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    let is_valid = !tag.is_empty()
+        && !["and", "or", "not"].contains(&tag.as_str())
+        && tag
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'));
+
+    is_valid.then_some(tag)
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, MailerError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Tag(normalize_tag(&word).ok_or_else(|| {
+                        new_rmcp_error(&format!("Invalid tag expression: invalid tag {word:?}"))
+                    })?),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Runs `parse` one nesting level deeper.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<TagExpression, MailerError>,
+    ) -> Result<TagExpression, MailerError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(new_rmcp_error("Invalid tag expression: nested too deeply"));
+        }
+
+        self.depth += 1;
+        let expression = parse(self);
+        self.depth -= 1;
+        expression
+    }
+
+    fn parse_or(&mut self) -> Result<TagExpression, MailerError> {
+        let mut expression = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.advance();
+            expression = TagExpression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<TagExpression, MailerError> {
+        let mut expression = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.advance();
+            expression = TagExpression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> Result<TagExpression, MailerError> {
+        if self.peek() == Some(&Token::Not) {
+            self.advance();
+            return Ok(TagExpression::Not(Box::new(self.nested(Self::parse_not)?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<TagExpression, MailerError> {
+        match self.advance() {
+            Some(Token::Tag(tag)) => Ok(TagExpression::Tag(tag)),
+            Some(Token::Open) => {
+                let expression = self.nested(Self::parse_or)?;
+                match self.advance() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err(new_rmcp_error("Invalid tag expression: missing ')'")),
+                }
+            }
+            Some(token) => Err(new_rmcp_error(&format!(
+                "Invalid tag expression: unexpected {token:?} at token {}",
+                self.position
+            ))),
+            None => Err(new_rmcp_error(
+                "Invalid tag expression: unexpected end of expression",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tag_expression() {
+        let tag = |tag: &str| Box::new(TagExpression::Tag(tag.to_string()));
+        let expression =
+            TagExpression::parse("Customer and (emea OR apac) AND NOT churned").unwrap();
+        assert_eq!(
+            expression,
+            TagExpression::And(
+                Box::new(TagExpression::And(
+                    tag("customer"),
+                    Box::new(TagExpression::Or(tag("emea"), tag("apac"))),
+                )),
+                Box::new(TagExpression::Not(tag("churned"))),
+            )
+        );
+
+        // AND binds tighter than OR
+        assert_eq!(
+            TagExpression::parse("a OR b AND c").unwrap(),
+            TagExpression::Or(tag("a"), Box::new(TagExpression::And(tag("b"), tag("c"))))
+        );
+
+        for invalid in ["", "a AND", "(a OR b", "a b", "a OR )", "NOT", "a AND b;c"] {
+            assert!(TagExpression::parse(invalid).is_err(), "{invalid}");
+        }
+
+        // Deep nesting is refused rather than overflowing the stack
+        let nested = |depth| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(TagExpression::parse(&nested(MAX_NESTING_DEPTH)).is_ok());
+        for deep in [nested(100_000), "NOT ".repeat(100_000) + "a"] {
+            let error = TagExpression::parse(&deep).unwrap_err();
+            assert!(
+                error
+                    .message
+                    .ends_with("Invalid tag expression: nested too deeply")
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_tag_expression() {
+        let expression =
+            TagExpression::parse("customer AND (emea OR apac) AND NOT churned").unwrap();
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<HashSet<_>>();
+
+        assert!(expression.matches(&tags(&["customer", "emea"])));
+        assert!(expression.matches(&tags(&["customer", "apac", "vip"])));
+        assert!(!expression.matches(&tags(&["customer", "apac", "churned"])));
+        assert!(!expression.matches(&tags(&["customer", "us"])));
+        assert!(!expression.matches(&tags(&["emea"])));
+        assert!(
+            TagExpression::parse("NOT churned")
+                .unwrap()
+                .matches(&tags(&[]))
+        );
+    }
+}
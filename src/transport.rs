@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use lettre::{
+    AsyncFileTransport, AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    address::Envelope,
+    transport::{
+        smtp::{
+            PoolConfig,
+            authentication::Credentials,
+            client::{Certificate, Identity, Tls, TlsParameters},
+        },
+        stub::AsyncStubTransport,
+    },
+};
+
+use crate::{
+    config::{DeliveryBackend, MailSender, MailerConfig, SandboxConfig, SmtpPoolConfig, TlsMode},
+    error::{MailerError, new_rmcp_error},
+    model::email_record::EmailStatus,
+};
+
+/// A delivery backend used by the `Mailer` to hand off built messages.
+#[async_trait]
+pub trait MailTransport: Debug + Send + Sync {
+    /// The name of the backend, recorded in the email history.
+    fn name(&self) -> &'static str;
+
+    /// Delivers the formatted message to the envelope on behalf of the sender.
+    /// A failed delivery is reported in the returned `Delivery` rather than as
+    /// an error, so that it can be recorded.
+    async fn send(&self, sender: &MailSender, envelope: &Envelope, message: &[u8]) -> Delivery;
+
+    /// Checks that the backend is able to deliver mail for the sender,
+    /// without sending anything.
+    async fn test_connection(&self, sender: &MailSender) -> Result<bool, MailerError>;
+}
+
+/// The outcome of handing a message to a transport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub status: EmailStatus,
+    /// The SMTP reply code, if the transport talks SMTP.
+    pub code: Option<u16>,
+    /// The reply of the server, or the error if the delivery failed.
+    pub message: Option<String>,
+}
+
+impl Delivery {
+    fn new(status: EmailStatus) -> Self {
+        Self {
+            status,
+            code: None,
+            message: None,
+        }
+    }
+
+    fn failed(code: Option<u16>, error: impl Display) -> Self {
+        Self {
+            status: EmailStatus::Failed,
+            code,
+            message: Some(error.to_string()),
+        }
+    }
+}
+
+/// Creates the transport selected by `MailerConfig::backend`, unless the
+/// sandbox replaces it with a file transport.
+pub fn from_config(config: &MailerConfig) -> Result<Arc<dyn MailTransport>, MailerError> {
+    if let Some(SandboxConfig { dir: Some(dir), .. }) = &config.sandbox {
+        return Ok(Arc::new(FileTransport::new(dir)?));
+    }
+
+    let transport: Arc<dyn MailTransport> = match &config.backend {
+        DeliveryBackend::Smtp => Arc::new(SmtpTransport::new(config.clone())),
+        DeliveryBackend::File { dir } => Arc::new(FileTransport::new(dir)?),
+        DeliveryBackend::Sendmail { command } => Arc::new(SendmailTransport::new(command.clone())),
+        DeliveryBackend::Stub => Arc::new(StubTransport::new()),
+    };
+    Ok(transport)
+}
+
+/// Delivers messages through the SMTP server of each sender.
+#[derive(Debug)]
+pub struct SmtpTransport {
+    config: MailerConfig,
+    /// Pooled SMTP transports keyed by sender email, so that connections
+    /// are reused across sends.
+    transports: Mutex<HashMap<String, AsyncSmtpTransport<Tokio1Executor>>>,
+}
+
+/// The resolved settings of a sender's transport. The config is read once at
+/// startup, so they don't change once the transport is built.
+#[derive(Debug, Clone)]
+struct TransportSettings {
+    sender: MailSender,
+    smtp_host: String,
+    smtp_port: u16,
+    tls_mode: TlsMode,
+    pool: SmtpPoolConfig,
+}
+
+impl SmtpTransport {
+    pub fn new(config: MailerConfig) -> Self {
+        Self {
+            config,
+            transports: Default::default(),
+        }
+    }
+
+    /// Returns the pooled transport of the sender, building it on first use.
+    fn transport(
+        &self,
+        sender: &MailSender,
+    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, MailerError> {
+        let mut transports = self
+            .transports
+            .lock()
+            .map_err(|_| new_rmcp_error("SMTP transport cache is poisoned"))?;
+
+        if let Some(transport) = transports.get(&sender.email) {
+            return Ok(transport.clone());
+        }
+
+        let transport = Self::build_transport(&TransportSettings {
+            sender: sender.clone(),
+            smtp_host: self.config.smtp_host_for(sender).to_string(),
+            smtp_port: self.config.smtp_port_for(sender),
+            tls_mode: self.config.tls_mode_for(sender),
+            pool: self.config.smtp_pool.clone(),
+        })?;
+        transports.insert(sender.email.clone(), transport.clone());
+
+        Ok(transport)
+    }
+
+    fn build_transport(
+        settings: &TransportSettings,
+    ) -> Result<AsyncSmtpTransport<Tokio1Executor>, MailerError> {
+        let pool_config = PoolConfig::new()
+            .max_size(settings.pool.max_size)
+            .idle_timeout(Duration::from_secs(settings.pool.idle_timeout_secs));
+
+        let mut builder =
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.smtp_host)
+                .port(settings.smtp_port)
+                .tls(Self::build_tls(settings)?)
+                .pool_config(pool_config);
+
+        if let Some(creds) = &settings.sender.credentials {
+            builder = builder.credentials(Credentials::new(
+                creds.username.clone(),
+                creds.password.clone(),
+            ));
+        }
+
+        Ok(builder.build())
+    }
+
+    fn build_tls(settings: &TransportSettings) -> Result<Tls, MailerError> {
+        if settings.tls_mode == TlsMode::None {
+            return Ok(Tls::None);
+        }
+
+        let sender = &settings.sender;
+        let mut tls_parameters = TlsParameters::builder(settings.smtp_host.clone());
+
+        if let Some(ca_certificate_path) = &sender.ca_certificate_path {
+            let pem = std::fs::read(ca_certificate_path)?;
+            tls_parameters = tls_parameters.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+
+        if let Some(client_certificate) = &sender.client_certificate {
+            let certificate_pem = std::fs::read(&client_certificate.certificate_path)?;
+            let private_key_pem = std::fs::read(&client_certificate.private_key_path)?;
+            tls_parameters = tls_parameters
+                .identify_with(Identity::from_pem(&certificate_pem, &private_key_pem)?);
+        }
+
+        let tls_parameters = tls_parameters.build()?;
+
+        Ok(match settings.tls_mode {
+            TlsMode::Implicit => Tls::Wrapper(tls_parameters),
+            TlsMode::StarttlsRequired => Tls::Required(tls_parameters),
+            TlsMode::StarttlsOpportunistic => Tls::Opportunistic(tls_parameters),
+            TlsMode::None => Tls::None,
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, sender: &MailSender, envelope: &Envelope, message: &[u8]) -> Delivery {
+        let transport = match self.transport(sender) {
+            Ok(transport) => transport,
+            Err(e) => return Delivery::failed(None, e),
+        };
+
+        match transport.send_raw(envelope, message).await {
+            Ok(response) => Delivery {
+                status: EmailStatus::Sent,
+                code: Some(response.code().into()),
+                message: Some(response.message().collect::<Vec<_>>().join(" ")),
+            },
+            Err(e) => Delivery::failed(e.status().map(u16::from), e),
+        }
+    }
+
+    async fn test_connection(&self, sender: &MailSender) -> Result<bool, MailerError> {
+        self.transport(sender)?
+            .test_connection()
+            .await
+            .map_err(MailerError::from)
+    }
+}
+
+/// Writes every message as an `.eml` file into a directory.
+#[derive(Debug)]
+pub struct FileTransport {
+    dir: String,
+    transport: AsyncFileTransport<Tokio1Executor>,
+}
+
+impl FileTransport {
+    pub fn new(dir: &str) -> Result<Self, MailerError> {
+        std::fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_string(),
+            transport: AsyncFileTransport::<Tokio1Executor>::new(dir),
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileTransport {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(&self, _sender: &MailSender, envelope: &Envelope, message: &[u8]) -> Delivery {
+        match self.transport.send_raw(envelope, message).await {
+            Ok(id) => Delivery {
+                message: Some(format!("Written to {}/{id}.eml", self.dir)),
+                ..Delivery::new(EmailStatus::Queued)
+            },
+            Err(e) => Delivery::failed(None, e),
+        }
+    }
+
+    async fn test_connection(&self, _sender: &MailSender) -> Result<bool, MailerError> {
+        Ok(Path::new(&self.dir).is_dir())
+    }
+}
+
+/// Pipes every message into a local `sendmail` binary.
+#[derive(Debug)]
+pub struct SendmailTransport {
+    command: Option<String>,
+    transport: AsyncSendmailTransport<Tokio1Executor>,
+}
+
+impl SendmailTransport {
+    pub fn new(command: Option<String>) -> Self {
+        let transport = match &command {
+            Some(command) => AsyncSendmailTransport::<Tokio1Executor>::new_with_command(command),
+            None => AsyncSendmailTransport::<Tokio1Executor>::new(),
+        };
+
+        Self { command, transport }
+    }
+}
+
+#[async_trait]
+impl MailTransport for SendmailTransport {
+    fn name(&self) -> &'static str {
+        "sendmail"
+    }
+
+    async fn send(&self, _sender: &MailSender, envelope: &Envelope, message: &[u8]) -> Delivery {
+        match self.transport.send_raw(envelope, message).await {
+            Ok(()) => Delivery::new(EmailStatus::Queued),
+            Err(e) => Delivery::failed(None, e),
+        }
+    }
+
+    async fn test_connection(&self, _sender: &MailSender) -> Result<bool, MailerError> {
+        // Only an absolute path can be checked, otherwise the binary is looked up in PATH
+        Ok(self
+            .command
+            .as_ref()
+            .map(Path::new)
+            .filter(|path| path.is_absolute())
+            .is_none_or(|path| path.exists()))
+    }
+}
+
+/// Keeps every message in memory instead of delivering it.
+#[derive(Debug)]
+pub struct StubTransport {
+    transport: AsyncStubTransport,
+}
+
+impl StubTransport {
+    pub fn new() -> Self {
+        Self {
+            transport: AsyncStubTransport::new_ok(),
+        }
+    }
+
+    /// Returns the raw messages delivered so far.
+    #[cfg(test)]
+    pub async fn messages(&self) -> Vec<String> {
+        self.transport
+            .messages()
+            .await
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect()
+    }
+}
+
+impl Default for StubTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl MailTransport for StubTransport {
+    fn name(&self) -> &'static str {
+        "stub"
+    }
+
+    async fn send(&self, _sender: &MailSender, envelope: &Envelope, message: &[u8]) -> Delivery {
+        match self.transport.send_raw(envelope, message).await {
+            Ok(()) => Delivery::new(EmailStatus::Sent),
+            Err(e) => Delivery::failed(None, e),
+        }
+    }
+
+    async fn test_connection(&self, _sender: &MailSender) -> Result<bool, MailerError> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SMTPCredentials;
+
+    #[tokio::test]
+    async fn test_smtp_transport_cache() {
+        let transport = SmtpTransport::new(MailerConfig::default());
+        let sender = MailSender {
+            email: "test@test.com".to_string(),
+            credentials: Some(SMTPCredentials {
+                username: "user".to_string(),
+                password: "password".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        // The transport is built on first use and then reused
+        transport.transport(&sender).unwrap();
+        transport.transport(&sender).unwrap();
+        assert_eq!(transport.transports.lock().unwrap().len(), 1);
+
+        // Each sender has a transport of its own
+        let other_sender = MailSender {
+            email: "test2@test.com".to_string(),
+            ..Default::default()
+        };
+        transport.transport(&other_sender).unwrap();
+        assert_eq!(transport.transports.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    response::Html,
+    routing::get,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64};
+use log::{error, info};
+use openssl::{hash::MessageDigest, memcmp, pkey::PKey, sign::Signer};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{DatabaseConfig, UnsubscribeConfig},
+    database::Database,
+    error::MailerError,
+};
+
+/// The path of the unsubscribe endpoint on the server.
+pub const UNSUBSCRIBE_PATH: &str = "/unsubscribe";
+
+struct UnsubscribeState {
+    secret: String,
+    db: Mutex<Database>,
+}
+
+/// The query of an unsubscribe link: the opted out address and group, and
+/// their signature.
+#[derive(Debug, Deserialize)]
+struct UnsubscribeQuery {
+    token: String,
+    sig: String,
+}
+
+/// The group of the link of an email sent to no group in particular, which
+/// unsubscribes from all groups.
+const ALL_GROUPS: &str = "all";
+
+/// Returns the signed one-click unsubscribe link of a member of a group, or
+/// of a recipient of an email sent to no group in particular.
+pub fn unsubscribe_url(
+    config: &UnsubscribeConfig,
+    email: &str,
+    group_id: Option<i32>,
+) -> Result<String, MailerError> {
+    let group = group_id.map_or(ALL_GROUPS.to_string(), |group_id| group_id.to_string());
+    let token = BASE64.encode(format!("{group}:{}", email.to_lowercase()));
+    let sig = sign(&config.secret, &token)?;
+
+    Ok(format!(
+        "{}{UNSUBSCRIBE_PATH}?token={token}&sig={sig}",
+        config.base_url.trim_end_matches('/')
+    ))
+}
+
+/// Returns the group id and address of a link, if its signature is valid.
+/// The group id is `None` for a link unsubscribing from all groups.
+fn verify(secret: &str, token: &str, sig: &str) -> Option<(Option<i32>, String)> {
+    let expected = sign(secret, token).ok()?;
+    if expected.len() != sig.len() || !memcmp::eq(expected.as_bytes(), sig.as_bytes()) {
+        return None;
+    }
+
+    let token = String::from_utf8(BASE64.decode(token).ok()?).ok()?;
+    let (group_id, email) = token.split_once(':')?;
+    let group_id = match group_id {
+        ALL_GROUPS => None,
+        group_id => Some(group_id.parse().ok()?),
+    };
+    Some((group_id, email.to_string()))
+}
+
+fn sign(secret: &str, token: &str) -> Result<String, MailerError> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(token.as_bytes())?;
+    Ok(BASE64.encode(signer.sign_to_vec()?))
+}
+
+/// Returns the router of the unsubscribe endpoint. It keeps a database
+/// connection of its own.
+pub fn router(config: &UnsubscribeConfig, db_config: &DatabaseConfig) -> Router {
+    let state = Arc::new(UnsubscribeState {
+        secret: config.secret.clone(),
+        db: Mutex::new(Database::new(db_config.clone())),
+    });
+
+    Router::new()
+        .route(UNSUBSCRIBE_PATH, get(confirm_unsubscribe).post(unsubscribe))
+        .with_state(state)
+}
+
+/// Shows a confirmation form. Opening the link alone doesn't unsubscribe,
+/// since mail scanners fetch the links of the messages.
+async fn confirm_unsubscribe(
+    State(state): State<Arc<UnsubscribeState>>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> (StatusCode, Html<String>) {
+    let Some((group_id, email)) = verify(&state.secret, &query.token, &query.sig) else {
+        return invalid_link();
+    };
+    // A link of no group in particular already unsubscribes from all of them
+    let all_option = if group_id.is_some() {
+        "<p><label><input type=\"checkbox\" name=\"all\" value=\"on\"> From all our emails</label></p>"
+    } else {
+        ""
+    };
+
+    (
+        StatusCode::OK,
+        Html(format!(
+            "<!DOCTYPE html><html><body>\
+            <form method=\"post\" action=\"{UNSUBSCRIBE_PATH}?token={}&amp;sig={}\">\
+            <p>Unsubscribe {} from these emails?</p>\
+            {all_option}\
+            <button type=\"submit\">Unsubscribe</button>\
+            </form></body></html>",
+            query.token,
+            query.sig,
+            escape_html(&email)
+        )),
+    )
+}
+
+/// Unsubscribes from the group, or from all groups if the form asks so.
+/// Mail clients post `List-Unsubscribe=One-Click` here (RFC 8058).
+async fn unsubscribe(
+    State(state): State<Arc<UnsubscribeState>>,
+    Query(query): Query<UnsubscribeQuery>,
+    body: Bytes,
+) -> (StatusCode, Html<String>) {
+    let Some((group_id, email)) = verify(&state.secret, &query.token, &query.sig) else {
+        return invalid_link();
+    };
+    let all = String::from_utf8_lossy(&body)
+        .split('&')
+        .any(|field| field == "all=on");
+    let group_id = group_id.filter(|_| !all);
+
+    let mut db = state.db.lock().await;
+    match db.add_unsubscribe(&email, group_id) {
+        Ok(_) => {
+            info!(
+                "{email} unsubscribed from {}",
+                match group_id {
+                    Some(group_id) => format!("group {group_id}"),
+                    None => "all groups".to_string(),
+                }
+            );
+            (
+                StatusCode::OK,
+                Html(
+                    "<!DOCTYPE html><html><body><p>You have been unsubscribed.</p></body></html>"
+                        .to_string(),
+                ),
+            )
+        }
+        Err(e) => {
+            error!("Failed to unsubscribe {email}: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Html("<!DOCTYPE html><html><body><p>Failed to unsubscribe, please try again later.</p></body></html>".to_string()),
+            )
+        }
+    }
+}
+
+fn invalid_link() -> (StatusCode, Html<String>) {
+    (
+        StatusCode::FORBIDDEN,
+        Html(
+            "<!DOCTYPE html><html><body><p>Invalid unsubscribe link.</p></body></html>".to_string(),
+        ),
+    )
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsubscribe_url() {
+        let config = UnsubscribeConfig {
+            base_url: "https://mailer.test.com/".to_string(),
+            secret: "0123456789abcdef".to_string(),
+        };
+        let url = unsubscribe_url(&config, "Bob@Domain.com", Some(3)).unwrap();
+        assert!(url.starts_with("https://mailer.test.com/unsubscribe?token="));
+
+        let (token, sig) = url
+            .split_once("token=")
+            .and_then(|(_, query)| query.split_once("&sig="))
+            .unwrap();
+        assert_eq!(
+            verify(&config.secret, token, sig),
+            Some((Some(3), "bob@domain.com".to_string()))
+        );
+        assert_eq!(verify("another secret key", token, sig), None);
+        let forged = BASE64.encode("4:bob@domain.com");
+        assert_eq!(verify(&config.secret, &forged, sig), None);
+
+        // The link of an email sent to no group unsubscribes from all of them
+        let url = unsubscribe_url(&config, "bob@domain.com", None).unwrap();
+        let (token, sig) = url
+            .split_once("token=")
+            .and_then(|(_, query)| query.split_once("&sig="))
+            .unwrap();
+        assert_eq!(
+            verify(&config.secret, token, sig),
+            Some((None, "bob@domain.com".to_string()))
+        );
+    }
+}